@@ -1,5 +1,6 @@
-use dirhash_rs::dirhash::DirHash;
+use dirhash_rs::dirhash::{DirHash, Strategy};
 use dirhash_rs::pathhash::pathhashspy::PathHashSpy;
+use dirhash_rs::walk::WalkConfig;
 use std::path::Path;
 
 #[path = "../tests/common/mod.rs"]
@@ -34,9 +35,11 @@ mod compute_hash_with_spies {
         bencher
             .with_inputs(|| {
                 let spies = create_large_spy_vec(file_count);
-                DirHash::new().with_files(spies)
+                DirHash::new()
+                    .with_files(spies)
+                    .with_strategy(Strategy::Serial)
             })
-            .bench_local_values(|mut dh| dh.compute_hash_serial());
+            .bench_local_values(|mut dh| dh.compute_hash());
     }
 
     #[divan::bench(args = [100, 1000, 10000, 100000], max_time = 5)]
@@ -44,9 +47,11 @@ mod compute_hash_with_spies {
         bencher
             .with_inputs(|| {
                 let spies = create_large_spy_vec(file_count);
-                DirHash::new().with_files(spies)
+                DirHash::new()
+                    .with_files(spies)
+                    .with_strategy(Strategy::Rayon1)
             })
-            .bench_local_values(|mut dh| dh.compute_hash_rayon1());
+            .bench_local_values(|mut dh| dh.compute_hash());
     }
 
     #[divan::bench(args = [100, 1000, 10000, 100000], max_time = 5)]
@@ -54,9 +59,11 @@ mod compute_hash_with_spies {
         bencher
             .with_inputs(|| {
                 let spies = create_large_spy_vec(file_count);
-                DirHash::new().with_files(spies)
+                DirHash::new()
+                    .with_files(spies)
+                    .with_strategy(Strategy::Rayon2)
             })
-            .bench_local_values(|mut dh| dh.compute_hash_rayon2());
+            .bench_local_values(|mut dh| dh.compute_hash());
     }
 }
 
@@ -79,10 +86,14 @@ mod compute_hash_with_tempfiles {
         bencher
             .with_inputs(|| {
                 DirHash::new()
-                    .with_files_from_dir(dir.path(), true, false, false, false)
+                    .with_files_from_dir(
+                        dir.path(),
+                        &WalkConfig::new().with_include_hidden_files(false),
+                    )
                     .expect("Can't create DirHash")
+                    .with_strategy(Strategy::Serial)
             })
-            .bench_local_values(|mut dh| dh.compute_hash_serial());
+            .bench_local_values(|mut dh| dh.compute_hash());
 
         dir.close().expect("Can't close tempdir");
     }
@@ -102,10 +113,14 @@ mod compute_hash_with_tempfiles {
         bencher
             .with_inputs(|| {
                 DirHash::new()
-                    .with_files_from_dir(dir.path(), true, false, false, false)
+                    .with_files_from_dir(
+                        dir.path(),
+                        &WalkConfig::new().with_include_hidden_files(false),
+                    )
                     .expect("Can't create DirHash")
+                    .with_strategy(Strategy::Rayon1)
             })
-            .bench_local_values(|mut dh| dh.compute_hash_rayon1());
+            .bench_local_values(|mut dh| dh.compute_hash());
 
         dir.close().expect("Can't close tempdir");
     }
@@ -125,10 +140,38 @@ mod compute_hash_with_tempfiles {
         bencher
             .with_inputs(|| {
                 DirHash::new()
-                    .with_files_from_dir(dir.path(), true, false, false, false)
+                    .with_files_from_dir(
+                        dir.path(),
+                        &WalkConfig::new().with_include_hidden_files(false),
+                    )
                     .expect("Can't create DirHash")
+                    .with_strategy(Strategy::Rayon2)
             })
-            .bench_local_values(|mut dh| dh.compute_hash_rayon2());
+            .bench_local_values(|mut dh| dh.compute_hash());
+
+        dir.close().expect("Can't close tempdir");
+    }
+
+    #[divan::bench(args = [100, 1000, 10000], max_time = 5)]
+    pub fn compute_hash_from_dir(bencher: divan::Bencher, file_count: usize) {
+        let dir = common::creating_tempdir(
+            None,
+            file_count,
+            &["a", "b"],
+            file_count,
+            &["c", "d"],
+            file_count,
+            true,
+        );
+
+        bencher.bench_local(|| {
+            DirHash::new()
+                .compute_hash_from_dir(
+                    dir.path(),
+                    &WalkConfig::new().with_include_hidden_files(false),
+                )
+                .expect("Can't compute hash")
+        });
 
         dir.close().expect("Can't close tempdir");
     }
@@ -0,0 +1,234 @@
+use std::{fmt::Display, str::FromStr};
+
+use blake2::Blake2b512;
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::error::DirHashError;
+
+/// Digest algorithm used for hashing the file contents as well as the resulting hashtable.
+///
+/// Each algorithm corresponds to a coreutils-style tool (`sha256sum`, `b2sum`, ...) producing the
+/// same output, which is used by [`crate::bash`] to cross-check the implementation.
+#[derive(
+    Clone, Copy, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Sha512,
+    Sha1,
+    Md5,
+    /// BLAKE2b with 512 bit output (as used by `b2sum`)
+    Blake2b,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 6] = [
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Sha512,
+        HashAlgorithm::Sha1,
+        HashAlgorithm::Md5,
+        HashAlgorithm::Blake2b,
+        HashAlgorithm::Blake3,
+    ];
+
+    /// Returns the name of the algorithm as used on the command line and in fingerprints.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha512 => "sha512",
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Blake2b => "blake2b",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Returns the tag of the BSD-style output (`--tag`) of the corresponding coreutils tool, e.g.
+    /// `SHA256 (path) = <hash>`.
+    pub fn tag(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "SHA256",
+            HashAlgorithm::Sha512 => "SHA512",
+            HashAlgorithm::Sha1 => "SHA1",
+            HashAlgorithm::Md5 => "MD5",
+            HashAlgorithm::Blake2b => "BLAKE2b",
+            HashAlgorithm::Blake3 => "BLAKE3",
+        }
+    }
+
+    /// Returns the length of the digest in bytes.
+    pub fn output_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha256 => 32,
+            HashAlgorithm::Sha512 => 64,
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Md5 => 16,
+            HashAlgorithm::Blake2b => 64,
+            HashAlgorithm::Blake3 => 32,
+        }
+    }
+
+    /// Creates a new incremental [`Hasher`] for this algorithm.
+    pub fn hasher(&self) -> Hasher {
+        match self {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+            HashAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            HashAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+            HashAlgorithm::Blake2b => Hasher::Blake2b(Blake2b512::new()),
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    /// Computes the digest of `data` in one go.
+    pub fn digest(&self, data: impl AsRef<[u8]>) -> Vec<u8> {
+        let mut hasher = self.hasher();
+        hasher.update(data.as_ref());
+        hasher.finalize()
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = DirHashError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        HashAlgorithm::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.name() == s)
+            .ok_or_else(|| DirHashError::UnknownAlgorithm(s.to_owned()))
+    }
+}
+
+/// Incremental hasher wrapping the implementations of the different [`HashAlgorithm`]s.
+#[derive(Clone, Debug)]
+pub enum Hasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Sha1(Sha1),
+    Md5(Md5),
+    Blake2b(Blake2b512),
+    // Boxed, as the BLAKE3 hasher state is significantly larger than the others.
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(data),
+            Hasher::Sha512(h) => h.update(data),
+            Hasher::Sha1(h) => h.update(data),
+            Hasher::Md5(h) => h.update(data),
+            Hasher::Blake2b(h) => h.update(data),
+            Hasher::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(h) => h.finalize().to_vec(),
+            Hasher::Sha512(h) => h.finalize().to_vec(),
+            Hasher::Sha1(h) => h.finalize().to_vec(),
+            Hasher::Md5(h) => h.finalize().to_vec(),
+            Hasher::Blake2b(h) => h.finalize().to_vec(),
+            Hasher::Blake3(h) => h.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Digests of the empty input, checked against the corresponding coreutils/b3sum tools.
+    fn empty_digest(algorithm: HashAlgorithm) -> &'static str {
+        match algorithm {
+            HashAlgorithm::Sha256 => {
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+            }
+            HashAlgorithm::Sha512 => {
+                "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+                 47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+            }
+            HashAlgorithm::Sha1 => "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            HashAlgorithm::Md5 => "d41d8cd98f00b204e9800998ecf8427e",
+            HashAlgorithm::Blake2b => {
+                "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419\
+                 d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce"
+            }
+            HashAlgorithm::Blake3 => {
+                "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+            }
+        }
+    }
+
+    #[test]
+    fn digest_empty() {
+        for algorithm in HashAlgorithm::ALL {
+            assert_eq!(
+                hex::encode(algorithm.digest([])),
+                empty_digest(algorithm),
+                "{algorithm}"
+            );
+        }
+    }
+
+    #[test]
+    fn output_len_matches_digest() {
+        for algorithm in HashAlgorithm::ALL {
+            assert_eq!(
+                algorithm.digest(b"some data").len(),
+                algorithm.output_len(),
+                "{algorithm}"
+            );
+        }
+    }
+
+    #[test]
+    fn incremental_equals_oneshot() {
+        for algorithm in HashAlgorithm::ALL {
+            let mut hasher = algorithm.hasher();
+            hasher.update(b"First line\n");
+            hasher.update(b"Second line\n");
+            assert_eq!(
+                hasher.finalize(),
+                algorithm.digest(b"First line\nSecond line\n"),
+                "{algorithm}"
+            );
+        }
+    }
+
+    #[test]
+    fn default_is_sha256() {
+        assert_eq!(HashAlgorithm::default(), HashAlgorithm::Sha256);
+    }
+
+    #[test]
+    fn from_str_roundtrip() {
+        for algorithm in HashAlgorithm::ALL {
+            assert_eq!(
+                algorithm.to_string().parse::<HashAlgorithm>().unwrap(),
+                algorithm
+            );
+        }
+    }
+
+    #[test]
+    fn from_str_unknown() {
+        let err = "sha3".parse::<HashAlgorithm>().unwrap_err();
+        assert!(matches!(err, DirHashError::UnknownAlgorithm(name) if name == "sha3"));
+    }
+}
@@ -1,7 +1,10 @@
-use std::{path::Path, process::Command};
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path, process::Command};
 
 use tracing::{debug, info};
 
+use crate::algorithm::HashAlgorithm;
+use crate::escape::escape_path;
+
 // Convenience function for computing hashtable and hash with bash (fd & sha256sum)
 pub fn list_files_with_bash(
     dir: &Path,
@@ -30,16 +33,22 @@ pub fn list_files_with_bash(
         fd_args.push_str("--hidden ");
     }
 
-    cmd.arg(format!("fd {} -t f | sort", fd_args));
+    // NUL-separated, as the paths may contain newlines
+    cmd.arg(format!("fd {} -t f -0 | sort -z", fd_args));
 
     info!("Cmd: {:?}", cmd);
 
     let list_output = cmd.output().expect("Command failed");
 
-    let list_output_str = String::from_utf8(list_output.stdout)
-        .expect("Non UTF-8 path encountered")
-        .trim_end()
-        .to_string();
+    // Paths are escaped like in the hashtable, so non UTF-8 paths can be compared as well
+    let stdout = list_output.stdout;
+    let list_output_str = stdout
+        .strip_suffix(b"\0")
+        .unwrap_or(&stdout)
+        .split(|&byte| byte == b'\0')
+        .map(|path| escape_path(OsStr::from_bytes(path)))
+        .collect::<Vec<_>>()
+        .join("\n");
     debug!("Output:\n--->{}<---", &list_output_str);
 
     list_output_str
@@ -52,6 +61,39 @@ pub fn compute_recursive_hash_with_bash(
     follow_links: bool,
     include_hidden_files: bool,
 ) -> (String, String) {
+    compute_recursive_hash_with_bash_and_algorithm(
+        dir,
+        absolute,
+        follow_links,
+        include_hidden_files,
+        HashAlgorithm::Sha256,
+    )
+}
+
+// Returns the coreutils-style tool producing the same output as the given algorithm. Note that
+// BLAKE3 requires `b3sum` to be installed, which isn't part of coreutils.
+fn checksum_command(algorithm: HashAlgorithm) -> &'static str {
+    match algorithm {
+        HashAlgorithm::Sha256 => "sha256sum",
+        HashAlgorithm::Sha512 => "sha512sum",
+        HashAlgorithm::Sha1 => "sha1sum",
+        HashAlgorithm::Md5 => "md5sum",
+        HashAlgorithm::Blake2b => "b2sum",
+        HashAlgorithm::Blake3 => "b3sum",
+    }
+}
+
+// Convenience function for computing hashtable and hash with bash (fd & the checksum tool matching
+// the algorithm, e.g. sha512sum)
+pub fn compute_recursive_hash_with_bash_and_algorithm(
+    dir: &Path,
+    absolute: bool,
+    follow_links: bool,
+    include_hidden_files: bool,
+    algorithm: HashAlgorithm,
+) -> (String, String) {
+    let checksum_cmd = checksum_command(algorithm);
+
     let mut cmd = Command::new("bash");
     cmd.current_dir(dir).env("LC_ALL", "C").arg("-c");
 
@@ -73,7 +115,10 @@ pub fn compute_recursive_hash_with_bash(
         fd_args.push_str("--hidden ");
     }
 
-    cmd.arg(format!("fd {} -t f --exec sha256sum | sort", fd_args));
+    cmd.arg(format!(
+        "fd {} -t f --exec {} | sort",
+        fd_args, checksum_cmd
+    ));
 
     info!("Cmd: {:?}", cmd);
 
@@ -84,13 +129,13 @@ pub fn compute_recursive_hash_with_bash(
 
     // Inefficient (recalculation), but shouldn't be a problem for tests
     //
-    // TODO: "echo" the previous output into sha256sum to remove recalculation
+    // TODO: "echo" the previous output into the checksum tool to remove recalculation
     let mut cmd = Command::new("bash");
     cmd.current_dir(dir).env("LC_ALL", "C").arg("-c");
 
     cmd.arg(format!(
-        "fd {} -t f --exec sha256sum | sort | sha256sum",
-        fd_args
+        "fd {} -t f --exec {} | sort | {}",
+        fd_args, checksum_cmd, checksum_cmd
     ));
 
     info!("Cmd: {:?}", cmd);
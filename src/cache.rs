@@ -0,0 +1,545 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use tracing::{debug, warn};
+
+use crate::algorithm::HashAlgorithm;
+use crate::error::{DirHashError, Result};
+use crate::hashtable::EntryKind;
+use crate::metadata::FileMetadata;
+use crate::pathhash::{PathHash, PathHashProvider};
+
+/// First line of a cache file. Files with another header are discarded.
+const CACHE_HEADER: &str = "# dirhash cache v1";
+
+/// Stat data identifying the contents of a file. If any of it changes, the file is rehashed.
+#[derive(Clone, Copy, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+pub struct FileStat {
+    pub dev: u64,
+    pub ino: u64,
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch
+    pub mtime_ns: i128,
+    /// Status change time in nanoseconds since the Unix epoch
+    pub ctime_ns: i128,
+}
+
+impl FileStat {
+    /// Reads the stat data of the file at `path`, following symlinks like when reading the file
+    /// contents.
+    pub fn read(path: &Path) -> Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Self {
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            size: metadata.size(),
+            mtime_ns: metadata.mtime() as i128 * 1_000_000_000 + metadata.mtime_nsec() as i128,
+            ctime_ns: metadata.ctime() as i128 * 1_000_000_000 + metadata.ctime_nsec() as i128,
+        })
+    }
+}
+
+// Key of the cache: the file (by device and inode) and the algorithm of the digest.
+type CacheKey = (u64, u64, HashAlgorithm);
+type CacheEntries = HashMap<CacheKey, (FileStat, Vec<u8>)>;
+
+/// Cache of file digests keyed by [`FileStat`], shared by all [`CachedPathHash`]es created from it
+/// (also across threads).
+///
+/// The cache is either kept in memory only ([`HashCache::new()`]) or loaded from and saved to a
+/// file ([`HashCache::open()`], [`HashCache::save()`]). The file contains one line per cached
+/// file:
+///
+/// ```text
+/// <algorithm> <dev> <ino> <size> <mtime_ns> <ctime_ns> <hex digest>
+/// ```
+#[derive(Clone, Default, Debug)]
+pub struct HashCache {
+    path: Option<PathBuf>,
+    rehash: bool,
+    entries: Arc<Mutex<CacheEntries>>,
+    hits: Arc<AtomicUsize>,
+    misses: Arc<AtomicUsize>,
+}
+
+impl HashCache {
+    /// Creates an empty cache kept in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the cache file at `path`, which is created by [`HashCache::save()`] if it doesn't
+    /// exist yet.
+    ///
+    /// As the cache can always be rebuilt, malformed lines (or a whole file of an unknown version)
+    /// are skipped with a warning. Only IO errors other than a missing file are returned.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let cache = Self {
+            path: Some(path.to_owned()),
+            ..Default::default()
+        };
+
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("Cache file {:?} doesn't exist yet", path);
+                return Ok(cache);
+            }
+            Err(e) => return Err(DirHashError::PathIo(path.to_owned(), e)),
+        };
+
+        let mut lines = contents.lines();
+        if lines.next() != Some(CACHE_HEADER) {
+            warn!("Discarded cache file {:?} of unknown version", path);
+            return Ok(cache);
+        }
+
+        {
+            let mut entries = cache.entries.lock().unwrap();
+            for (i, line) in lines.enumerate() {
+                match parse_line(line) {
+                    Some((key, value)) => {
+                        entries.insert(key, value);
+                    }
+                    None => warn!("Skipped malformed line {} of cache file {:?}", i + 2, path),
+                }
+            }
+            debug!(
+                "Loaded {} entries from cache file {:?}",
+                entries.len(),
+                path
+            );
+        }
+
+        Ok(cache)
+    }
+
+    /// If set, the stored digests are ignored, so that all files are rehashed. The cache is still
+    /// updated with the new digests.
+    pub fn with_rehash(mut self, rehash: bool) -> Self {
+        self.rehash = rehash;
+        self
+    }
+
+    pub fn rehash(&self) -> bool {
+        self.rehash
+    }
+
+    /// Returns the path of the cache file, or `None` if the cache is kept in memory only.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of lookups that returned a digest.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of lookups that didn't return a digest.
+    pub fn misses(&self) -> usize {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    /// Returns the digest computed with `algorithm` of the file identified by `stat`, if cached
+    /// with exactly the same stat data.
+    pub fn get(&self, stat: &FileStat, algorithm: HashAlgorithm) -> Option<Vec<u8>> {
+        let hash = match self.rehash {
+            true => None,
+            false => self
+                .entries
+                .lock()
+                .unwrap()
+                .get(&(stat.dev, stat.ino, algorithm))
+                .filter(|(cached_stat, _)| cached_stat == stat)
+                .map(|(_, hash)| hash.clone()),
+        };
+
+        match hash {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+
+        hash
+    }
+
+    /// Stores the digest computed with `algorithm` of the file identified by `stat`, replacing any
+    /// digest of an older version of the file.
+    pub fn insert(&self, stat: FileStat, algorithm: HashAlgorithm, hash: Vec<u8>) {
+        self.entries
+            .lock()
+            .unwrap()
+            .insert((stat.dev, stat.ino, algorithm), (stat, hash));
+    }
+
+    /// Writes the cache to its file (creating the parent directories if needed). Does nothing for
+    /// caches kept in memory only.
+    ///
+    /// The file is replaced atomically, so concurrent runs never see a partially written cache.
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        self.write(path)
+            .map_err(|e| DirHashError::PathIo(path.to_owned(), e))?;
+
+        debug!("Saved {} entries to cache file {:?}", self.len(), path);
+        Ok(())
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut lines: Vec<String> = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((_, _, algorithm), (stat, hash))| {
+                format!(
+                    "{algorithm} {} {} {} {} {} {}",
+                    stat.dev,
+                    stat.ino,
+                    stat.size,
+                    stat.mtime_ns,
+                    stat.ctime_ns,
+                    hex::encode(hash)
+                )
+            })
+            .collect();
+        lines.sort();
+
+        let mut tmp_name = path.as_os_str().to_owned();
+        tmp_name.push(format!(".{}.tmp", std::process::id()));
+        let tmp_path = PathBuf::from(tmp_name);
+
+        let mut file = fs::File::create(&tmp_path)?;
+        writeln!(file, "{CACHE_HEADER}")?;
+        for line in lines {
+            writeln!(file, "{line}")?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(CacheKey, (FileStat, Vec<u8>))> {
+    let columns: Vec<_> = line.split(' ').collect();
+    let [algorithm, dev, ino, size, mtime_ns, ctime_ns, hash] = columns[..] else {
+        return None;
+    };
+
+    let algorithm: HashAlgorithm = algorithm.parse().ok()?;
+    let stat = FileStat {
+        dev: dev.parse().ok()?,
+        ino: ino.parse().ok()?,
+        size: size.parse().ok()?,
+        mtime_ns: mtime_ns.parse().ok()?,
+        ctime_ns: ctime_ns.parse().ok()?,
+    };
+    let hash = hex::decode(hash).ok()?;
+    if hash.len() != algorithm.output_len() {
+        return None;
+    }
+
+    Some(((stat.dev, stat.ino, algorithm), (stat, hash)))
+}
+
+/// [`PathHashProvider`] wrapping a [`PathHash`], which only reads the file contents if its digest
+/// isn't found in the [`HashCache`].
+///
+/// Symlinks and directories aren't cached, as hashing them doesn't read any file contents. The
+/// metadata selected by the [`crate::metadata::MetadataOptions`] is always read from the file.
+#[derive(Clone, Debug)]
+pub struct CachedPathHash {
+    pathhash: PathHash,
+    cache: HashCache,
+    hash: Option<Vec<u8>>,
+    metadata: Option<FileMetadata>,
+}
+
+impl CachedPathHash {
+    pub fn new(pathhash: PathHash, cache: HashCache) -> Self {
+        Self {
+            pathhash,
+            cache,
+            hash: None,
+            metadata: None,
+        }
+    }
+
+    pub fn pathhash(&self) -> &PathHash {
+        &self.pathhash
+    }
+
+    pub fn cache(&self) -> &HashCache {
+        &self.cache
+    }
+}
+
+impl PathHashProvider for CachedPathHash {
+    /// Looks up the digest of the file in the cache, and only computes it with the wrapped
+    /// [`PathHash`] on a miss. The cache is updated unless the file changed while hashing.
+    fn compute_hash(&mut self) -> Result<()> {
+        if self.pathhash.kind() != EntryKind::File {
+            self.pathhash.compute_hash()?;
+            self.hash = self.pathhash.hash().map(<[u8]>::to_vec);
+            self.metadata = self.pathhash.metadata().cloned();
+            return Ok(());
+        }
+
+        let path = self.pathhash.path();
+        let algorithm = self.pathhash.algorithm();
+        let stat = FileStat::read(path)?;
+
+        if let Some(hash) = self.cache.get(&stat, algorithm) {
+            debug!("Cache hit for {:?}", path);
+            self.metadata = Some(FileMetadata::read(
+                path,
+                &self.pathhash.metadata_options(),
+                algorithm,
+            )?);
+            self.hash = Some(hash);
+            return Ok(());
+        }
+
+        self.pathhash.compute_hash()?;
+        let hash = self.pathhash.hash().unwrap().to_vec();
+
+        if FileStat::read(self.pathhash.path())? == stat {
+            self.cache.insert(stat, algorithm, hash.clone());
+        } else {
+            warn!(
+                "{:?} changed while hashing -> not cached",
+                self.pathhash.path()
+            );
+        }
+
+        self.hash = Some(hash);
+        self.metadata = self.pathhash.metadata().cloned();
+        Ok(())
+    }
+
+    fn hash(&self) -> Option<&[u8]> {
+        self.hash.as_deref()
+    }
+
+    fn path(&self) -> &Path {
+        self.pathhash.path()
+    }
+
+    fn metadata(&self) -> Option<&FileMetadata> {
+        self.metadata.as_ref()
+    }
+
+    fn kind(&self) -> EntryKind {
+        self.pathhash.kind()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::metadata::MetadataOptions;
+
+    fn cached_pathhash(path: &Path, cache: &HashCache) -> CachedPathHash {
+        CachedPathHash::new(
+            PathHash::new(path).expect("Can't create PathHash"),
+            cache.clone(),
+        )
+    }
+
+    #[test]
+    fn hit_and_invalidation() {
+        let dir = tempdir().expect("Can't create tempdir");
+        let file = dir.path().join("file");
+        fs::write(&file, b"contents").expect("Can't write to file");
+
+        let cache = HashCache::new();
+
+        let mut ph = cached_pathhash(&file, &cache);
+        ph.compute_hash().expect("Can't compute hash");
+        assert_eq!(
+            ph.hash(),
+            Some(&HashAlgorithm::Sha256.digest("contents")[..])
+        );
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (0, 1, 1));
+
+        let mut ph = cached_pathhash(&file, &cache);
+        ph.compute_hash().expect("Can't compute hash");
+        assert_eq!(
+            ph.hash(),
+            Some(&HashAlgorithm::Sha256.digest("contents")[..])
+        );
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+
+        // Any stat change invalidates the entry
+        fs::write(&file, b"modified").expect("Can't write to file");
+        let mut ph = cached_pathhash(&file, &cache);
+        ph.compute_hash().expect("Can't compute hash");
+        assert_eq!(
+            ph.hash(),
+            Some(&HashAlgorithm::Sha256.digest("modified")[..])
+        );
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (1, 2, 1));
+
+        dir.close().expect("Can't close tempdir");
+    }
+
+    #[test]
+    fn stale_digest_is_not_returned() {
+        let dir = tempdir().expect("Can't create tempdir");
+        let file = dir.path().join("file");
+        fs::write(&file, b"contents").expect("Can't write to file");
+
+        let cache = HashCache::new();
+        let stat = FileStat::read(&file).expect("Can't read stat");
+        cache.insert(stat, HashAlgorithm::Sha256, vec![0xaa; 32]);
+
+        // Same stat data -> the cached digest is trusted
+        let mut ph = cached_pathhash(&file, &cache);
+        ph.compute_hash().expect("Can't compute hash");
+        assert_eq!(ph.hash(), Some(&[0xaa; 32][..]));
+
+        // Other algorithm -> miss
+        let mut ph = CachedPathHash::new(
+            PathHash::new(&file)
+                .expect("Can't create PathHash")
+                .with_algorithm(HashAlgorithm::Md5),
+            cache.clone(),
+        );
+        ph.compute_hash().expect("Can't compute hash");
+        assert_eq!(ph.hash(), Some(&HashAlgorithm::Md5.digest("contents")[..]));
+
+        // Rehash -> miss
+        let cache = cache.with_rehash(true);
+        let mut ph = cached_pathhash(&file, &cache);
+        ph.compute_hash().expect("Can't compute hash");
+        assert_eq!(
+            ph.hash(),
+            Some(&HashAlgorithm::Sha256.digest("contents")[..])
+        );
+
+        dir.close().expect("Can't close tempdir");
+    }
+
+    #[test]
+    fn metadata_on_hit() {
+        let dir = tempdir().expect("Can't create tempdir");
+        let file = dir.path().join("file");
+        fs::write(&file, b"contents").expect("Can't write to file");
+
+        let cache = HashCache::new();
+        let options = MetadataOptions {
+            size: true,
+            ..Default::default()
+        };
+
+        for _ in 0..2 {
+            let mut ph = CachedPathHash::new(
+                PathHash::new(&file)
+                    .expect("Can't create PathHash")
+                    .with_metadata_options(options),
+                cache.clone(),
+            );
+            ph.compute_hash().expect("Can't compute hash");
+            assert_eq!(ph.metadata().and_then(|m| m.size), Some(8));
+        }
+        assert_eq!(cache.hits(), 1);
+
+        dir.close().expect("Can't close tempdir");
+    }
+
+    #[test]
+    fn save_and_open() {
+        let dir = tempdir().expect("Can't create tempdir");
+        let file = dir.path().join("file");
+        fs::write(&file, b"contents").expect("Can't write to file");
+        let cache_path = dir.path().join("cache/hashes");
+
+        let cache = HashCache::open(&cache_path).expect("Can't open cache");
+        assert!(cache.is_empty());
+        assert_eq!(cache.path(), Some(cache_path.as_path()));
+        cached_pathhash(&file, &cache)
+            .compute_hash()
+            .expect("Can't compute hash");
+        cache.save().expect("Can't save cache");
+
+        let contents = fs::read_to_string(&cache_path).expect("Can't read cache file");
+        assert!(contents.starts_with("# dirhash cache v1\nsha256 "));
+
+        let cache = HashCache::open(&cache_path).expect("Can't open cache");
+        assert_eq!(cache.len(), 1);
+        cached_pathhash(&file, &cache)
+            .compute_hash()
+            .expect("Can't compute hash");
+        assert_eq!(cache.hits(), 1);
+
+        dir.close().expect("Can't close tempdir");
+    }
+
+    #[test]
+    fn open_skips_malformed_lines() {
+        let dir = tempdir().expect("Can't create tempdir");
+        let cache_path = dir.path().join("hashes");
+
+        fs::write(
+            &cache_path,
+            format!(
+                "{CACHE_HEADER}\nsha256 1 2 3 4 5 {}\nsha256 1 2 3 4 5 abcd\nmd5 1 2\ngarbage\n",
+                "00".repeat(32)
+            ),
+        )
+        .expect("Can't write cache file");
+        let cache = HashCache::open(&cache_path).expect("Can't open cache");
+        assert_eq!(cache.len(), 1);
+
+        fs::write(&cache_path, "# dirhash cache v0\n").expect("Can't write cache file");
+        let cache = HashCache::open(&cache_path).expect("Can't open cache");
+        assert!(cache.is_empty());
+
+        dir.close().expect("Can't close tempdir");
+    }
+
+    #[test]
+    fn symlinks_are_not_cached() {
+        let dir = tempdir().expect("Can't create tempdir");
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink("target", &link).expect("Can't create symlink");
+
+        let cache = HashCache::new();
+        let mut ph = CachedPathHash::new(
+            PathHash::symlink(&link).expect("Can't create PathHash"),
+            cache.clone(),
+        );
+        ph.compute_hash().expect("Can't compute hash");
+        assert_eq!(ph.hash(), Some(&HashAlgorithm::Sha256.digest("target")[..]));
+        assert_eq!(ph.kind(), EntryKind::Symlink);
+        assert!(cache.is_empty());
+
+        dir.close().expect("Can't close tempdir");
+    }
+}
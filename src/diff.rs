@@ -0,0 +1,297 @@
+use std::{collections::BTreeMap, ffi::OsStr, os::unix::ffi::OsStrExt};
+
+use crate::algorithm::HashAlgorithm;
+use crate::hashtable::{EntryKind, HashTable, HashTableEntry};
+
+// Removed and added entries, by kind and hash.
+type MoveCandidates<'a> = BTreeMap<(EntryKind, &'a [u8]), (Vec<usize>, Vec<usize>)>;
+
+/// Entry-by-entry difference between two [`HashTable`]s, matched by their path.
+///
+/// Entries only present in one of the hashtables are paired up by their hash and kind to detect
+/// moved (or renamed) files. If several entries have the same contents, entries with the same file
+/// name are paired first, then the remaining ones in the order of their paths. Entries that can't
+/// be paired are listed as added or removed, and so are entries without contents (empty files and
+/// directory markers), which can't be told apart.
+///
+/// All lists are sorted by (old) path.
+#[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+pub struct HashTableDiff {
+    /// Entries only present in the new hashtable.
+    pub added: Vec<HashTableEntry>,
+    /// Entries only present in the old hashtable.
+    pub removed: Vec<HashTableEntry>,
+    /// Entries present in both hashtables, but with different hashes or kinds (old, new).
+    pub modified: Vec<(HashTableEntry, HashTableEntry)>,
+    /// Entries present in both hashtables with the same hash, but different metadata (old, new).
+    pub metadata_changed: Vec<(HashTableEntry, HashTableEntry)>,
+    /// Entries with the same hash and kind, but different paths (old, new).
+    pub moved: Vec<(HashTableEntry, HashTableEntry)>,
+}
+
+impl HashTableDiff {
+    pub fn new(old: &HashTable, new: &HashTable) -> Self {
+        let old_entries: BTreeMap<&OsStr, &HashTableEntry> =
+            old.entries().iter().map(|e| (e.path(), e)).collect();
+        let new_entries: BTreeMap<&OsStr, &HashTableEntry> =
+            new.entries().iter().map(|e| (e.path(), e)).collect();
+
+        let mut diff = HashTableDiff::default();
+
+        for (path, old_entry) in &old_entries {
+            match new_entries.get(path) {
+                Some(new_entry) => {
+                    if old_entry.hash() != new_entry.hash() || old_entry.kind() != new_entry.kind()
+                    {
+                        diff.modified
+                            .push(((*old_entry).clone(), (*new_entry).clone()));
+                    } else if old_entry.metadata() != new_entry.metadata() {
+                        diff.metadata_changed
+                            .push(((*old_entry).clone(), (*new_entry).clone()));
+                    }
+                }
+                None => diff.removed.push((*old_entry).clone()),
+            }
+        }
+
+        for (path, new_entry) in &new_entries {
+            if !old_entries.contains_key(path) {
+                diff.added.push((*new_entry).clone());
+            }
+        }
+
+        diff.pair_moved();
+        diff
+    }
+
+    // Moves pairs of removed and added entries with the same contents to `moved`.
+    fn pair_moved(&mut self) {
+        // The hash of no data depends on the algorithm, which isn't known here.
+        let empty_digests = HashAlgorithm::ALL.map(|algorithm| algorithm.digest([]));
+
+        // Indices of the removed and added entries by contents, each in the order of the paths.
+        let mut candidates = MoveCandidates::new();
+        for (i, entry) in self.removed.iter().enumerate() {
+            if empty_digests.iter().any(|empty| empty == entry.hash()) {
+                continue;
+            }
+            candidates
+                .entry((entry.kind(), entry.hash()))
+                .or_default()
+                .0
+                .push(i);
+        }
+        for (i, entry) in self.added.iter().enumerate() {
+            if let Some((_, added)) = candidates.get_mut(&(entry.kind(), entry.hash())) {
+                added.push(i);
+            }
+        }
+
+        let mut pairs = vec![];
+        for (mut removed, mut added) in candidates.into_values() {
+            for same_name in [true, false] {
+                removed.retain(|&old| {
+                    let position = added.iter().position(|&new| {
+                        !same_name
+                            || file_name(self.removed[old].path())
+                                == file_name(self.added[new].path())
+                    });
+                    match position {
+                        Some(position) => {
+                            pairs.push((old, added.remove(position)));
+                            false
+                        }
+                        None => true,
+                    }
+                });
+            }
+        }
+        pairs.sort();
+
+        let mut removed: Vec<_> = std::mem::take(&mut self.removed)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut added: Vec<_> = std::mem::take(&mut self.added)
+            .into_iter()
+            .map(Some)
+            .collect();
+        for (old, new) in pairs {
+            if let (Some(old), Some(new)) = (removed[old].take(), added[new].take()) {
+                self.moved.push((old, new));
+            }
+        }
+        self.removed = removed.into_iter().flatten().collect();
+        self.added = added.into_iter().flatten().collect();
+    }
+
+    /// Returns `true` if both hashtables contain the same paths with the same hashes and metadata.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+            && self.metadata_changed.is_empty()
+            && self.moved.is_empty()
+    }
+}
+
+// Returns the last component of a hashtable path (ignoring the `/` of directories).
+fn file_name(path: &OsStr) -> &[u8] {
+    let path = path.as_bytes();
+    let path = path.strip_suffix(b"/").unwrap_or(path);
+    path.rsplit(|&byte| byte == b'/').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::FileMetadata;
+
+    fn hashtable(entries: &[(u8, &str)]) -> HashTable {
+        let mut ht = HashTable::new();
+        for (hash, path) in entries {
+            ht.add(HashTableEntry::new([*hash; 32], *path).unwrap());
+        }
+        ht.sort();
+        ht
+    }
+
+    #[test]
+    fn identical() {
+        let old = hashtable(&[(1, "./a"), (2, "./b")]);
+        let new = hashtable(&[(2, "./b"), (1, "./a")]);
+
+        let diff = HashTableDiff::new(&old, &new);
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn both_empty() {
+        let diff = HashTableDiff::new(&HashTable::new(), &HashTable::new());
+        assert!(diff.is_empty());
+    }
+
+    #[test]
+    fn added_removed_modified() {
+        let old = hashtable(&[(1, "./a"), (2, "./b"), (3, "./c"), (4, "./z")]);
+        let new = hashtable(&[(1, "./a"), (9, "./b"), (5, "./d"), (0, "./e")]);
+
+        let diff = HashTableDiff::new(&old, &new);
+        assert!(!diff.is_empty());
+
+        let paths = |entries: &[HashTableEntry]| {
+            entries
+                .iter()
+                .map(|e| e.path().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(paths(&diff.added), ["./d", "./e"]);
+        assert_eq!(paths(&diff.removed), ["./c", "./z"]);
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.modified[0].0.path(), "./b");
+        assert_eq!(diff.modified[0].0.hash(), [2; 32]);
+        assert_eq!(diff.modified[0].1.hash(), [9; 32]);
+    }
+
+    #[test]
+    fn metadata_changed() {
+        let mode = |mode| FileMetadata {
+            mode: Some(mode),
+            ..Default::default()
+        };
+        let entry = |hash, path, metadata| {
+            HashTableEntry::new([hash; 32], path)
+                .unwrap()
+                .with_metadata(metadata)
+        };
+
+        let mut old = HashTable::new();
+        old.add(entry(1, "./a", mode(0o755)));
+        old.add(entry(2, "./b", mode(0o644)));
+        old.add(entry(3, "./c", mode(0o644)));
+        let mut new = HashTable::new();
+        new.add(entry(1, "./a", mode(0o644)));
+        new.add(entry(9, "./b", mode(0o600)));
+        new.add(entry(3, "./c", mode(0o644)));
+
+        let diff = HashTableDiff::new(&old, &new);
+        assert!(!diff.is_empty());
+        assert!(diff.added.is_empty());
+        assert!(diff.removed.is_empty());
+
+        // Content changes take precedence
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.modified[0].0.path(), "./b");
+
+        assert_eq!(diff.metadata_changed.len(), 1);
+        assert_eq!(diff.metadata_changed[0].0.path(), "./a");
+        assert_eq!(diff.metadata_changed[0].0.metadata(), &mode(0o755));
+        assert_eq!(diff.metadata_changed[0].1.metadata(), &mode(0o644));
+    }
+
+    #[test]
+    fn moved() {
+        let old = hashtable(&[(1, "./a"), (2, "./b"), (3, "./c")]);
+        let new = hashtable(&[(1, "./x/a"), (2, "./renamed"), (4, "./c"), (5, "./new")]);
+
+        let diff = HashTableDiff::new(&old, &new);
+        let moved: Vec<_> = diff
+            .moved
+            .iter()
+            .map(|(old, new)| (old.path().to_str().unwrap(), new.path().to_str().unwrap()))
+            .collect();
+        assert_eq!(moved, [("./a", "./x/a"), ("./b", "./renamed")]);
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].path(), "./new");
+        assert!(diff.removed.is_empty());
+    }
+
+    #[test]
+    fn moved_duplicates() {
+        // Same file names are paired first, the rest in path order
+        let old = hashtable(&[(1, "./a/same"), (1, "./b"), (1, "./c"), (1, "./d")]);
+        let new = hashtable(&[(1, "./x/other"), (1, "./y"), (1, "./z/same")]);
+
+        let diff = HashTableDiff::new(&old, &new);
+        let moved: Vec<_> = diff
+            .moved
+            .iter()
+            .map(|(old, new)| (old.path().to_str().unwrap(), new.path().to_str().unwrap()))
+            .collect();
+        assert_eq!(
+            moved,
+            [
+                ("./a/same", "./z/same"),
+                ("./b", "./x/other"),
+                ("./c", "./y")
+            ]
+        );
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].path(), "./d");
+        assert!(diff.added.is_empty());
+
+        // Empty files aren't paired
+        let empty = HashAlgorithm::Sha256.digest([]);
+        let mut old = HashTable::new();
+        old.add(HashTableEntry::new(&empty, "./a").unwrap());
+        let mut new = HashTable::new();
+        new.add(HashTableEntry::new(&empty, "./b").unwrap());
+        let diff = HashTableDiff::new(&old, &new);
+        assert!(diff.moved.is_empty());
+        assert_eq!(diff.added.len(), 1);
+
+        // Only entries of the same kind are paired
+        let mut new = HashTable::new();
+        new.add(
+            HashTableEntry::new([1; 32], "./b")
+                .unwrap()
+                .with_kind(EntryKind::Symlink),
+        );
+        let diff = HashTableDiff::new(&hashtable(&[(1, "./a")]), &new);
+        assert!(diff.moved.is_empty());
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.removed.len(), 1);
+    }
+}
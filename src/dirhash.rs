@@ -1,17 +1,34 @@
-use std::borrow::Cow;
-use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 
 use rayon::prelude::*;
-use sha2::{Digest, Sha256};
+use serde::{Deserialize, Serialize};
 use tracing::{debug, error, info, warn};
 use walkdir::WalkDir;
 
+use crate::algorithm::HashAlgorithm;
+use crate::cache::{CachedPathHash, HashCache};
 use crate::error::{DirHashError, InvalidFileTypeKind, Result};
-use crate::hashtable::{HashTable, HashTableEntry};
-use crate::pathhash::{PathHash, PathHashProvider};
-
-#[derive(Clone, Copy, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+use crate::filter::Filter;
+use crate::hashtable::{EntryKind, HashTable, HashTableEntry};
+use crate::metadata::MetadataOptions;
+use crate::pathhash::{PathHash, PathHashProvider, ReadOptions};
+use crate::progress::{Progress, ProgressHandle};
+use crate::walk::{DirectoryMode, SymlinkMode, WalkConfig};
+
+/// Why a path found by [`DirHash::with_files_from_dir()`] isn't part of the hashtable.
+///
+/// Serialized with the reason as `snake_case` tag and the pattern (if any) as content, e.g.
+/// `{"reason": "hidden"}` or `{"reason": "excluded", "pattern": "*.o"}`. Fingerprints store the
+/// [`Debug`] representation instead, which is parsed by [`FromStr`].
+#[derive(Clone, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "reason", content = "pattern")]
 pub enum IgnoreReason {
     Dir,
     BlockDevice,
@@ -20,15 +37,123 @@ pub enum IgnoreReason {
     Socket,
     Hidden,
     Symlink,
+    /// Matched the contained exclude pattern (or a pattern of an ignore file)
+    Excluded(String),
+    /// Didn't match any of the include patterns
+    NotIncluded,
+    /// Directory on another file system, see [`WalkConfig::with_one_file_system()`]
+    MountPoint,
+    /// Smaller than the minimum size, see [`WalkConfig::with_min_size()`]
+    TooSmall,
+    /// Larger than the maximum size, see [`WalkConfig::with_max_size()`]
+    TooLarge,
+    /// Modified before the [`WalkConfig::with_newer()`] timestamp
+    TooOld,
+    /// Modified at or after the [`WalkConfig::with_older()`] timestamp
+    TooNew,
+}
+
+impl FromStr for IgnoreReason {
+    type Err = DirHashError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let unknown = || DirHashError::UnknownIgnoreReason(s.to_owned());
+
+        if let Some(pattern) = s
+            .strip_prefix("Excluded(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            return unescape_debug_str(pattern)
+                .map(IgnoreReason::Excluded)
+                .ok_or_else(unknown);
+        }
+
+        match s {
+            "Dir" => Ok(IgnoreReason::Dir),
+            "BlockDevice" => Ok(IgnoreReason::BlockDevice),
+            "CharDevice" => Ok(IgnoreReason::CharDevice),
+            "FIFO" => Ok(IgnoreReason::FIFO),
+            "Socket" => Ok(IgnoreReason::Socket),
+            "Hidden" => Ok(IgnoreReason::Hidden),
+            "Symlink" => Ok(IgnoreReason::Symlink),
+            "NotIncluded" => Ok(IgnoreReason::NotIncluded),
+            "MountPoint" => Ok(IgnoreReason::MountPoint),
+            "TooSmall" => Ok(IgnoreReason::TooSmall),
+            "TooLarge" => Ok(IgnoreReason::TooLarge),
+            "TooOld" => Ok(IgnoreReason::TooOld),
+            "TooNew" => Ok(IgnoreReason::TooNew),
+            _ => Err(unknown()),
+        }
+    }
+}
+
+// Reverses the `Debug` representation of a `str` (quoted, with escape sequences).
+fn unescape_debug_str(quoted: &str) -> Option<String> {
+    let mut chars = quoted.strip_prefix('"')?.strip_suffix('"')?.chars();
+    let mut unescaped = String::new();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        unescaped.push(match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            'u' => {
+                let hex: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+            }
+            c @ ('\\' | '"' | '\'') => c,
+            _ => return None,
+        });
+    }
+
+    Some(unescaped)
+}
+
+/// How [`DirHash::compute_hash()`] hashes the files.
+///
+/// The default is [`Strategy::Serial`], unless the crate is built with the `rayon1` or `rayon2`
+/// feature.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+pub enum Strategy {
+    /// One file after the other, see [`DirHash::compute_hash_serial()`]
+    Serial,
+    /// In parallel, see [`DirHash::compute_hash_rayon1()`]
+    Rayon1,
+    /// In parallel, see [`DirHash::compute_hash_rayon2()`]
+    Rayon2,
+}
+
+impl Default for Strategy {
+    fn default() -> Self {
+        if cfg!(feature = "rayon2") {
+            Strategy::Rayon2
+        } else if cfg!(feature = "rayon1") {
+            Strategy::Rayon1
+        } else {
+            Strategy::Serial
+        }
+    }
 }
 
 #[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
 pub struct DirHash<T> {
     root: Option<PathBuf>,
     pathhashvec: Vec<T>,
-    hash: Option<[u8; 32]>,
+    algorithm: HashAlgorithm,
+    read_options: ReadOptions,
+    metadata_options: MetadataOptions,
+    hash: Option<Vec<u8>>,
     hashtable: Option<HashTable>,
     ignored: Vec<(PathBuf, IgnoreReason)>,
+    progress: Option<ProgressHandle>,
+    strategy: Strategy,
+    threads: usize,
 }
 
 impl<T> DirHash<T>
@@ -39,9 +164,15 @@ where
         DirHash {
             root: None,
             pathhashvec: Vec::new(),
+            algorithm: HashAlgorithm::default(),
+            read_options: ReadOptions::default(),
+            metadata_options: MetadataOptions::default(),
             hash: None,
             hashtable: None,
             ignored: Vec::new(),
+            progress: None,
+            strategy: Strategy::default(),
+            threads: 0,
         }
     }
 
@@ -55,12 +186,79 @@ where
         self
     }
 
+    /// Keeps only the files for which `keep` returns `true`, e.g. to skip files before hashing.
+    pub fn retain_files(mut self, keep: impl FnMut(&T) -> bool) -> Self {
+        self.pathhashvec.retain(keep);
+        self
+    }
+
+    /// Sets the algorithm used for hashing the hashtable. When using
+    /// [`DirHash::with_files_from_dir()`], it must be set beforehand, as the files are then hashed
+    /// with the same algorithm. Files added with [`DirHash::with_files()`] must provide hashes of
+    /// matching length.
+    pub fn with_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Sets how the files found by [`DirHash::with_files_from_dir()`] are read when hashing. Like
+    /// the algorithm, it must be set beforehand.
+    pub fn with_read_options(mut self, read_options: ReadOptions) -> Self {
+        self.read_options = read_options;
+        self
+    }
+
+    /// Selects the file metadata of the files found by [`DirHash::with_files_from_dir()`] that is
+    /// added to the hashtable. Like the algorithm, it must be set beforehand.
+    pub fn with_metadata_options(mut self, metadata_options: MetadataOptions) -> Self {
+        self.metadata_options = metadata_options;
+        self
+    }
+
+    /// Reports the progress of [`DirHash::with_files_from_dir()`] and [`DirHash::compute_hash()`]
+    /// to `progress`. Like the algorithm, it must be set beforehand.
+    pub fn with_progress(mut self, progress: Arc<dyn Progress>) -> Self {
+        self.progress = Some(ProgressHandle::new(progress));
+        self
+    }
+
+    /// Selects how [`DirHash::compute_hash()`] hashes the files.
+    pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets the number of threads of the parallel strategies. With 0 (the default), the files are
+    /// hashed in the current rayon thread pool (the global one, unless called from
+    /// [`rayon::ThreadPool::install()`]). Otherwise, a dedicated pool with this many threads is
+    /// created for each [`DirHash::compute_hash()`], leaving the global pool alone.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
     pub fn root(&self) -> Option<&Path> {
         self.root.as_deref()
     }
 
-    pub fn hash(&self) -> Option<&[u8; 32]> {
-        self.hash.as_ref()
+    pub fn files(&self) -> &[T] {
+        self.pathhashvec.as_slice()
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn read_options(&self) -> ReadOptions {
+        self.read_options
+    }
+
+    pub fn metadata_options(&self) -> MetadataOptions {
+        self.metadata_options
+    }
+
+    pub fn hash(&self) -> Option<&[u8]> {
+        self.hash.as_deref()
     }
 
     pub fn hashtable(&self) -> Option<&HashTable> {
@@ -71,48 +269,61 @@ where
         self.ignored.as_slice()
     }
 
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Computes the hash with the configured [`Strategy`] and number of threads.
     pub fn compute_hash(&mut self) -> Result<()> {
-        #[cfg(not(any(feature = "rayon1", feature = "rayon2")))]
-        {
-            self.compute_hash_serial()
+        if self.strategy == Strategy::Serial {
+            return self.compute_hash_serial();
         }
-
-        #[cfg(feature = "rayon1")]
-        {
-            self.compute_hash_rayon1()
+        if self.threads == 0 {
+            return self.compute_hash_parallel();
         }
 
-        #[cfg(feature = "rayon2")]
-        {
-            self.compute_hash_rayon2()
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()?;
+        pool.install(|| self.compute_hash_parallel())
+    }
+
+    // Runs the parallel strategy in the current thread pool.
+    fn compute_hash_parallel(&mut self) -> Result<()> {
+        match self.strategy {
+            Strategy::Serial => self.compute_hash_serial(),
+            Strategy::Rayon1 => self.compute_hash_rayon1(),
+            Strategy::Rayon2 => self.compute_hash_rayon2(),
         }
     }
 
     /// Computes hash of all PathHashs.
     pub fn compute_hash_serial(&mut self) -> Result<()> {
         let mut ht = HashTable::new();
+        let sizes = self.start_progress();
 
-        for pb in &mut self.pathhashvec {
+        for (i, pb) in self.pathhashvec.iter_mut().enumerate() {
             if pb.hash().is_none() {
-                pb.compute_hash()?;
+                pb.compute_hash().map_err(|e| e.with_path(pb.path()))?;
             }
 
-            let maybe_stripped_path = match &self.root {
-                Some(root) => Cow::from("./") + pb.path().strip_prefix(root)?.to_string_lossy(),
-                None => pb.path().to_string_lossy(),
-            };
+            if let Some(progress) = &self.progress {
+                progress.file_hashed(pb.path(), sizes[i]);
+            }
 
-            ht.add(
-                HashTableEntry::new(pb.hash().unwrap(), maybe_stripped_path)
-                    .expect("Can't create HashTableEntry"),
-            );
+            ht.add(hashtable_entry(pb, self.root.as_deref(), self.algorithm)?);
         }
 
+        self.finish_progress();
         ht.sort();
 
-        let hash = Sha256::digest(ht.to_string());
+        let hash = self.algorithm.digest(ht.to_string());
         self.hashtable = Some(ht);
-        self.hash = Some(hash.into());
+        self.hash = Some(hash);
 
         Ok(())
     }
@@ -120,22 +331,22 @@ where
     // compute in parallel, collect, add serially
     pub fn compute_hash_rayon1(&mut self) -> Result<()> {
         let mut ht = HashTable::new();
+        let sizes = self.start_progress();
 
         let entries: Result<Vec<_>> = self
             .pathhashvec
             .par_iter_mut()
-            .map(|ph| -> Result<HashTableEntry> {
+            .enumerate()
+            .map(|(i, ph)| -> Result<HashTableEntry> {
                 if ph.hash().is_none() {
-                    ph.compute_hash()?;
+                    ph.compute_hash().map_err(|e| e.with_path(ph.path()))?;
                 }
 
-                let maybe_stripped_path = match &self.root {
-                    Some(root) => Cow::from("./") + ph.path().strip_prefix(root)?.to_string_lossy(),
-                    None => ph.path().to_string_lossy(),
-                };
+                if let Some(progress) = &self.progress {
+                    progress.file_hashed(ph.path(), sizes[i]);
+                }
 
-                Ok(HashTableEntry::new(ph.hash().unwrap(), maybe_stripped_path)
-                    .expect("Can't create HashTableEntry"))
+                hashtable_entry(ph, self.root.as_deref(), self.algorithm)
             })
             .collect();
 
@@ -143,11 +354,12 @@ where
             ht.add(entry);
         }
 
+        self.finish_progress();
         ht.sort();
 
-        let hash = Sha256::digest(ht.to_string());
+        let hash = self.algorithm.digest(ht.to_string());
         self.hashtable = Some(ht);
-        self.hash = Some(hash.into());
+        self.hash = Some(hash);
 
         Ok(())
     }
@@ -155,36 +367,55 @@ where
     // protect hashtable with mutex
     pub fn compute_hash_rayon2(&mut self) -> Result<()> {
         let ht = Mutex::new(HashTable::new());
+        let sizes = self.start_progress();
 
         self.pathhashvec
             .par_iter_mut()
-            .try_for_each(|ph| -> Result<()> {
+            .enumerate()
+            .try_for_each(|(i, ph)| -> Result<()> {
                 if ph.hash().is_none() {
-                    ph.compute_hash()?;
+                    ph.compute_hash().map_err(|e| e.with_path(ph.path()))?;
                 }
 
-                let maybe_stripped_path = match &self.root {
-                    Some(root) => Cow::from("./") + ph.path().strip_prefix(root)?.to_string_lossy(),
-                    None => ph.path().to_string_lossy(),
-                };
+                if let Some(progress) = &self.progress {
+                    progress.file_hashed(ph.path(), sizes[i]);
+                }
 
-                let entry = HashTableEntry::new(ph.hash().unwrap(), maybe_stripped_path)
-                    .expect("Can't create HashTableEntry");
+                let entry = hashtable_entry(ph, self.root.as_deref(), self.algorithm)?;
                 ht.lock().unwrap().add(entry);
                 Ok(())
             })?;
 
         let mut ht = ht.into_inner().unwrap();
 
+        self.finish_progress();
         ht.sort();
 
-        let hash = Sha256::digest(ht.to_string());
+        let hash = self.algorithm.digest(ht.to_string());
         self.hashtable = Some(ht);
-        self.hash = Some(hash.into());
+        self.hash = Some(hash);
 
         Ok(())
     }
 
+    // Reports the start of the hashing and returns the size of each file, if progress is observed.
+    fn start_progress(&self) -> Vec<u64> {
+        let Some(progress) = &self.progress else {
+            return vec![];
+        };
+
+        let sizes: Vec<u64> = self.pathhashvec.iter().map(file_size).collect();
+        progress.hashing_started(sizes.len(), sizes.iter().sum());
+
+        sizes
+    }
+
+    fn finish_progress(&self) {
+        if let Some(progress) = &self.progress {
+            progress.hashing_finished();
+        }
+    }
+
     pub fn list_paths(&self) -> Result<Vec<&Path>> {
         let mut paths = vec![];
 
@@ -203,22 +434,401 @@ where
     }
 }
 
-impl DirHash<PathHash> {
-    // This is not as nice as the builder-lite pattern used when adding the files without WalkDir.
-    // How can the builder-lite pattern be applied here as well? Maybe a specific WalkDir type is
-    // required with a build() method that then creates the DirHash. Then builder-lite is used when
-    // adding files manually and a real builder pattern when WalkDir is required.
-    pub fn with_files_from_dir(
-        mut self,
+// State of the parallel walk of `DirHash::compute_hash_from_dir()`, shared by its tasks.
+struct Pipeline<'a, T, F> {
+    dh: &'a DirHash<PathHash>,
+    config: &'a WalkConfig,
+    root: Option<&'a Path>,
+    // Device of the walked directory, if the walk stays on its file system.
+    device: Option<u64>,
+    wrap: &'a F,
+    hashed: Mutex<Vec<(T, HashTableEntry)>>,
+    ignored: Mutex<Vec<(PathBuf, IgnoreReason)>>,
+    // The first error, which stops all remaining tasks.
+    error: Mutex<Option<DirHashError>>,
+}
+
+impl<T, F> Pipeline<'_, T, F>
+where
+    T: PathHashProvider + Send,
+    F: Fn(PathHash) -> T + Sync,
+{
+    fn fail(&self, e: DirHashError) {
+        self.error.lock().unwrap().get_or_insert(e);
+    }
+
+    fn failed(&self) -> bool {
+        self.error.lock().unwrap().is_some()
+    }
+
+    // Handles the entry at `path`, found at `depth` in the directory filtered by `filter`.
+    // `file_type` is the type of the target of followed symlinks. `ancestors` are the device and
+    // inode numbers of the directories above, to detect loops when following symlinks.
+    //
+    // Directories are read and files are hashed in new tasks of `scope`.
+    fn visit<'s>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
+        path: PathBuf,
+        file_type: fs::FileType,
+        depth: usize,
+        filter: &mut Filter,
+        ancestors: &[(u64, u64)],
+    ) -> Result<()> {
+        if !file_type.is_dir() {
+            if depth < self.config.min_depth() {
+                debug!("Above min depth -> skip");
+                return Ok(());
+            }
+
+            let is_symlink = file_type.is_symlink();
+            match self
+                .dh
+                .check_file(&path, is_symlink, depth, self.config, filter)?
+            {
+                Found::File(ph) => {
+                    self.dh.report_found(&path);
+                    self.hash(scope, ph);
+                }
+                Found::Ignored(reason) => self.ignored.lock().unwrap().push((path, reason)),
+            }
+            return Ok(());
+        }
+
+        if depth > 0 {
+            if let Some(reason) = filter.check(&path, true, depth) {
+                debug!("Excluded directory -> skip contents");
+                self.ignored.lock().unwrap().push((path, reason));
+                return Ok(());
+            }
+
+            if is_mount_point(&path, self.device)? {
+                debug!("Mount point -> skip contents");
+                let reason = IgnoreReason::MountPoint;
+                self.ignored.lock().unwrap().push((path, reason));
+                return Ok(());
+            }
+        }
+
+        let mut ancestors = ancestors.to_vec();
+        if self.config.follow_symlinks() {
+            let metadata =
+                fs::metadata(&path).map_err(|e| DirHashError::PathIo(path.clone(), e))?;
+            let id = (metadata.dev(), metadata.ino());
+            if ancestors.contains(&id) {
+                let e =
+                    io::Error::other("file system loop: points to one of its parent directories");
+                return Err(DirHashError::PathIo(path, e));
+            }
+            ancestors.push(id);
+        }
+
+        let filter = filter.clone();
+        scope.spawn(move |scope| {
+            if let Err(e) = self.visit_dir(scope, &path, depth, filter, &ancestors) {
+                self.fail(e);
+            }
+        });
+        Ok(())
+    }
+
+    // Reads the (not excluded) directory at `path` and visits its entries.
+    fn visit_dir<'s>(
+        &'s self,
+        scope: &rayon::Scope<'s>,
         path: &Path,
-        set_root: bool,
-        follow_symlinks: bool,
-        include_hidden_files: bool,
-        ignore_invalid_filetypes: bool,
-    ) -> Result<Self> {
-        let mut files: Vec<PathHash> = vec![];
+        depth: usize,
+        mut filter: Filter,
+        ancestors: &[(u64, u64)],
+    ) -> Result<()> {
+        if self.failed() {
+            return Ok(());
+        }
+
+        filter.enter_dir(path, depth)?;
+
+        // Directories at the max depth are only read if the marker depends on their entries.
+        let read_error = |e| DirHashError::PathIo(path.to_owned(), e);
+        let entries = match self.config.max_depth() == Some(depth) {
+            true => None,
+            false => Some(
+                fs::read_dir(path)
+                    .map_err(read_error)?
+                    .collect::<io::Result<Vec<_>>>()
+                    .map_err(read_error)?,
+            ),
+        };
+
+        let marker =
+            self.dh
+                .directory_marker(path, depth, self.config, &filter, || match &entries {
+                    Some(entries) => Ok(entries.is_empty()),
+                    None => is_empty_dir(path),
+                })?;
+        if let Some(marker) = marker {
+            self.dh.report_found(path);
+            self.hash(scope, marker);
+        }
+
+        for entry in entries.into_iter().flatten() {
+            let path = entry.path();
+            let mut file_type = entry
+                .file_type()
+                .map_err(|e| DirHashError::PathIo(path.clone(), e))?;
+            if file_type.is_symlink() && self.config.follow_symlinks() {
+                file_type = fs::metadata(&path)
+                    .map_err(|e| DirHashError::PathIo(path.clone(), e))?
+                    .file_type();
+            }
+
+            self.visit(scope, path, file_type, depth + 1, &mut filter, ancestors)?;
+        }
+
+        Ok(())
+    }
+
+    // Hashes `ph` in a new task of `scope`.
+    fn hash<'s>(&'s self, scope: &rayon::Scope<'s>, ph: PathHash) {
+        scope.spawn(move |_| {
+            if self.failed() {
+                return;
+            }
+            if let Err(e) = self.hash_file(ph) {
+                self.fail(e);
+            }
+        });
+    }
+
+    fn hash_file(&self, ph: PathHash) -> Result<()> {
+        let mut ph = (self.wrap)(ph);
+        if ph.hash().is_none() {
+            ph.compute_hash().map_err(|e| e.with_path(ph.path()))?;
+        }
+
+        if let Some(progress) = &self.dh.progress {
+            progress.file_hashed(ph.path(), file_size(&ph));
+        }
+
+        let entry = hashtable_entry(&ph, self.root, self.dh.algorithm)?;
+        self.hashed.lock().unwrap().push((ph, entry));
+        Ok(())
+    }
+}
+
+// What the walk does with an entry that isn't a directory.
+enum Found {
+    File(PathHash),
+    Ignored(IgnoreReason),
+}
+
+// Returns the device of the walked directory at `path`, if the walk stays on its file system.
+fn root_device(path: &Path, config: &WalkConfig) -> Result<Option<u64>> {
+    if !config.one_file_system() {
+        return Ok(None);
+    }
+    let metadata = fs::metadata(path).map_err(|e| DirHashError::PathIo(path.to_owned(), e))?;
+    Ok(Some(metadata.dev()))
+}
+
+// Returns `true` if the directory at `path` isn't on the file system with the `device` of the
+// walked directory (if set by `root_device()`).
+fn is_mount_point(path: &Path, device: Option<u64>) -> Result<bool> {
+    let Some(device) = device else {
+        return Ok(false);
+    };
+    let metadata = fs::metadata(path).map_err(|e| DirHashError::PathIo(path.to_owned(), e))?;
+    Ok(metadata.dev() != device)
+}
+
+// Returns why the regular file at `path` is skipped because of its size or modification time, if
+// it is.
+fn check_size_and_mtime(path: &Path, config: &WalkConfig) -> Result<Option<IgnoreReason>> {
+    if !config.filters_metadata() {
+        return Ok(None);
+    }
+
+    let metadata = fs::metadata(path).map_err(|e| DirHashError::PathIo(path.to_owned(), e))?;
+    let size = metadata.len();
+    // Comparing the seconds is enough, as the timestamps don't have fractions.
+    let mtime = metadata.mtime();
+
+    let reason = if config.min_size().is_some_and(|min| size < min) {
+        Some(IgnoreReason::TooSmall)
+    } else if config.max_size().is_some_and(|max| size > max) {
+        Some(IgnoreReason::TooLarge)
+    } else if config.newer().is_some_and(|newer| mtime < newer) {
+        Some(IgnoreReason::TooOld)
+    } else if config.older().is_some_and(|older| mtime >= older) {
+        Some(IgnoreReason::TooNew)
+    } else {
+        None
+    };
+    Ok(reason)
+}
+
+// Enters the directories between `root` and the listed `relative` path into `filter`, like a walk
+// would. `parents` holds the directories entered for the previous path (at depth 1, 2, ...), with
+// the reason they are skipped, if they are. Returns the reason of the nearest skipped parent.
+//
+// Symlinked directories are skipped unless symlinks are followed, and then they must point below
+// the `canonical_root`.
+fn enter_parents(
+    root: &Path,
+    canonical_root: &Path,
+    relative: &Path,
+    config: &WalkConfig,
+    device: Option<u64>,
+    parents: &mut Vec<(PathBuf, Option<IgnoreReason>)>,
+    filter: &mut Filter,
+) -> Result<Option<IgnoreReason>> {
+    let mut dirs: Vec<PathBuf> = relative
+        .ancestors()
+        .skip(1)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(|dir| root.join(dir))
+        .collect();
+    dirs.reverse();
+
+    let common = parents
+        .iter()
+        .zip(&dirs)
+        .take_while(|((parent, _), dir)| parent == *dir)
+        .count();
+    parents.truncate(common);
+
+    for dir in dirs.into_iter().skip(common) {
+        let depth = parents.len() + 1;
+        let reason = match parents.last() {
+            Some((_, Some(reason))) => Some(reason.clone()),
+            _ => {
+                let is_symlink = fs::symlink_metadata(&dir)
+                    .map_err(|e| DirHashError::PathIo(dir.clone(), e))?
+                    .is_symlink();
+                if is_symlink && !config.follow_symlinks() {
+                    Some(IgnoreReason::Symlink)
+                } else {
+                    if is_symlink {
+                        let target = fs::canonicalize(&dir)
+                            .map_err(|e| DirHashError::PathIo(dir.clone(), e))?;
+                        if !target.starts_with(canonical_root) {
+                            return Err(DirHashError::OutsideRoot(root.join(relative)));
+                        }
+                    }
+
+                    match filter.check(&dir, true, depth) {
+                        Some(reason) => Some(reason),
+                        None if is_mount_point(&dir, device)? => Some(IgnoreReason::MountPoint),
+                        None => {
+                            filter.enter_dir(&dir, depth)?;
+                            None
+                        }
+                    }
+                }
+            }
+        };
+        parents.push((dir, reason));
+    }
+
+    Ok(parents.last().and_then(|(_, reason)| reason.clone()))
+}
+
+// Returns the listed `path` relative to `root`, without `.` components, or `None` if it's outside
+// of `root`. Relative paths are relative to `root`, absolute paths are compared with the
+// `absolute_root` (and the `canonical_root`).
+//
+// A `..` can't be resolved lexically, as the directory before it may be a symlink. So the path up
+// to the last `..` is canonicalized and compared with the `canonical_root` instead.
+fn relative_to_root(
+    path: &Path,
+    root: &Path,
+    absolute_root: &Path,
+    canonical_root: &Path,
+) -> Result<Option<PathBuf>> {
+    let components: Vec<Component> = path.components().collect();
+    let without_cur_dir = |components: &[Component]| -> PathBuf {
+        components
+            .iter()
+            .filter(|component| **component != Component::CurDir)
+            .collect()
+    };
+
+    let Some(last_parent) = components
+        .iter()
+        .rposition(|component| *component == Component::ParentDir)
+    else {
+        let path = without_cur_dir(&components);
+        if !path.is_absolute() {
+            return Ok(Some(path));
+        }
+        let relative = path
+            .strip_prefix(absolute_root)
+            .or_else(|_| path.strip_prefix(canonical_root));
+        return Ok(relative.ok().map(Path::to_owned));
+    };
+
+    let (head, tail) = components.split_at(last_parent + 1);
+    let head = root.join(head.iter().collect::<PathBuf>());
+    let head = fs::canonicalize(&head).map_err(|e| DirHashError::PathIo(head, e))?;
+    Ok(head
+        .strip_prefix(canonical_root)
+        .ok()
+        .map(|head| head.join(without_cur_dir(tail))))
+}
+
+fn is_empty_dir(path: &Path) -> Result<bool> {
+    Ok(fs::read_dir(path)
+        .map_err(|e| DirHashError::PathIo(path.to_owned(), e))?
+        .next()
+        .is_none())
+}
+
+// Returns the size of `ph` for progress reports (0 for anything but regular files).
+fn file_size<T: PathHashProvider>(ph: &T) -> u64 {
+    match ph.kind() {
+        EntryKind::File => fs::metadata(ph.path()).map_or(0, |m| m.len()),
+        _ => 0,
+    }
+}
 
-        for entry in WalkDir::new(path).follow_links(follow_symlinks).into_iter() {
+// Creates the hashtable entry of an already hashed `ph`, with its path relative to `root` (if any).
+fn hashtable_entry<T: PathHashProvider>(
+    ph: &T,
+    root: Option<&Path>,
+    algorithm: HashAlgorithm,
+) -> Result<HashTableEntry> {
+    let mut maybe_stripped_path = match root {
+        Some(root) => {
+            let mut path = OsString::from("./");
+            path.push(ph.path().strip_prefix(root)?);
+            path
+        }
+        None => ph.path().as_os_str().to_owned(),
+    };
+
+    if ph.kind() == EntryKind::Dir {
+        maybe_stripped_path.push("/");
+    }
+
+    Ok(
+        HashTableEntry::for_algorithm(algorithm, ph.hash().unwrap(), maybe_stripped_path)?
+            .with_kind(ph.kind())
+            .with_metadata(ph.metadata().cloned().unwrap_or_default()),
+    )
+}
+
+impl DirHash<PathHash> {
+    /// Adds all files found by walking `path` as configured by `config`. Unless absolute paths are
+    /// configured, `path` is set as the root.
+    pub fn with_files_from_dir(mut self, path: &Path, config: &WalkConfig) -> Result<Self> {
+        let mut files: Vec<PathHash> = vec![];
+        let mut filter = Filter::new(path, config.filter())?;
+        let device = root_device(path, config)?;
+
+        let mut walker = WalkDir::new(path)
+            .follow_links(config.follow_symlinks())
+            .max_depth(config.max_depth().unwrap_or(usize::MAX))
+            .into_iter();
+        while let Some(entry) = walker.next() {
             let entry = entry?;
             info!("{:?}", entry);
 
@@ -234,73 +844,175 @@ impl DirHash<PathHash> {
             // directories. However, WalkDir then continues in this symlinked directory and yields
             // the contained files. And file links are now part of the hashing process as they now
             // get the type of their target (i.e., "file").
+            //
+            // With SymlinkMode::Hash, links aren't followed either, but hashed as links (i.e. their
+            // target path) instead of being ignored.
 
             if entry.file_type().is_dir() {
-                debug!("Directory -> skip");
+                if entry.depth() > 0 {
+                    if let Some(reason) = filter.check(entry.path(), true, entry.depth()) {
+                        debug!("Excluded directory -> skip contents");
+                        self.ignored.push((entry.path().to_owned(), reason));
+                        walker.skip_current_dir();
+                        continue;
+                    }
+
+                    if is_mount_point(entry.path(), device)? {
+                        debug!("Mount point -> skip contents");
+                        self.ignored
+                            .push((entry.path().to_owned(), IgnoreReason::MountPoint));
+                        walker.skip_current_dir();
+                        continue;
+                    }
+                }
+
+                filter.enter_dir(entry.path(), entry.depth())?;
+
+                let marker =
+                    self.directory_marker(entry.path(), entry.depth(), config, &filter, || {
+                        is_empty_dir(entry.path())
+                    })?;
+                if let Some(marker) = marker {
+                    files.push(marker);
+                    self.report_found(entry.path());
+                }
                 continue;
             }
 
-            if entry.file_type().is_symlink() {
-                debug!("Symlink -> skip");
-                self.ignored
-                    .push((entry.path().to_owned(), IgnoreReason::Symlink));
+            if entry.depth() < config.min_depth() {
+                debug!("Above min depth -> skip");
                 continue;
             }
 
-            if (!include_hidden_files)
-                && entry
-                    .path()
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .starts_with(".")
-            {
-                debug!("Hidden file -> skip");
-                self.ignored
-                    .push((entry.path().to_owned(), IgnoreReason::Hidden));
+            let is_symlink = entry.file_type().is_symlink();
+            match self.check_file(entry.path(), is_symlink, entry.depth(), config, &mut filter)? {
+                Found::File(ph) => {
+                    files.push(ph);
+                    self.report_found(entry.path());
+                }
+                Found::Ignored(reason) => self.ignored.push((entry.path().to_owned(), reason)),
+            }
+        }
+
+        if !config.absolute() {
+            self.root = Some(path.to_owned());
+        }
+
+        self.ignored.sort();
+
+        self.pathhashvec = files;
+        Ok(self)
+    }
+
+    /// Adds the listed `paths` instead of walking `root`, e.g. the output of `find -print0` or
+    /// `git ls-files -z`. Relative paths are relative to `root`, absolute paths must be below it,
+    /// otherwise [`DirHashError::OutsideRoot`] is returned. Unless absolute paths are configured,
+    /// `root` is set as the root.
+    ///
+    /// The paths are checked as configured by `config`, like the entries found by
+    /// [`DirHash::with_files_from_dir()`], including the symlinks, patterns and mount points of the
+    /// directories between `root` and each path. Followed symlinks and `..` components must not
+    /// leave `root` either. Listed directories only get a marker (if configured), their contents
+    /// aren't added. Paths listed more than once are added once.
+    pub fn with_files_from_list<P: AsRef<Path>>(
+        mut self,
+        root: &Path,
+        paths: impl IntoIterator<Item = P>,
+        config: &WalkConfig,
+    ) -> Result<Self> {
+        let absolute_root =
+            std::path::absolute(root).map_err(|e| DirHashError::PathIo(root.to_owned(), e))?;
+        let canonical_root =
+            fs::canonicalize(root).map_err(|e| DirHashError::PathIo(root.to_owned(), e))?;
+        let mut relative_paths = paths
+            .into_iter()
+            .map(|path| {
+                let path = path.as_ref();
+                relative_to_root(path, root, &absolute_root, &canonical_root)?
+                    .ok_or_else(|| DirHashError::OutsideRoot(path.to_owned()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // Sorted, the paths below a directory follow each other, so it's entered only once.
+        relative_paths.sort();
+        relative_paths.dedup();
+
+        let mut files: Vec<PathHash> = vec![];
+        let mut filter = Filter::new(root, config.filter())?;
+        let device = root_device(root, config)?;
+        filter.enter_dir(root, 0)?;
+        let mut parents = vec![];
+
+        for relative in relative_paths {
+            let path = root.join(&relative);
+            let depth = relative.components().count();
+            info!("{:?}", path);
+
+            if config.max_depth().is_some_and(|max| depth > max) {
+                debug!("Below max depth -> skip");
                 continue;
             }
 
-            // TODO: help...? how can this be improved?
-            match PathHash::new(entry.path()) {
-                Ok(ph) => files.push(ph),
-                Err(e) => {
-                    if ignore_invalid_filetypes {
-                        if let DirHashError::InvalidFileType(filetype, path) = e {
-                            warn!("Ignored invalid file type {:?} for {:?}", filetype, path);
-                            match filetype {
-                                InvalidFileTypeKind::Dir => {
-                                    self.ignored.push((path.to_owned(), IgnoreReason::Dir))
-                                }
-
-                                InvalidFileTypeKind::BlockDevice => self
-                                    .ignored
-                                    .push((path.to_owned(), IgnoreReason::BlockDevice)),
-                                InvalidFileTypeKind::CharDevice => self
-                                    .ignored
-                                    .push((path.to_owned(), IgnoreReason::CharDevice)),
-                                InvalidFileTypeKind::FIFO => {
-                                    self.ignored.push((path.to_owned(), IgnoreReason::FIFO))
-                                }
-                                InvalidFileTypeKind::Socket => {
-                                    self.ignored.push((path.to_owned(), IgnoreReason::Socket))
-                                }
-                            }
-                        } else {
-                            error!("Error while creating PathHash: {}", e);
-                            return Err(e);
-                        }
-                    } else {
-                        error!("Error while creating PathHash: {}", e);
-                        return Err(e);
+            let parent_reason = enter_parents(
+                root,
+                &canonical_root,
+                &relative,
+                config,
+                device,
+                &mut parents,
+                &mut filter,
+            )?;
+            if let Some(reason) = parent_reason {
+                debug!("Skipped parent directory -> skip");
+                self.ignored.push((path, reason));
+                continue;
+            }
+
+            let metadata = match config.follow_symlinks() {
+                true => fs::metadata(&path),
+                false => fs::symlink_metadata(&path),
+            }
+            .map_err(|e| DirHashError::PathIo(path.clone(), e))?;
+
+            if metadata.is_dir() {
+                if depth > 0 {
+                    if let Some(reason) = filter.check(&path, true, depth) {
+                        debug!("Excluded directory -> skip");
+                        self.ignored.push((path, reason));
+                        continue;
                     }
+
+                    if is_mount_point(&path, device)? {
+                        debug!("Mount point -> skip");
+                        self.ignored.push((path, IgnoreReason::MountPoint));
+                        continue;
+                    }
+                }
+
+                let marker =
+                    self.directory_marker(&path, depth, config, &filter, || is_empty_dir(&path))?;
+                if let Some(marker) = marker {
+                    files.push(marker);
+                    self.report_found(&path);
+                }
+                continue;
+            }
+
+            if depth < config.min_depth() {
+                debug!("Above min depth -> skip");
+                continue;
+            }
+
+            match self.check_file(&path, metadata.is_symlink(), depth, config, &mut filter)? {
+                Found::File(ph) => {
+                    files.push(ph);
+                    self.report_found(&path);
                 }
+                Found::Ignored(reason) => self.ignored.push((path, reason)),
             }
         }
 
-        if set_root {
-            self.root = Some(path.to_owned());
+        if !config.absolute() {
+            self.root = Some(root.to_owned());
         }
 
         self.ignored.sort();
@@ -308,6 +1020,245 @@ impl DirHash<PathHash> {
         self.pathhashvec = files;
         Ok(self)
     }
+
+    /// Wraps all files in [`CachedPathHash`]es using `cache`, so that only files whose digest isn't
+    /// cached are read by [`DirHash::compute_hash()`].
+    pub fn with_cache(self, cache: &HashCache) -> DirHash<CachedPathHash> {
+        DirHash {
+            root: self.root,
+            pathhashvec: self
+                .pathhashvec
+                .into_iter()
+                .map(|ph| CachedPathHash::new(ph, cache.clone()))
+                .collect(),
+            algorithm: self.algorithm,
+            read_options: self.read_options,
+            metadata_options: self.metadata_options,
+            hash: self.hash,
+            hashtable: self.hashtable,
+            ignored: self.ignored,
+            progress: self.progress,
+            strategy: self.strategy,
+            threads: self.threads,
+        }
+    }
+
+    /// Walks `path` like [`DirHash::with_files_from_dir()`] and hashes the files like
+    /// [`DirHash::compute_hash()`] in a single parallel pass: directories are read concurrently
+    /// and each file is hashed as soon as it's found, while the walk continues.
+    ///
+    /// The hashtable, the hash and the ignored paths are the same as with the separate steps, and
+    /// [`DirHash::files()`] are sorted by path. It runs in the thread pool selected by
+    /// [`DirHash::with_threads()`], regardless of the [`Strategy`]. Progress observers get
+    /// [`Progress::hashing_started()`] with 0 files first, as the totals aren't known in advance.
+    pub fn compute_hash_from_dir(self, path: &Path, config: &WalkConfig) -> Result<Self> {
+        self.pipeline(path, config, |ph| ph)
+    }
+
+    /// Like [`DirHash::compute_hash_from_dir()`], but with the files wrapped in
+    /// [`CachedPathHash`]es using `cache`, like [`DirHash::with_cache()`].
+    pub fn compute_cached_hash_from_dir(
+        self,
+        path: &Path,
+        config: &WalkConfig,
+        cache: &HashCache,
+    ) -> Result<DirHash<CachedPathHash>> {
+        self.pipeline(path, config, |ph| CachedPathHash::new(ph, cache.clone()))
+    }
+
+    fn pipeline<T, F>(self, path: &Path, config: &WalkConfig, wrap: F) -> Result<DirHash<T>>
+    where
+        T: PathHashProvider + Send,
+        F: Fn(PathHash) -> T + Sync,
+    {
+        if self.threads == 0 {
+            return self.walk_and_hash(path, config, &wrap);
+        }
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()?;
+        pool.install(|| self.walk_and_hash(path, config, &wrap))
+    }
+
+    // Runs the pipeline in the current thread pool.
+    fn walk_and_hash<T, F>(
+        mut self,
+        path: &Path,
+        config: &WalkConfig,
+        wrap: &F,
+    ) -> Result<DirHash<T>>
+    where
+        T: PathHashProvider + Send,
+        F: Fn(PathHash) -> T + Sync,
+    {
+        let root = (!config.absolute()).then(|| path.to_owned());
+        let mut filter = Filter::new(path, config.filter())?;
+        let device = root_device(path, config)?;
+        // Like WalkDir, the root is followed even if it's a symlink.
+        let file_type = fs::metadata(path)
+            .map_err(|e| DirHashError::PathIo(path.to_owned(), e))?
+            .file_type();
+
+        if let Some(progress) = &self.progress {
+            progress.hashing_started(0, 0);
+        }
+
+        let pipeline = Pipeline {
+            dh: &self,
+            config,
+            root: root.as_deref(),
+            device,
+            wrap,
+            hashed: Mutex::new(vec![]),
+            ignored: Mutex::new(vec![]),
+            error: Mutex::new(None),
+        };
+        rayon::scope(|scope| {
+            let root = path.to_owned();
+            if let Err(e) = pipeline.visit(scope, root, file_type, 0, &mut filter, &[]) {
+                pipeline.fail(e);
+            }
+        });
+
+        let Pipeline {
+            hashed,
+            ignored,
+            error,
+            ..
+        } = pipeline;
+        if let Some(e) = error.into_inner().unwrap() {
+            return Err(e);
+        }
+
+        let mut hashed = hashed.into_inner().unwrap();
+        hashed.sort_by(|(a, _), (b, _)| a.path().cmp(b.path()));
+
+        let mut ht = HashTable::new();
+        let mut files = Vec::with_capacity(hashed.len());
+        for (ph, entry) in hashed {
+            files.push(ph);
+            ht.add(entry);
+        }
+
+        self.finish_progress();
+        ht.sort();
+        self.ignored.append(&mut ignored.into_inner().unwrap());
+        self.ignored.sort();
+
+        Ok(DirHash {
+            hash: Some(self.algorithm.digest(ht.to_string())),
+            hashtable: Some(ht),
+            root,
+            pathhashvec: files,
+            algorithm: self.algorithm,
+            read_options: self.read_options,
+            metadata_options: self.metadata_options,
+            ignored: self.ignored,
+            progress: self.progress,
+            strategy: self.strategy,
+            threads: self.threads,
+        })
+    }
+
+    fn report_found(&self, path: &Path) {
+        if let Some(progress) = &self.progress {
+            progress.file_found(path);
+        }
+    }
+
+    // Returns the marker of the (not excluded) directory at `path`, found at `depth` of the walk,
+    // if it gets one. `is_empty` is only called for `DirectoryMode::Empty`.
+    fn directory_marker(
+        &self,
+        path: &Path,
+        depth: usize,
+        config: &WalkConfig,
+        filter: &Filter,
+        is_empty: impl FnOnce() -> Result<bool>,
+    ) -> Result<Option<PathHash>> {
+        let marked = match config.directories() {
+            DirectoryMode::None => false,
+            DirectoryMode::Empty => is_empty()?,
+            DirectoryMode::All => true,
+        };
+        if depth == 0 || depth < config.min_depth() || !marked || !filter.includes_dir(path) {
+            debug!("Directory -> skip");
+            return Ok(None);
+        }
+
+        debug!("Directory -> add marker");
+        let marker = PathHash::directory(path)
+            .map_err(|e| e.with_path(path))?
+            .with_algorithm(self.algorithm)
+            .with_metadata_options(self.metadata_options);
+        Ok(Some(marker))
+    }
+
+    // Checks the entry at `path` (anything but a directory), found at `depth` of the walk.
+    // `is_symlink` is only set for symlinks that aren't followed.
+    fn check_file(
+        &self,
+        path: &Path,
+        is_symlink: bool,
+        depth: usize,
+        config: &WalkConfig,
+        filter: &mut Filter,
+    ) -> Result<Found> {
+        if is_symlink && config.symlinks() != SymlinkMode::Hash {
+            debug!("Symlink -> skip");
+            return Ok(Found::Ignored(IgnoreReason::Symlink));
+        }
+
+        if (!config.include_hidden_files())
+            && path.file_name().unwrap().as_bytes().starts_with(b".")
+        {
+            debug!("Hidden file -> skip");
+            return Ok(Found::Ignored(IgnoreReason::Hidden));
+        }
+
+        if let Some(reason) = filter.check(path, false, depth) {
+            debug!("Excluded file -> skip");
+            return Ok(Found::Ignored(reason));
+        }
+
+        let pathhash = match is_symlink {
+            true => PathHash::symlink(path),
+            false => PathHash::new(path),
+        }
+        .map_err(|e| e.with_path(path));
+
+        if pathhash.is_ok() && !is_symlink {
+            if let Some(reason) = check_size_and_mtime(path, config)? {
+                debug!("Filtered by size or modification time -> skip");
+                return Ok(Found::Ignored(reason));
+            }
+        }
+
+        match pathhash {
+            Ok(ph) => Ok(Found::File(
+                ph.with_algorithm(self.algorithm)
+                    .with_read_options(self.read_options)
+                    .with_metadata_options(self.metadata_options),
+            )),
+            Err(DirHashError::InvalidFileType(filetype, path))
+                if config.ignore_invalid_filetypes() =>
+            {
+                warn!("Ignored invalid file type {:?} for {:?}", filetype, path);
+                Ok(Found::Ignored(match filetype {
+                    InvalidFileTypeKind::Dir => IgnoreReason::Dir,
+                    InvalidFileTypeKind::BlockDevice => IgnoreReason::BlockDevice,
+                    InvalidFileTypeKind::CharDevice => IgnoreReason::CharDevice,
+                    InvalidFileTypeKind::FIFO => IgnoreReason::FIFO,
+                    InvalidFileTypeKind::Socket => IgnoreReason::Socket,
+                }))
+            }
+            Err(e) => {
+                error!("Error while creating PathHash: {}", e);
+                Err(e)
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -352,7 +1303,7 @@ mod tests {
         let spies: Vec<PathHashSpy> = vec![];
         let mut dh = DirHash::new().with_files(spies);
         assert!(dh.hash().is_none());
-        dh.hash = Some(*b"01234567890123456789012345678901");
+        dh.hash = Some(b"01234567890123456789012345678901".to_vec());
         assert!(dh.hash().is_some());
         assert_eq!(dh.hash().unwrap()[7], 0x37);
     }
@@ -527,6 +1478,47 @@ mod tests {
         assert_eq!(dh.hash().unwrap(), b"\x4d\xcf\x91\xbe\xae\x7c\x9f\xcc\x68\xdf\x4f\x57\xab\x43\x44\xa7\x44\xe7\xd0\xc3\x26\x00\x3a\x03\xe7\x99\x6f\x87\xfe\x45\x13\x90");
     }
 
+    #[test]
+    fn compute_hash_with_algorithm() {
+        let spies = vec![
+            PathHashSpy::with_hash_vecs("/b", Some(vec![0xbb; 16]), None),
+            PathHashSpy::with_hash_vecs("/a", Some(vec![0xaa; 16]), None),
+        ];
+        let mut dh = DirHash::new()
+            .with_files(spies)
+            .with_algorithm(HashAlgorithm::Md5);
+
+        assert!(dh.compute_hash().is_ok());
+
+        assert_eq!(
+            dh.hashtable().unwrap().to_string(),
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa  /a\n\
+             bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb  /b\n"
+        );
+        assert_eq!(
+            hex::encode(dh.hash().unwrap()),
+            "f7ae9dc018835d5415ea11bb8c8af423"
+        );
+    }
+
+    #[test]
+    fn compute_hash_with_mismatched_algorithm() {
+        let spies = vec![PathHashSpy::new(
+            "/some/path",
+            Some(*b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"),
+            None,
+        )];
+        let mut dh = DirHash::new()
+            .with_files(spies)
+            .with_algorithm(HashAlgorithm::Sha512);
+
+        let err = dh.compute_hash().unwrap_err();
+        assert!(matches!(err, DirHashError::HashTableEntry(32)));
+
+        assert!(dh.hashtable.is_none());
+        assert!(dh.hash.is_none());
+    }
+
     #[test]
     fn compute_hash_no_files() {
         let spies: Vec<PathHashSpy> = vec![];
@@ -541,6 +1533,34 @@ mod tests {
         assert_eq!(dh.hash().unwrap(), b"\xe3\xb0\xc4\x42\x98\xfc\x1c\x14\x9a\xfb\xf4\xc8\x99\x6f\xb9\x24\x27\xae\x41\xe4\x64\x9b\x93\x4c\xa4\x95\x99\x1b\x78\x52\xb8\x55");
     }
 
+    #[test]
+    fn compute_hash_strategies() {
+        let spies = || {
+            (0..100)
+                .map(|i| PathHashSpy::new(format!("/path/{i}"), Some([i; 32]), None))
+                .collect::<Vec<_>>()
+        };
+        let mut serial = DirHash::new()
+            .with_files(spies())
+            .with_strategy(Strategy::Serial);
+        serial.compute_hash().unwrap();
+
+        for strategy in [Strategy::Rayon1, Strategy::Rayon2] {
+            for threads in [0, 1, 3] {
+                let mut dh = DirHash::new()
+                    .with_files(spies())
+                    .with_strategy(strategy)
+                    .with_threads(threads);
+                assert_eq!(dh.strategy(), strategy);
+                assert_eq!(dh.threads(), threads);
+
+                dh.compute_hash().unwrap();
+                assert_eq!(dh.hashtable(), serial.hashtable());
+                assert_eq!(dh.hash(), serial.hash());
+            }
+        }
+    }
+
     #[test]
     fn list_paths_with_root() {
         let spies = vec![
@@ -587,6 +1607,20 @@ mod tests {
         assert!(matches!(err, DirHashError::RootMismatch(_)));
     }
 
+    #[test]
+    fn retain_files() {
+        let spies = vec![
+            PathHashSpy::new("/pre/fix/some/path", None, None),
+            PathHashSpy::new("/pre/fix/other/path", None, None),
+        ];
+        let dh = DirHash::new()
+            .with_files(spies)
+            .retain_files(|ph| ph.path().starts_with("/pre/fix/other"));
+
+        assert_eq!(dh.files().len(), 1);
+        assert_eq!(dh.files()[0].path(), Path::new("/pre/fix/other/path"));
+    }
+
     #[test]
     fn list_paths_no_files() {
         let dh: DirHash<PathHashSpy> = DirHash::new();
@@ -596,4 +1630,48 @@ mod tests {
             .expect("Can't get the paths from the dirhash");
         assert!(paths.is_empty());
     }
+
+    #[test]
+    fn ignore_reason_from_debug_str() {
+        for reason in [
+            IgnoreReason::Dir,
+            IgnoreReason::FIFO,
+            IgnoreReason::Hidden,
+            IgnoreReason::NotIncluded,
+            IgnoreReason::MountPoint,
+            IgnoreReason::TooSmall,
+            IgnoreReason::TooNew,
+            IgnoreReason::Excluded(String::from("*.o")),
+            IgnoreReason::Excluded(String::from("quote\" back\\slash\ttab ' (x)")),
+            IgnoreReason::Excluded(String::from("e\u{301} \u{7f}")),
+        ] {
+            let debug = format!("{:?}", reason);
+            assert_eq!(debug.parse::<IgnoreReason>().ok(), Some(reason), "{debug}");
+        }
+
+        for s in [
+            "",
+            "hidden",
+            "Excluded",
+            "Excluded(*.o)",
+            "Excluded(\"\\x\")",
+        ] {
+            let err = s.parse::<IgnoreReason>().unwrap_err();
+            assert!(matches!(err, DirHashError::UnknownIgnoreReason(_)), "{s}");
+        }
+    }
+
+    #[test]
+    fn ignore_reason_serde() {
+        let json = serde_json::to_string(&IgnoreReason::BlockDevice).unwrap();
+        assert_eq!(json, r#"{"reason":"block_device"}"#);
+
+        let reason = IgnoreReason::Excluded(String::from("target/"));
+        let json = serde_json::to_string(&reason).unwrap();
+        assert_eq!(json, r#"{"reason":"excluded","pattern":"target/"}"#);
+        assert_eq!(
+            serde_json::from_str::<IgnoreReason>(&json).expect("Can't deserialize"),
+            reason
+        );
+    }
 }
@@ -0,0 +1,166 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::{DirHashError, Result};
+use crate::hashtable::{EntryKind, HashTable};
+
+/// Regular files with identical contents, found by [`find_duplicates()`].
+#[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+pub struct DuplicateGroup {
+    hash: Vec<u8>,
+    size: u64,
+    paths: Vec<OsString>,
+}
+
+impl DuplicateGroup {
+    pub fn hash(&self) -> &[u8] {
+        &self.hash
+    }
+
+    /// Returns the size of each file in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the paths of the files (at least two), sorted.
+    pub fn paths(&self) -> &[OsString] {
+        &self.paths
+    }
+
+    /// Returns the number of bytes that would be freed by keeping only one of the files.
+    pub fn wasted(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+/// Groups the regular files of `hashtable` by their hash. The entries must contain the file size
+/// (see [`crate::metadata::MetadataOptions::size`]), entries without it are skipped, and so are
+/// files smaller than `min_size` bytes.
+///
+/// The groups are sorted by the wasted bytes (largest first), then by hash.
+pub fn find_duplicates(hashtable: &HashTable, min_size: u64) -> Vec<DuplicateGroup> {
+    let mut groups: BTreeMap<&[u8], DuplicateGroup> = BTreeMap::new();
+
+    for entry in hashtable.entries() {
+        let Some(size) = entry.metadata().size else {
+            continue;
+        };
+        if entry.kind() != EntryKind::File || size < min_size {
+            continue;
+        }
+
+        groups
+            .entry(entry.hash())
+            .or_insert_with(|| DuplicateGroup {
+                hash: entry.hash().to_vec(),
+                size,
+                paths: vec![],
+            })
+            .paths
+            .push(entry.path().to_owned());
+    }
+
+    let mut duplicates: Vec<_> = groups
+        .into_values()
+        .filter(|group| group.paths.len() > 1)
+        .map(|mut group| {
+            group.paths.sort();
+            group
+        })
+        .collect();
+    duplicates.sort_by(|a, b| b.wasted().cmp(&a.wasted()).then(a.hash.cmp(&b.hash)));
+
+    duplicates
+}
+
+/// Returns the `paths` of at least `min_size` bytes whose size equals the size of another path.
+/// Only these can have duplicates, so the other files don't need to be hashed.
+pub fn size_collisions<'a>(
+    paths: impl IntoIterator<Item = &'a Path>,
+    min_size: u64,
+) -> Result<HashSet<PathBuf>> {
+    let mut by_size: BTreeMap<u64, Vec<&Path>> = BTreeMap::new();
+
+    for path in paths {
+        let size = fs::metadata(path)
+            .map_err(|e| DirHashError::PathIo(path.to_owned(), e))?
+            .len();
+        if size >= min_size {
+            by_size.entry(size).or_default().push(path);
+        }
+    }
+
+    Ok(by_size
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .flatten()
+        .map(Path::to_owned)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashtable::HashTableEntry;
+    use crate::metadata::FileMetadata;
+
+    fn entry(hash: u8, size: u64, path: &str) -> HashTableEntry {
+        HashTableEntry::new([hash; 32], path)
+            .unwrap()
+            .with_metadata(FileMetadata {
+                size: Some(size),
+                ..Default::default()
+            })
+    }
+
+    #[test]
+    fn groups_sorted_by_wasted_bytes() {
+        let mut ht = HashTable::new();
+        ht.add(entry(1, 10, "./b"));
+        ht.add(entry(1, 10, "./a"));
+        ht.add(entry(2, 5, "./c"));
+        ht.add(entry(2, 5, "./d"));
+        ht.add(entry(2, 5, "./e"));
+        ht.add(entry(2, 5, "./f"));
+        ht.add(entry(3, 100, "./unique"));
+        ht.add(HashTableEntry::new([1; 32], "./no_size").unwrap());
+        ht.add(entry(1, 10, "./link").with_kind(EntryKind::Symlink));
+
+        let groups = find_duplicates(&ht, 0);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].hash(), [2; 32]);
+        assert_eq!(groups[0].wasted(), 15);
+        assert_eq!(groups[1].paths(), ["./a", "./b"]);
+        assert_eq!(groups[1].size(), 10);
+        assert_eq!(groups[1].wasted(), 10);
+
+        let groups = find_duplicates(&ht, 6);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].hash(), [1; 32]);
+    }
+
+    #[test]
+    fn size_collisions_only() {
+        let dir = tempfile::tempdir().expect("Can't create tempdir");
+        for (name, contents) in [("a", "1"), ("b", "2"), ("c", "33"), ("d", ""), ("e", "")] {
+            fs::write(dir.path().join(name), contents).unwrap();
+        }
+        let paths: Vec<_> = ["a", "b", "c", "d", "e"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+
+        let collisions = size_collisions(paths.iter().map(PathBuf::as_path), 1).unwrap();
+        assert_eq!(
+            collisions,
+            HashSet::from([dir.path().join("a"), dir.path().join("b")])
+        );
+
+        let err = size_collisions([dir.path().join("missing").as_path()], 0).unwrap_err();
+        assert!(matches!(err, DirHashError::PathIo(..)));
+    }
+}
@@ -15,16 +15,64 @@ pub enum InvalidFileTypeKind {
 pub enum DirHashError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
-    #[error("PathHash: Invalid filetype: {0:?}")]
+    /// IO error caused by the contained path
+    #[error("IO error: {path}: {1}", path = .0.display())]
+    PathIo(PathBuf, #[source] std::io::Error),
+    #[error("PathHash: Invalid filetype {0:?}: {path}", path = .1.display())]
     InvalidFileType(InvalidFileTypeKind, PathBuf),
-    #[error("HashTableEntry: conversion from a slice to an array failed")]
-    HashTableEntry(#[from] std::array::TryFromSliceError),
-    #[error("Walkdir: Error while walking directory")]
+    #[error("HashTableEntry: hash length {0} doesn't match the digest length of the algorithm")]
+    HashTableEntry(usize),
+    #[error("Walkdir: {0}")]
     WalkDir(#[from] walkdir::Error),
     #[error("DirHash: Mismatched roots")]
     RootMismatch(#[from] std::path::StripPrefixError),
+    #[error("Unknown hash algorithm: {0}")]
+    UnknownAlgorithm(String),
+    #[error("Line {0}: Malformed hashtable entry: {1}")]
+    ParseEntry(usize, String),
+    #[error("Line {0}: Malformed fingerprint metadata: {1}")]
+    ParseMetadata(usize, serde_json::Error),
+    #[error("Line {0}: Malformed fingerprint: {1}")]
+    ParseFingerprint(usize, String),
+    #[error("Fingerprint: Unsupported version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Can't serialize fingerprint metadata: {0}")]
+    SerializeMetadata(serde_json::Error),
+    #[error("Can't serialize JSON output: {0}")]
+    SerializeOutput(serde_json::Error),
+    #[error("Line {0}: Malformed checksum line: {1}")]
+    ParseManifest(usize, String),
+    #[error("Unknown checksum file format: {0}")]
+    UnknownManifestFormat(String),
+    #[error("Unknown ignore reason: {0}")]
+    UnknownIgnoreReason(String),
+    #[error("Not a directory: {path}", path = .0.display())]
+    NotADirectory(PathBuf),
+    #[error("Not a file: {path}", path = .0.display())]
+    NotAFile(PathBuf),
+    #[error("Path outside of the root: {path}", path = .0.display())]
+    OutsideRoot(PathBuf),
+    #[error("Conflicting options: {0}")]
+    ConflictingOptions(String),
+    #[error("Filter: Invalid pattern or ignore file: {0}")]
+    Filter(#[from] ignore::Error),
+    #[error("Can't create thread pool: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+    #[error("Merkle tree: {0} is both a file and a directory")]
+    MerkleTree(String),
     #[error("Unknown error")]
     Unknown,
 }
 
+impl DirHashError {
+    /// Attaches `path` to a plain [`DirHashError::Io`], turning it into a
+    /// [`DirHashError::PathIo`]. Other errors are returned unchanged.
+    pub fn with_path(self, path: impl AsRef<std::path::Path>) -> Self {
+        match self {
+            DirHashError::Io(e) => DirHashError::PathIo(path.as_ref().to_owned(), e),
+            e => e,
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, DirHashError>;
@@ -0,0 +1,166 @@
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    fmt::Write,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+};
+
+/// Escapes `path` for the line-based formats (hashtables, fingerprints and checksum files), like
+/// the coreutils checksum tools (e.g. `sha256sum`): backslashes are doubled, newlines and carriage
+/// returns are written as `\n` and `\r`. Bytes that aren't valid UTF-8 are written as `\xHH`, which
+/// coreutils doesn't do. Lines containing an escaped path start with a backslash, so a path can't
+/// span several lines.
+///
+/// Returns the path unchanged (borrowed) if nothing needs to be escaped, so an owned result means
+/// the line must be marked.
+pub fn escape_path(path: &OsStr) -> Cow<'_, str> {
+    let bytes = path.as_bytes();
+    if let Ok(path) = std::str::from_utf8(bytes) {
+        if !path.chars().any(|c| escape_char(c).is_some()) {
+            return Cow::Borrowed(path);
+        }
+    }
+
+    let mut escaped = String::with_capacity(bytes.len() + 2);
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match escape_char(c) {
+                Some(sequence) => escaped.push_str(sequence),
+                None => escaped.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            write!(escaped, "\\x{byte:02x}").expect("Can't write to string buffer");
+        }
+    }
+
+    Cow::Owned(escaped)
+}
+
+/// Reverses [`escape_path()`]. Returns `None` for an invalid escape sequence. Other bytes are kept
+/// as they are, so paths escaped by coreutils (which writes invalid UTF-8 as is) can be unescaped
+/// too.
+pub fn unescape_path(escaped: impl AsRef<[u8]>) -> Option<OsString> {
+    let escaped = escaped.as_ref();
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut rest = escaped;
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+
+        match rest {
+            [b'\\', tail @ ..] => {
+                bytes.push(b'\\');
+                rest = tail;
+            }
+            [b'n', tail @ ..] => {
+                bytes.push(b'\n');
+                rest = tail;
+            }
+            [b'r', tail @ ..] => {
+                bytes.push(b'\r');
+                rest = tail;
+            }
+            [b'x', high, low, tail @ ..] if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                let hex = [*high, *low];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = tail;
+            }
+            _ => return None,
+        }
+    }
+
+    Some(OsString::from_vec(bytes))
+}
+
+/// Returns the marker of a line starting with the `escaped` path (as returned by [`escape_path()`]):
+/// a backslash if the path was escaped, otherwise nothing. Unescaped paths can't contain a
+/// backslash, so the escaped form alone tells them apart.
+pub fn line_marker(escaped: &str) -> &'static str {
+    match escaped.contains('\\') {
+        true => "\\",
+        false => "",
+    }
+}
+
+// Returns the escape sequence of the (valid UTF-8) character `c`, if it needs one.
+fn escape_char(c: char) -> Option<&'static str> {
+    match c {
+        '\\' => Some("\\\\"),
+        '\n' => Some("\\n"),
+        '\r' => Some("\\r"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unchanged() {
+        let path = OsStr::new("./dir/file name ✓");
+        assert!(matches!(
+            escape_path(path),
+            Cow::Borrowed("./dir/file name ✓")
+        ));
+        assert_eq!(unescape_path("./dir/file name ✓").unwrap(), path);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let paths: [&[u8]; 6] = [
+            b"back\\slash",
+            b"./new\nline\n",
+            b"./carriage\r\nreturn",
+            b"./a\x80",
+            b"./a\x81\xff\\x80",
+            "./ü\u{FFFD}".as_bytes(),
+        ];
+        let escaped: Vec<_> = paths
+            .iter()
+            .map(|path| escape_path(OsStr::from_bytes(path)).into_owned())
+            .collect();
+        assert_eq!(
+            escaped,
+            [
+                "back\\\\slash",
+                "./new\\nline\\n",
+                "./carriage\\r\\nreturn",
+                "./a\\x80",
+                "./a\\x81\\xff\\\\x80",
+                "./ü\u{FFFD}"
+            ]
+        );
+
+        for (path, escaped) in paths.iter().zip(&escaped) {
+            assert_eq!(unescape_path(escaped).unwrap().as_bytes(), *path);
+        }
+
+        let raw: &[u8] = b"./raw\\\\n\\n\xff";
+        assert_eq!(unescape_path(raw).unwrap().as_bytes(), b"./raw\\n\n\xff");
+    }
+
+    #[test]
+    fn marker() {
+        assert_eq!(line_marker(&escape_path(OsStr::new("./a b"))), "");
+        assert_eq!(line_marker(&escape_path(OsStr::new("./a\nb"))), "\\");
+        assert_eq!(line_marker(&escape_path(OsStr::new("./a\\b"))), "\\");
+        assert_eq!(
+            line_marker(&escape_path(OsStr::from_bytes(b"./a\xff"))),
+            "\\"
+        );
+    }
+
+    #[test]
+    fn invalid_sequences() {
+        for escaped in ["a\\", "a\\t", "a\\N", "a\\x8", "a\\xzz", "a\\x\\\\"] {
+            assert_eq!(unescape_path(escaped), None, "{escaped}");
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::dirhash::IgnoreReason;
+use crate::error::Result;
+
+/// Names of the ignore files read when [`FilterOptions::ignore_files`] is set, in increasing order
+/// of precedence.
+pub const IGNORE_FILE_NAMES: [&str; 3] = [".gitignore", ".ignore", ".dirhashignore"];
+
+/// Gitignore-style filter for the files found by [`crate::dirhash::DirHash::with_files_from_dir()`].
+///
+/// All patterns use the syntax of `.gitignore` files and are relative to the walked directory
+/// (e.g. `target/` matches every directory named `target`, `/target/` only the top-level one).
+///
+/// Only the options in use are serialized, so fingerprints without any filter stay unchanged.
+#[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
+pub struct FilterOptions {
+    /// Paths to exclude. Excluded directories are not descended into. A pattern prefixed with `!`
+    /// re-includes a path excluded by an earlier pattern or an ignore file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Files to include. If empty, all files are included. A file is included if it or one of its
+    /// parent directories matches.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Respect the [`IGNORE_FILE_NAMES`] in the walked directory and all of its subdirectories.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_files: bool,
+}
+
+impl FilterOptions {
+    /// Returns `true` if nothing is filtered.
+    pub fn is_empty(&self) -> bool {
+        self.exclude.is_empty() && self.include.is_empty() && !self.ignore_files
+    }
+}
+
+/// [`FilterOptions`] compiled for a walk starting at a specific root.
+///
+/// The walk must report each directory it descends into with [`Filter::enter_dir()`], so that the
+/// ignore files found on the way are applied to the entries below. Cloning is cheap, so a parallel
+/// walk can keep a filter per directory.
+#[derive(Clone, Debug)]
+pub(crate) struct Filter {
+    exclude: Arc<Gitignore>,
+    include: Option<Arc<Gitignore>>,
+    ignore_files: bool,
+    // Matchers of the ignore files of the directories on the current path, with the walk depth of
+    // the directory they were found in.
+    ignore_stack: Vec<(usize, Arc<Gitignore>)>,
+}
+
+impl Filter {
+    pub(crate) fn new(root: &Path, options: &FilterOptions) -> Result<Self> {
+        let include = if options.include.is_empty() {
+            None
+        } else {
+            Some(Arc::new(build_matcher(root, &options.include)?))
+        };
+
+        Ok(Self {
+            exclude: Arc::new(build_matcher(root, &options.exclude)?),
+            include,
+            ignore_files: options.ignore_files,
+            ignore_stack: Vec::new(),
+        })
+    }
+
+    /// Reads the ignore files (if enabled) of `dir`, found at `depth` of the walk.
+    pub(crate) fn enter_dir(&mut self, dir: &Path, depth: usize) -> Result<()> {
+        self.leave_dirs(depth);
+
+        if !self.ignore_files {
+            return Ok(());
+        }
+
+        let mut builder = GitignoreBuilder::new(dir);
+        let mut found = false;
+
+        for name in IGNORE_FILE_NAMES {
+            let ignore_file = dir.join(name);
+            if !ignore_file.is_file() {
+                continue;
+            }
+
+            debug!("Reading ignore file {:?}", ignore_file);
+            found = true;
+
+            // Like git, skip invalid patterns instead of failing, but not unreadable files.
+            if let Some(e) = builder.add(&ignore_file) {
+                if e.is_io() {
+                    return Err(e.into());
+                }
+                warn!("Skipped invalid patterns in {:?}: {}", ignore_file, e);
+            }
+        }
+
+        if found {
+            self.ignore_stack.push((depth, Arc::new(builder.build()?)));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the reason why the entry at `path` (found at `depth` of the walk) is filtered out,
+    /// or `None` if it is kept.
+    ///
+    /// The exclude patterns take precedence over the ignore files, and ignore files in deeper
+    /// directories take precedence over the ones above. The include patterns are only checked for
+    /// files.
+    pub(crate) fn check(
+        &mut self,
+        path: &Path,
+        is_dir: bool,
+        depth: usize,
+    ) -> Option<IgnoreReason> {
+        self.leave_dirs(depth);
+
+        match self.exclude.matched(path, is_dir) {
+            Match::Ignore(glob) => return Some(IgnoreReason::Excluded(glob.original().to_owned())),
+            Match::Whitelist(_) => {}
+            Match::None => {
+                for (_, matcher) in self.ignore_stack.iter().rev() {
+                    match matcher.matched(path, is_dir) {
+                        Match::Ignore(glob) => {
+                            return Some(IgnoreReason::Excluded(glob.original().to_owned()))
+                        }
+                        Match::Whitelist(_) => break,
+                        Match::None => {}
+                    }
+                }
+            }
+        }
+
+        if let Some(include) = &self.include {
+            if !is_dir && !include.matched_path_or_any_parents(path, false).is_ignore() {
+                return Some(IgnoreReason::NotIncluded);
+            }
+        }
+
+        None
+    }
+
+    /// Returns `true` if the (not excluded) directory at `path` is covered by the include patterns,
+    /// i.e. if it or one of its parent directories matches.
+    pub(crate) fn includes_dir(&self, path: &Path) -> bool {
+        match &self.include {
+            Some(include) => include.matched_path_or_any_parents(path, true).is_ignore(),
+            None => true,
+        }
+    }
+
+    // Drops the matchers of directories the walk has left.
+    fn leave_dirs(&mut self, depth: usize) {
+        while self.ignore_stack.last().is_some_and(|(d, _)| *d >= depth) {
+            self.ignore_stack.pop();
+        }
+    }
+}
+
+fn build_matcher(root: &Path, patterns: &[String]) -> Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in patterns {
+        builder.add_line(None, pattern)?;
+    }
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn options(exclude: &[&str], include: &[&str], ignore_files: bool) -> FilterOptions {
+        FilterOptions {
+            exclude: exclude.iter().map(|p| p.to_string()).collect(),
+            include: include.iter().map(|p| p.to_string()).collect(),
+            ignore_files,
+        }
+    }
+
+    fn excluded(pattern: &str) -> Option<IgnoreReason> {
+        Some(IgnoreReason::Excluded(pattern.to_owned()))
+    }
+
+    #[test]
+    fn is_empty() {
+        assert!(FilterOptions::default().is_empty());
+        assert!(!options(&["target/"], &[], false).is_empty());
+        assert!(!options(&[], &["*.rs"], false).is_empty());
+        assert!(!options(&[], &[], true).is_empty());
+    }
+
+    #[test]
+    fn exclude() {
+        let root = Path::new("/root");
+        let mut filter = Filter::new(root, &options(&["target/", "*.o", "!keep.o"], &[], false))
+            .expect("Can't create Filter");
+
+        assert_eq!(
+            filter.check(&root.join("target"), true, 1),
+            excluded("target/")
+        );
+        assert_eq!(
+            filter.check(&root.join("a/target"), true, 2),
+            excluded("target/")
+        );
+        assert_eq!(filter.check(&root.join("target"), false, 1), None);
+        assert_eq!(filter.check(&root.join("a/b.o"), false, 2), excluded("*.o"));
+        assert_eq!(filter.check(&root.join("a/keep.o"), false, 2), None);
+        assert_eq!(filter.check(&root.join("a/b.c"), false, 2), None);
+    }
+
+    #[test]
+    fn exclude_anchored() {
+        let root = Path::new("/root");
+        let mut filter =
+            Filter::new(root, &options(&["/build"], &[], false)).expect("Can't create Filter");
+
+        assert_eq!(
+            filter.check(&root.join("build"), true, 1),
+            excluded("/build")
+        );
+        assert_eq!(filter.check(&root.join("a/build"), true, 2), None);
+    }
+
+    #[test]
+    fn include() {
+        let root = Path::new("/root");
+        let mut filter = Filter::new(root, &options(&["*.tmp.rs"], &["*.rs", "docs/"], false))
+            .expect("Can't create Filter");
+
+        assert_eq!(filter.check(&root.join("src/main.rs"), false, 2), None);
+        assert_eq!(filter.check(&root.join("docs/readme.md"), false, 2), None);
+        assert_eq!(
+            filter.check(&root.join("readme.md"), false, 1),
+            Some(IgnoreReason::NotIncluded)
+        );
+        assert_eq!(
+            filter.check(&root.join("a.tmp.rs"), false, 1),
+            excluded("*.tmp.rs")
+        );
+        // Directories are still descended into
+        assert_eq!(filter.check(&root.join("src"), true, 1), None);
+
+        assert!(filter.includes_dir(&root.join("docs")));
+        assert!(filter.includes_dir(&root.join("docs/api")));
+        assert!(!filter.includes_dir(&root.join("src")));
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        let err = Filter::new(Path::new("/root"), &options(&["{a,b"], &[], false)).unwrap_err();
+        assert!(matches!(err, crate::error::DirHashError::Filter(_)));
+    }
+
+    #[test]
+    fn ignore_files() {
+        let dir = tempdir().expect("Can't create tempdir");
+        let root = dir.path();
+        fs::create_dir_all(root.join("a/b")).expect("Can't create dirs");
+        fs::write(root.join(".gitignore"), "*.log\n*.tmp\n").expect("Can't write ignore file");
+        fs::write(root.join(".dirhashignore"), "!keep.tmp\n").expect("Can't write ignore file");
+        fs::write(root.join("a/.ignore"), "!important.log\nsecret\n")
+            .expect("Can't write ignore file");
+
+        let mut filter = Filter::new(root, &options(&[], &[], true)).expect("Can't create Filter");
+        filter.enter_dir(root, 0).expect("Can't enter root");
+
+        assert_eq!(
+            filter.check(&root.join("x.log"), false, 1),
+            excluded("*.log")
+        );
+        assert_eq!(
+            filter.check(&root.join("x.tmp"), false, 1),
+            excluded("*.tmp")
+        );
+        assert_eq!(filter.check(&root.join("keep.tmp"), false, 1), None);
+        assert_eq!(filter.check(&root.join("secret"), false, 1), None);
+
+        assert_eq!(filter.check(&root.join("a"), true, 1), None);
+        filter
+            .enter_dir(&root.join("a"), 1)
+            .expect("Can't enter dir");
+        assert_eq!(filter.check(&root.join("a/important.log"), false, 2), None);
+        assert_eq!(
+            filter.check(&root.join("a/other.log"), false, 2),
+            excluded("*.log")
+        );
+        assert_eq!(
+            filter.check(&root.join("a/secret"), false, 2),
+            excluded("secret")
+        );
+
+        filter
+            .enter_dir(&root.join("a/b"), 2)
+            .expect("Can't enter dir");
+        assert_eq!(
+            filter.check(&root.join("a/b/secret"), false, 3),
+            excluded("secret")
+        );
+
+        // Leaving "a" drops its ignore file
+        assert_eq!(filter.check(&root.join("secret"), false, 1), None);
+        assert_eq!(
+            filter.check(&root.join("important.log"), false, 1),
+            excluded("*.log")
+        );
+
+        dir.close().expect("Can't close tempdir");
+    }
+
+    #[test]
+    fn ignore_files_disabled() {
+        let dir = tempdir().expect("Can't create tempdir");
+        let root = dir.path();
+        fs::write(root.join(".gitignore"), "*.log\n").expect("Can't write ignore file");
+
+        let mut filter = Filter::new(root, &options(&[], &[], false)).expect("Can't create Filter");
+        filter.enter_dir(root, 0).expect("Can't enter root");
+        assert_eq!(filter.check(&root.join("x.log"), false, 1), None);
+
+        dir.close().expect("Can't close tempdir");
+    }
+
+    #[test]
+    fn exclude_overrides_ignore_files() {
+        let dir = tempdir().expect("Can't create tempdir");
+        let root = dir.path();
+        fs::write(root.join(".gitignore"), "*.log\n").expect("Can't write ignore file");
+
+        let mut filter =
+            Filter::new(root, &options(&["!x.log"], &[], true)).expect("Can't create Filter");
+        filter.enter_dir(root, 0).expect("Can't enter root");
+        assert_eq!(filter.check(&root.join("x.log"), false, 1), None);
+        assert_eq!(
+            filter.check(&root.join("y.log"), false, 1),
+            excluded("*.log")
+        );
+
+        dir.close().expect("Can't close tempdir");
+    }
+}
@@ -0,0 +1,465 @@
+use std::{fmt::Display, io::BufRead, str::FromStr};
+
+use serde::de::DeserializeOwned;
+
+use crate::algorithm::HashAlgorithm;
+use crate::dirhash::IgnoreReason;
+use crate::error::{DirHashError, Result};
+use crate::escape::line_marker;
+use crate::hashtable::{HashTable, HashTableEntry};
+
+const METADATA_PREFIX: &str = "# ";
+const IGNORED_HEADER: &str = "Ignored files:";
+
+/// A fingerprint file as written by `dirhash analyze`:
+///
+/// ```text
+/// # {
+/// #   "version": 1,
+/// #   ...
+/// # }
+///
+/// <hash>  ./some/file
+/// <hash>  ./other/file
+///
+/// <hash of the hashtable>
+///
+/// Ignored files:
+/// ./.hidden: Hidden
+/// ./a.o: Excluded("*.o")
+/// ```
+///
+/// The metadata is kept as JSON text, so it is written back unchanged. Its `algorithm` field
+/// (defaults to [`HashAlgorithm::Sha256`] if missing) determines the expected hash lengths. The
+/// "Ignored files:" section is optional, its reasons are written as the [`Debug`] representation of
+/// [`IgnoreReason`]. Like in the hashtable, lines with an escaped path start with a backslash.
+#[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+pub struct Fingerprint {
+    metadata: String,
+    algorithm: HashAlgorithm,
+    hashtable: HashTable,
+    hash: Vec<u8>,
+    ignored: Vec<(String, IgnoreReason)>,
+}
+
+impl Fingerprint {
+    /// Creates a fingerprint from its parts. `metadata` must be a JSON object and is checked for the
+    /// `algorithm` field. `ignored` contains the path (escaped like in the hashtable) and the reason
+    /// of each ignored file.
+    pub fn new(
+        metadata: impl Into<String>,
+        hashtable: HashTable,
+        hash: impl AsRef<[u8]>,
+        ignored: Vec<(String, IgnoreReason)>,
+    ) -> Result<Self> {
+        let metadata = metadata.into();
+        let algorithm = parse_algorithm(&metadata)?;
+
+        let hash = hash.as_ref();
+        if hash.len() != algorithm.output_len() {
+            return Err(DirHashError::HashTableEntry(hash.len()));
+        }
+
+        Ok(Self {
+            metadata,
+            algorithm,
+            hashtable,
+            hash: hash.to_vec(),
+            ignored,
+        })
+    }
+
+    pub fn from_reader(reader: impl BufRead) -> Result<Self> {
+        let mut lines = NumberedLines::new(reader);
+
+        // Metadata
+        let mut metadata = vec![];
+        while let Some(line) = lines.next_if(|line| line.starts_with(METADATA_PREFIX))? {
+            metadata.push(line[METADATA_PREFIX.len()..].to_owned());
+        }
+
+        if metadata.is_empty() {
+            return Err(DirHashError::ParseFingerprint(
+                1,
+                String::from("missing metadata"),
+            ));
+        }
+
+        let metadata = metadata.join("\n");
+        let algorithm = parse_algorithm(&metadata)?;
+
+        if !lines.expect("an empty line after the metadata")?.is_empty() {
+            return Err(lines.error("expected an empty line after the metadata"));
+        }
+
+        // Hashtable (ends with an empty line)
+        let mut hashtable = HashTable::new();
+        loop {
+            let line = lines.expect("the end of the hashtable")?;
+            if line.is_empty() {
+                break;
+            }
+            hashtable.add(HashTableEntry::parse(
+                &line,
+                lines.line_number(),
+                algorithm,
+            )?);
+        }
+
+        // Hash
+        let line = lines.expect("the hash")?;
+        let hash = hex::decode(&line).map_err(|e| lines.error(&format!("invalid hash: {e}")))?;
+        if hash.len() != algorithm.output_len() {
+            return Err(lines.error(&format!(
+                "hash length {} doesn't match {algorithm} ({})",
+                hash.len(),
+                algorithm.output_len()
+            )));
+        }
+
+        // Ignored files (optional)
+        let mut ignored = vec![];
+        if let Some(line) = lines.next()? {
+            if !line.is_empty() {
+                return Err(lines.error("expected an empty line after the hash"));
+            }
+
+            if lines.expect(&format!("\"{IGNORED_HEADER}\""))? != IGNORED_HEADER {
+                return Err(lines.error(&format!("expected \"{IGNORED_HEADER}\"")));
+            }
+
+            while let Some(line) = lines.next()? {
+                let line = line.strip_prefix('\\').unwrap_or(&line);
+                let (path, reason) = parse_ignored(line).ok_or_else(|| {
+                    lines.error("malformed ignored file (expected \"<path>: <reason>\")")
+                })?;
+                ignored.push((path.to_owned(), reason));
+            }
+        }
+
+        Ok(Self {
+            metadata,
+            algorithm,
+            hashtable,
+            hash,
+            ignored,
+        })
+    }
+
+    /// Returns the metadata as JSON text (without the `# ` prefixes).
+    pub fn metadata(&self) -> &str {
+        &self.metadata
+    }
+
+    /// Deserializes the metadata into `M`.
+    pub fn metadata_as<M: DeserializeOwned>(&self) -> Result<M> {
+        serde_json::from_str(&self.metadata).map_err(|e| DirHashError::ParseMetadata(e.line(), e))
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn hashtable(&self) -> &HashTable {
+        &self.hashtable
+    }
+
+    pub fn hash(&self) -> &[u8] {
+        &self.hash
+    }
+
+    /// Returns the path (escaped like in the hashtable, without the line marker) and the reason of
+    /// each ignored file.
+    pub fn ignored(&self) -> &[(String, IgnoreReason)] {
+        self.ignored.as_slice()
+    }
+}
+
+// Lines of a reader, keeping track of the (1-based) number of the last returned line for errors.
+struct NumberedLines<R: BufRead> {
+    lines: std::iter::Peekable<std::io::Lines<R>>,
+    line_number: usize,
+}
+
+impl<R: BufRead> NumberedLines<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines().peekable(),
+            line_number: 0,
+        }
+    }
+
+    fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    fn next(&mut self) -> Result<Option<String>> {
+        match self.lines.next().transpose()? {
+            Some(line) => {
+                self.line_number += 1;
+                Ok(Some(line))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_if(&mut self, func: impl FnOnce(&str) -> bool) -> Result<Option<String>> {
+        match self.lines.peek() {
+            Some(Ok(line)) if func(line) => self.next(),
+            Some(Ok(_)) | None => Ok(None),
+            // Return the error
+            Some(Err(_)) => self.next(),
+        }
+    }
+
+    // Returns the next line or an error at the line after the last one if the end is reached.
+    fn expect(&mut self, expected: &str) -> Result<String> {
+        match self.next()? {
+            Some(line) => Ok(line),
+            None => Err(DirHashError::ParseFingerprint(
+                self.line_number + 1,
+                format!("unexpected end of file, expected {expected}"),
+            )),
+        }
+    }
+
+    fn error(&self, reason: &str) -> DirHashError {
+        DirHashError::ParseFingerprint(self.line_number, reason.to_owned())
+    }
+}
+
+// Splits a line of the "Ignored files:" section into path and reason. Both may contain ": ", so the
+// reason is the shortest suffix after a ": " that is a valid reason. A suffix starting inside the
+// pattern of `Excluded` can't be one, as its quotes are escaped.
+fn parse_ignored(line: &str) -> Option<(&str, IgnoreReason)> {
+    line.rmatch_indices(": ").find_map(|(i, separator)| {
+        let reason = line[i + separator.len()..].parse().ok()?;
+        Some((&line[..i], reason))
+    })
+}
+
+// Reads the algorithm from the metadata. As the metadata always starts at the first line, the line
+// numbers of the JSON errors also match the lines of the fingerprint.
+fn parse_algorithm(metadata: &str) -> Result<HashAlgorithm> {
+    let value: serde_json::Value =
+        serde_json::from_str(metadata).map_err(|e| DirHashError::ParseMetadata(e.line(), e))?;
+
+    match value.get("algorithm") {
+        Some(algorithm) => {
+            serde_json::from_value(algorithm.clone()).map_err(|e| DirHashError::ParseMetadata(1, e))
+        }
+        None => Ok(HashAlgorithm::default()),
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = DirHashError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_reader(s.as_bytes())
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in self.metadata.lines() {
+            writeln!(f, "{METADATA_PREFIX}{line}")?;
+        }
+
+        write!(f, "\n{}\n{}\n", self.hashtable, hex::encode(&self.hash))?;
+
+        if !self.ignored.is_empty() {
+            write!(f, "\n{IGNORED_HEADER}\n")?;
+            for (path, reason) in &self.ignored {
+                writeln!(f, "{}{path}: {reason:?}", line_marker(path))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    const FINGERPRINT: &str = r#"# {
+#   "version": 1,
+#   "path": "/tmp/.tmp_cli_verify",
+#   "absolute": false
+# }
+
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./0
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./d/0
+
+d54869b935d36b0260556f9d283c92c32d2b44ccba9c0c5f6a7bf69183650b4e
+
+Ignored files:
+./.hidden: Hidden
+./d/link: Symlink
+"#;
+
+    #[derive(Debug, Deserialize)]
+    struct Metadata {
+        version: u8,
+        path: String,
+        absolute: bool,
+    }
+
+    #[test]
+    fn parse() {
+        let fp: Fingerprint = FINGERPRINT.parse().expect("Can't parse fingerprint");
+
+        assert_eq!(fp.algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(fp.hashtable().entries().len(), 2);
+        assert_eq!(fp.hashtable().entries()[1].path(), "./d/0");
+        assert_eq!(
+            hex::encode(fp.hash()),
+            "d54869b935d36b0260556f9d283c92c32d2b44ccba9c0c5f6a7bf69183650b4e"
+        );
+        assert_eq!(
+            fp.ignored(),
+            [
+                (String::from("./.hidden"), IgnoreReason::Hidden),
+                (String::from("./d/link"), IgnoreReason::Symlink),
+            ]
+        );
+
+        let meta: Metadata = fp.metadata_as().expect("Can't deserialize metadata");
+        assert_eq!(meta.version, 1);
+        assert_eq!(meta.path, "/tmp/.tmp_cli_verify");
+        assert!(!meta.absolute);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let fp: Fingerprint = FINGERPRINT.parse().expect("Can't parse fingerprint");
+        assert_eq!(fp.to_string(), FINGERPRINT);
+
+        let without_ignored = FINGERPRINT.split("\nIgnored files:").next().unwrap();
+        let fp: Fingerprint = without_ignored.parse().expect("Can't parse fingerprint");
+        assert!(fp.ignored().is_empty());
+        assert_eq!(fp.to_string(), without_ignored);
+    }
+
+    #[test]
+    fn parse_ignored_separator() {
+        let fingerprint = FINGERPRINT.replace(
+            "./d/link: Symlink\n",
+            "./b: c: Excluded(\"*: c\")\n./d: Hidden: Excluded(\"d: \\\"Hidden\\\")\")\n./e: Hidden: Hidden\n\\./f\\nx: Hidden\n",
+        );
+        let fp: Fingerprint = fingerprint.parse().expect("Can't parse fingerprint");
+        assert_eq!(
+            fp.ignored()[1..],
+            [
+                (
+                    String::from("./b: c"),
+                    IgnoreReason::Excluded(String::from("*: c"))
+                ),
+                (
+                    String::from("./d: Hidden"),
+                    IgnoreReason::Excluded(String::from("d: \"Hidden\")"))
+                ),
+                (String::from("./e: Hidden"), IgnoreReason::Hidden),
+                // Without the line marker
+                (String::from("./f\\nx"), IgnoreReason::Hidden),
+            ]
+        );
+        assert_eq!(fp.to_string(), fingerprint);
+    }
+
+    #[test]
+    fn roundtrip_no_entries() {
+        let fingerprint = "# {}\n\
+                           \n\
+                           \n\
+                           e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\n";
+        let fp: Fingerprint = fingerprint.parse().expect("Can't parse fingerprint");
+        assert!(fp.hashtable().entries().is_empty());
+        assert_eq!(fp.to_string(), fingerprint);
+    }
+
+    #[test]
+    fn new() {
+        let fp: Fingerprint = FINGERPRINT.parse().expect("Can't parse fingerprint");
+        let created = Fingerprint::new(
+            fp.metadata(),
+            fp.hashtable().clone(),
+            fp.hash(),
+            fp.ignored().to_vec(),
+        )
+        .expect("Can't create fingerprint");
+        assert_eq!(created, fp);
+
+        let err = Fingerprint::new("{}", HashTable::new(), [0; 16], vec![]).unwrap_err();
+        assert!(matches!(err, DirHashError::HashTableEntry(16)));
+    }
+
+    #[test]
+    fn parse_with_algorithm() {
+        let fingerprint = "# {\n\
+                           #   \"algorithm\": \"md5\"\n\
+                           # }\n\
+                           \n\
+                           d41d8cd98f00b204e9800998ecf8427e  ./0\n\
+                           \n\
+                           30216ac49398b5b1cd1b9ef2f926dd3e\n";
+        let fp: Fingerprint = fingerprint.parse().expect("Can't parse fingerprint");
+        assert_eq!(fp.algorithm(), HashAlgorithm::Md5);
+        assert_eq!(fp.to_string(), fingerprint);
+
+        let err = fingerprint
+            .replace("md5", "sha512")
+            .parse::<Fingerprint>()
+            .unwrap_err();
+        assert!(matches!(err, DirHashError::ParseEntry(5, _)));
+    }
+
+    fn assert_parse_error(fingerprint: &str, expected: &str) {
+        let err = fingerprint.parse::<Fingerprint>().unwrap_err();
+        assert_eq!(err.to_string(), expected, "{fingerprint:?}");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_parse_error("", "Line 1: Malformed fingerprint: missing metadata");
+        assert_parse_error(
+            "# {\n# \"version\": \n# }\n",
+            "Line 3: Malformed fingerprint metadata: expected value at line 3 column 1",
+        );
+        assert_parse_error(
+            "# {}\nfoo\n",
+            "Line 2: Malformed fingerprint: expected an empty line after the metadata",
+        );
+        assert_parse_error(
+            "# {}\n\nfoo\n\n",
+            "Line 3: Malformed hashtable entry: missing separator",
+        );
+        assert_parse_error(
+            "# {}\n\n\n",
+            "Line 4: Malformed fingerprint: unexpected end of file, expected the hash",
+        );
+        assert_parse_error(
+            "# {}\n\n",
+            "Line 3: Malformed fingerprint: unexpected end of file, expected the end of the hashtable",
+        );
+        assert_parse_error(
+            "# {}\n\n\nabcd\n",
+            "Line 4: Malformed fingerprint: hash length 2 doesn't match sha256 (32)",
+        );
+        assert_parse_error(
+            "# {}\n\n\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\n\nIgnored:\n",
+            "Line 6: Malformed fingerprint: expected \"Ignored files:\"",
+        );
+        assert_parse_error(
+            "# {}\n\n\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\n\nIgnored files:\n./foo\n",
+            "Line 7: Malformed fingerprint: malformed ignored file (expected \"<path>: <reason>\")",
+        );
+        assert_parse_error(
+            "# {}\n\n\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\n\nIgnored files:\n./foo: Unknown\n",
+            "Line 7: Malformed fingerprint: malformed ignored file (expected \"<path>: <reason>\")",
+        );
+    }
+}
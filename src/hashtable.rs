@@ -1,33 +1,265 @@
-use std::fmt::Display;
+use std::{
+    borrow::Cow,
+    ffi::{OsStr, OsString},
+    fmt::Display,
+    io::BufRead,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::algorithm::HashAlgorithm;
+use crate::error::{DirHashError, Result};
+use crate::escape::{escape_path, unescape_path};
+use crate::metadata::FileMetadata;
+
+/// What a [`HashTableEntry`] was computed from. Everything but regular files is marked by a column
+/// between the hash and the path.
+#[derive(
+    Clone, Copy, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// Contents of a regular file (or the target of a followed symlink)
+    #[default]
+    File,
+    /// Target path of a symlink
+    Symlink,
+    /// Marker of a directory, whose path ends with a `/`. The hash is the digest of no data (i.e.
+    /// the same as for an empty file).
+    Dir,
+}
 
-use crate::error::Result;
+impl EntryKind {
+    const ALL: [EntryKind; 3] = [EntryKind::File, EntryKind::Symlink, EntryKind::Dir];
 
-#[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+    /// Returns the column marking the kind in the hashtable, if any.
+    pub fn marker(&self) -> Option<&'static str> {
+        match self {
+            EntryKind::File => None,
+            EntryKind::Symlink => Some("symlink"),
+            EntryKind::Dir => Some("dir"),
+        }
+    }
+
+    fn from_marker(marker: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|kind| kind.marker() == Some(marker))
+    }
+}
+
+/// Single line of a [`HashTable`].
+///
+/// The path is kept as is, even if it isn't valid UTF-8. In the text format, it's escaped by
+/// [`escape_path()`] if necessary, with a backslash at the start of the line.
+///
+/// Serialized as an object with the hash as hex string, e.g. `{"hash": "e3b0...", "path": "./a",
+/// "kind": "file"}`. Paths that aren't valid UTF-8 are serialized as arrays of bytes. The metadata
+/// is only serialized if set.
+#[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
 pub struct HashTableEntry {
-    hash: [u8; 32],
-    path: String,
+    #[serde(with = "hex::serde")]
+    hash: Vec<u8>,
+    #[serde(with = "os_string")]
+    path: OsString,
+    #[serde(default)]
+    kind: EntryKind,
+    #[serde(default, skip_serializing_if = "FileMetadata::is_empty")]
+    metadata: FileMetadata,
 }
 
 impl HashTableEntry {
+    /// Creates an entry for a hash computed with the default algorithm ([`HashAlgorithm::Sha256`]).
     pub fn new<P, H>(hash: H, path: P) -> Result<Self>
     where
-        P: Into<String>,
+        P: Into<OsString>,
         H: AsRef<[u8]>,
     {
+        Self::for_algorithm(HashAlgorithm::default(), hash, path)
+    }
+
+    /// Creates an entry for a hash computed with `algorithm`. Returns an
+    /// [`DirHashError::HashTableEntry`] if the length of the hash doesn't match the digest length of
+    /// the algorithm.
+    pub fn for_algorithm<P, H>(algorithm: HashAlgorithm, hash: H, path: P) -> Result<Self>
+    where
+        P: Into<OsString>,
+        H: AsRef<[u8]>,
+    {
+        let hash = hash.as_ref();
+        if hash.len() != algorithm.output_len() {
+            return Err(DirHashError::HashTableEntry(hash.len()));
+        }
+
         Ok(Self {
-            hash: hash.as_ref().try_into()?,
+            hash: hash.to_vec(),
             path: path.into(),
+            kind: EntryKind::default(),
+            metadata: FileMetadata::default(),
         })
     }
+
+    /// Marks the entry as computed from something other than file contents.
+    pub fn with_kind(mut self, kind: EntryKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    /// Adds the metadata of the file, which is displayed in additional columns between the hash and
+    /// the path.
+    pub fn with_metadata(mut self, metadata: FileMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn hash(&self) -> &[u8] {
+        &self.hash
+    }
+
+    pub fn path(&self) -> &OsStr {
+        &self.path
+    }
+
+    /// Returns the path as written in the hashtable, see [`escape_path()`].
+    pub fn escaped_path(&self) -> Cow<'_, str> {
+        escape_path(&self.path)
+    }
+
+    pub fn kind(&self) -> EntryKind {
+        self.kind
+    }
+
+    pub fn metadata(&self) -> &FileMetadata {
+        &self.metadata
+    }
+
+    /// Parses a single line in the format of [`Display`] (i.e. `<hex hash>  <path>`, optionally
+    /// with the kind marker and metadata columns before the double space, and a backslash before
+    /// the hash if the path is escaped). `line_number` is only used for the error.
+    pub(crate) fn parse(line: &str, line_number: usize, algorithm: HashAlgorithm) -> Result<Self> {
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+
+        let (columns, path) = line.split_once("  ").ok_or_else(|| {
+            DirHashError::ParseEntry(line_number, String::from("missing separator"))
+        })?;
+
+        let (hash, mut columns) = match columns.split_once(' ') {
+            Some((hash, columns)) => (hash, Some(columns)),
+            None => (columns, None),
+        };
+
+        let mut kind = EntryKind::default();
+        if let Some(rest) = columns {
+            let (marker, metadata) = match rest.split_once(' ') {
+                Some((marker, metadata)) => (marker, Some(metadata)),
+                None => (rest, None),
+            };
+            if let Some(marker_kind) = EntryKind::from_marker(marker) {
+                kind = marker_kind;
+                columns = metadata;
+            }
+        }
+
+        let metadata = match columns {
+            Some(columns) => FileMetadata::parse(columns)
+                .map_err(|e| DirHashError::ParseEntry(line_number, e))?,
+            None => FileMetadata::default(),
+        };
+
+        let hash = hex::decode(hash)
+            .map_err(|e| DirHashError::ParseEntry(line_number, format!("invalid hash: {e}")))?;
+
+        if path.is_empty() {
+            return Err(DirHashError::ParseEntry(
+                line_number,
+                String::from("empty path"),
+            ));
+        }
+
+        let path = match escaped {
+            true => unescape_path(path).ok_or_else(|| {
+                DirHashError::ParseEntry(line_number, String::from("invalid escape sequence"))
+            })?,
+            false => OsString::from(path),
+        };
+
+        let entry = Self::for_algorithm(algorithm, &hash, path).map_err(|_| {
+            DirHashError::ParseEntry(
+                line_number,
+                format!(
+                    "hash length {} doesn't match {algorithm} ({})",
+                    hash.len(),
+                    algorithm.output_len()
+                ),
+            )
+        })?;
+
+        Ok(entry.with_kind(kind).with_metadata(metadata))
+    }
+}
+
+/// Parses an entry whose hash was computed with the default algorithm.
+impl FromStr for HashTableEntry {
+    type Err = DirHashError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s, 1, HashAlgorithm::default())
+    }
 }
 
 impl Display for HashTableEntry {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}  {}", hex::encode(self.hash), self.path)
+        let path = self.escaped_path();
+        if let Cow::Owned(_) = path {
+            write!(f, "\\")?;
+        }
+        write!(f, "{}", hex::encode(&self.hash))?;
+        if let Some(marker) = self.kind.marker() {
+            write!(f, " {marker}")?;
+        }
+        if !self.metadata.is_empty() {
+            write!(f, " {}", self.metadata)?;
+        }
+        write!(f, "  {path}")
     }
 }
 
-#[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+// Serializes paths as strings, or as arrays of bytes if they aren't valid UTF-8.
+mod os_string {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Str(String),
+        Bytes(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(path: &OsString, serializer: S) -> Result<S::Ok, S::Error> {
+        match path.to_str() {
+            Some(path) => serializer.serialize_str(path),
+            None => serializer.collect_seq(path.as_bytes()),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<OsString, D::Error> {
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Str(path) => path.into(),
+            Repr::Bytes(bytes) => OsString::from_vec(bytes),
+        })
+    }
+}
+
+/// List of [`HashTableEntry`]s, serialized as an array.
+#[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct HashTable {
     entries: Vec<HashTableEntry>,
 }
@@ -47,8 +279,42 @@ impl HashTable {
         self.entries.append(entries);
     }
 
+    /// Sorts the entries by their lines as bytes (like `LC_ALL=C sort`), so the hashtable matches
+    /// the sorted output of `sha256sum`, including the lines of escaped paths.
     pub fn sort(&mut self) {
-        self.entries.sort();
+        self.entries.sort_by_cached_key(ToString::to_string);
+    }
+
+    pub fn entries(&self) -> &[HashTableEntry] {
+        self.entries.as_slice()
+    }
+
+    /// Reads a hashtable in the format of [`Display`] (i.e. the output of `sha256sum`) with hashes
+    /// computed by the default algorithm. The entries are kept in the order they were read.
+    pub fn from_reader(reader: impl BufRead) -> Result<Self> {
+        Self::from_reader_with_algorithm(reader, HashAlgorithm::default())
+    }
+
+    /// Same as [`HashTable::from_reader()`], but for hashes computed by `algorithm`.
+    pub fn from_reader_with_algorithm(
+        reader: impl BufRead,
+        algorithm: HashAlgorithm,
+    ) -> Result<Self> {
+        let mut ht = HashTable::new();
+
+        for (i, line) in reader.lines().enumerate() {
+            ht.add(HashTableEntry::parse(&line?, i + 1, algorithm)?);
+        }
+
+        Ok(ht)
+    }
+}
+
+impl FromStr for HashTable {
+    type Err = DirHashError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_reader(s.as_bytes())
     }
 }
 
@@ -97,6 +363,21 @@ mod tests {
         assert!(matches!(err, crate::error::DirHashError::HashTableEntry(_)));
     }
 
+    #[test]
+    fn new_hashtableentry_other_algorithm() {
+        let hte = HashTableEntry::for_algorithm(HashAlgorithm::Sha512, [7; 64], "/some/path")
+            .expect("Can't create HashTableEntry");
+        assert_eq!(hte.hash, [7; 64]);
+        assert_eq!(hte.to_string(), format!("{}  /some/path", "07".repeat(64)));
+
+        let err =
+            HashTableEntry::for_algorithm(HashAlgorithm::Md5, [0; 32], "/some/path").unwrap_err();
+        assert!(matches!(
+            err,
+            crate::error::DirHashError::HashTableEntry(32)
+        ));
+    }
+
     #[test]
     fn new_hashtable() {
         let ht = HashTable::new();
@@ -290,14 +571,15 @@ mod tests {
         assert_eq!(ht.entries[8].path, "B");
         assert_eq!(ht.entries[9].path, "T");
         assert_eq!(ht.entries[10].path, "[brackets]");
-        assert_eq!(ht.entries[11].path, "\\backslash");
-        assert_eq!(ht.entries[12].path, "_underscore");
-        assert_eq!(ht.entries[13].path, "a");
-        assert_eq!(ht.entries[14].path, "d");
-        assert_eq!(ht.entries[15].path, "{braces}");
-        assert_eq!(ht.entries[16].path, "|pipe");
-        assert_eq!(ht.entries[17].path, "~tilde");
-        assert_eq!(ht.entries[18].path, "ä_umlaut");
+        assert_eq!(ht.entries[11].path, "_underscore");
+        assert_eq!(ht.entries[12].path, "a");
+        assert_eq!(ht.entries[13].path, "d");
+        assert_eq!(ht.entries[14].path, "{braces}");
+        assert_eq!(ht.entries[15].path, "|pipe");
+        assert_eq!(ht.entries[16].path, "~tilde");
+        assert_eq!(ht.entries[17].path, "ä_umlaut");
+        // The line of an escaped path starts with a backslash
+        assert_eq!(ht.entries[18].path, "\\backslash");
     }
 
     #[test]
@@ -317,6 +599,164 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_hashtableentry() {
+        let entry: HashTableEntry =
+            "0202020202020202020202020202020202020202020202020202020202020202  /some/path  with spaces"
+                .parse()
+                .expect("Can't parse HashTableEntry");
+        assert_eq!(entry.hash, [2; 32]);
+        assert_eq!(entry.path, "/some/path  with spaces");
+    }
+
+    #[test]
+    fn display_and_parse_hashtableentry_with_metadata() {
+        let entry = HashTableEntry::new([2; 32], "./some  path")
+            .expect("Can't create HashTableEntry")
+            .with_metadata(FileMetadata {
+                mode: Some(0o755),
+                size: Some(12),
+                ..Default::default()
+            });
+        let line = format!("{} mode=0755 size=12  ./some  path", "02".repeat(32));
+        assert_eq!(entry.to_string(), line);
+
+        let parsed: HashTableEntry = line.parse().expect("Can't parse HashTableEntry");
+        assert_eq!(parsed, entry);
+    }
+
+    #[test]
+    fn display_and_parse_hashtableentry_with_kind() {
+        let entry = HashTableEntry::new([3; 32], "./link")
+            .expect("Can't create HashTableEntry")
+            .with_kind(EntryKind::Symlink);
+        let line = format!("{} symlink  ./link", "03".repeat(32));
+        assert_eq!(entry.to_string(), line);
+        assert_eq!(line.parse::<HashTableEntry>().unwrap(), entry);
+
+        let entry = entry.with_metadata(FileMetadata {
+            owner: Some((0, 0)),
+            ..Default::default()
+        });
+        let line = format!("{} symlink owner=0:0  ./link", "03".repeat(32));
+        assert_eq!(entry.to_string(), line);
+        assert_eq!(line.parse::<HashTableEntry>().unwrap(), entry);
+    }
+
+    #[test]
+    fn display_and_parse_dir_marker() {
+        let entry = HashTableEntry::new(HashAlgorithm::Sha256.digest(""), "./var/")
+            .expect("Can't create HashTableEntry")
+            .with_kind(EntryKind::Dir);
+        let line = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 dir  ./var/";
+        assert_eq!(entry.to_string(), line);
+        assert_eq!(line.parse::<HashTableEntry>().unwrap(), entry);
+    }
+
+    #[test]
+    fn display_and_parse_escaped_path() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = OsStr::from_bytes(b"./back\\slash \x80");
+        let entry = HashTableEntry::new([2; 32], path).expect("Can't create HashTableEntry");
+        let line = "\\0202020202020202020202020202020202020202020202020202020202020202  ./back\\\\slash \\x80";
+        assert_eq!(entry.path(), path);
+        assert_eq!(entry.escaped_path(), "./back\\\\slash \\x80");
+        assert_eq!(entry.to_string(), line);
+        assert_eq!(line.parse::<HashTableEntry>().unwrap(), entry);
+
+        let json = serde_json::to_string(&entry).expect("Can't serialize HashTableEntry");
+        assert!(json.contains("\"path\":[46,47,98,97,99,107,92,115,108,97,115,104,32,128]"));
+        assert_eq!(
+            serde_json::from_str::<HashTableEntry>(&json).expect("Can't deserialize entry"),
+            entry
+        );
+    }
+
+    #[test]
+    fn display_and_parse_newline_in_path() {
+        // The name can't inject a second entry
+        let fake = "0303030303030303030303030303030303030303030303030303030303030303  ./fake";
+        let mut ht = HashTable::new();
+        ht.add(HashTableEntry::new([2; 32], format!("./a\n{fake}\r")).unwrap());
+
+        let listing = ht.to_string();
+        assert_eq!(listing.lines().count(), 1);
+        assert!(listing.starts_with('\\'));
+        assert!(listing.ends_with(
+            "  ./a\\n0303030303030303030303030303030303030303030303030303030303030303  ./fake\\r\n"
+        ));
+
+        let parsed: HashTable = listing.parse().expect("Can't parse HashTable");
+        assert_eq!(parsed, ht);
+    }
+
+    #[test]
+    fn parse_hashtableentry_malformed() {
+        for line in [
+            "",
+            "0202020202020202020202020202020202020202020202020202020202020202 /single/space",
+            "020202020202020202020202020202020202020202020202020202020202020  /odd/length",
+            "xx02020202020202020202020202020202020202020202020202020202020202  /not/hex",
+            "02020202020202020202020202020202020202020202020202020202020202  /too/short",
+            "0202020202020202020202020202020202020202020202020202020202020202  ",
+            "0202020202020202020202020202020202020202020202020202020202020202 mode=x  /bad/mode",
+            "0202020202020202020202020202020202020202020202020202020202020202 size=0 symlink  /order",
+            "0202020202020202020202020202020202020202020202020202020202020202 /path  with spaces",
+            "\\0202020202020202020202020202020202020202020202020202020202020202  /bad\\escape",
+        ] {
+            let err = line.parse::<HashTableEntry>().unwrap_err();
+            assert!(
+                matches!(err, DirHashError::ParseEntry(1, _)),
+                "{line:?}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_hashtable() {
+        let listing = "1616161616161616161616161616161616161616161616161616161616161616  /path0\n\
+                       ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff  /path1\n";
+
+        let ht: HashTable = listing.parse().expect("Can't parse HashTable");
+        assert_eq!(ht.entries.len(), 2);
+        assert_eq!(ht.entries[0].path, "/path0");
+        assert_eq!(ht.entries[0].hash, [0x16; 32]);
+        assert_eq!(ht.entries[1].path, "/path1");
+        assert_eq!(ht.entries[1].hash, [0xff; 32]);
+        assert_eq!(ht.to_string(), listing);
+
+        let ht: HashTable = "".parse().expect("Can't parse HashTable");
+        assert!(ht.entries.is_empty());
+    }
+
+    #[test]
+    fn parse_hashtable_with_algorithm() {
+        let listing = "d41d8cd98f00b204e9800998ecf8427e  ./0\n\
+                       d41d8cd98f00b204e9800998ecf8427e  ./1\n";
+
+        let ht = HashTable::from_reader_with_algorithm(listing.as_bytes(), HashAlgorithm::Md5)
+            .expect("Can't parse HashTable");
+        assert_eq!(ht.to_string(), listing);
+
+        let err = HashTable::from_reader(listing.as_bytes()).unwrap_err();
+        assert!(matches!(err, DirHashError::ParseEntry(1, _)));
+    }
+
+    #[test]
+    fn parse_hashtable_reports_line_number() {
+        let listing = "1616161616161616161616161616161616161616161616161616161616161616  /path0\n\
+                       1616161616161616161616161616161616161616161616161616161616161616  /path1\n\
+                       garbage\n";
+
+        let err = listing.parse::<HashTable>().unwrap_err();
+        assert!(matches!(err, DirHashError::ParseEntry(3, _)));
+        assert_eq!(
+            err.to_string(),
+            "Line 3: Malformed hashtable entry: missing separator"
+        );
+    }
+
     #[test]
     fn display_hashtable() {
         let mut ht = HashTable::new();
@@ -337,4 +777,35 @@ mod tests {
              5858585858585858585858585858585858585858585858585858585858585858  /path3\n"
         );
     }
+
+    #[test]
+    fn serde() {
+        let mut ht = HashTable::new();
+        ht.add(HashTableEntry::new([0xab; 32], "./a").unwrap());
+        ht.add(
+            HashTableEntry::new([0; 32], "./b/")
+                .unwrap()
+                .with_kind(EntryKind::Dir)
+                .with_metadata(FileMetadata {
+                    mode: Some(0o755),
+                    owner: Some((1000, 100)),
+                    xattrs: Some(vec![0xcd; 2]),
+                    ..Default::default()
+                }),
+        );
+
+        let json = serde_json::to_string(&ht).expect("Can't serialize HashTable");
+        assert_eq!(
+            json,
+            format!(
+                r#"[{{"hash":"{}","path":"./a","kind":"file"}},{{"hash":"{}","path":"./b/","kind":"dir","metadata":{{"mode":493,"owner":[1000,100],"xattrs":"cdcd"}}}}]"#,
+                "ab".repeat(32),
+                "00".repeat(32)
+            )
+        );
+        assert_eq!(
+            serde_json::from_str::<HashTable>(&json).expect("Can't deserialize HashTable"),
+            ht
+        );
+    }
 }
@@ -7,10 +7,22 @@
 //! `LC_ALL=C fd -a -t f $argv --exec sha256sum | sort | tee /dev/tty | sha256sum`
 pub mod dirhash;
 
+pub mod algorithm;
 pub mod bash;
+pub mod cache;
+pub mod diff;
+pub mod dupes;
 pub mod error;
+pub mod escape;
+pub mod filter;
+pub mod fingerprint;
 pub mod hashtable;
+pub mod manifest;
+pub mod merkle;
+pub mod metadata;
 pub mod pathhash;
+pub mod progress;
+pub mod walk;
 
 #[cfg(any(test, feature = "test-utils"))]
 pub mod test_config;
@@ -3,35 +3,106 @@
 // dh list: list all files
 // dh analyze: analyze file and create a fingerprint
 // dh verify: verify the fingerprint
+// dh tree: print or compare subtree hashes
+// dh check: verify a checksum file (sha256sum -c)
+// dh diff: compare two directories or fingerprints
+// dh dupes: find files with identical contents
 //
 
 use std::{
     env::current_dir,
+    ffi::{OsStr, OsString},
     fmt::Write,
-    fs::{self, File},
-    io::{BufRead, BufReader},
+    fs,
+    io::{self, IsTerminal, Read},
+    os::unix::ffi::{OsStrExt, OsStringExt},
     path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use dirhash_rs::{
-    dirhash::{DirHash, IgnoreReason},
-    pathhash::PathHashProvider,
+    algorithm::HashAlgorithm,
+    cache::{CachedPathHash, HashCache},
+    diff::HashTableDiff,
+    dirhash::{DirHash, IgnoreReason, Strategy},
+    dupes::{find_duplicates, size_collisions},
+    error::{DirHashError, Result},
+    escape::{escape_path, line_marker, unescape_path},
+    filter::FilterOptions,
+    fingerprint::Fingerprint,
+    hashtable::{EntryKind, HashTable, HashTableEntry},
+    manifest::{CheckStatus, Manifest, ManifestFormat},
+    merkle::MerkleTree,
+    metadata::MetadataOptions,
+    pathhash::{PathHash, PathHashProvider, ReadOptions},
+    progress::Progress,
+    walk::{DirectoryMode, SymlinkMode, WalkConfig},
 };
-use pathdiff::diff_paths;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use tracing::{debug, info};
 
-#[derive(Debug, Args, Clone, Serialize, Deserialize)]
-struct WalkOptions {
-    /// Use absolute paths (instead of relative)
-    #[arg(short, long)]
-    absolute: bool,
+/// Exit code on success.
+const EXIT_OK: i32 = 0;
+/// Exit code of `verify`, `tree --compare`, `diff` and `check` if the fingerprint, the trees or
+/// the checksums don't match.
+const EXIT_MISMATCH: i32 = 1;
+/// Exit code for invalid command line arguments (as used by clap).
+const EXIT_USAGE: i32 = 2;
+/// Exit code if reading or writing a file or directory failed.
+const EXIT_IO: i32 = 3;
+/// Exit code for malformed or unsupported fingerprint files.
+const EXIT_MALFORMED: i32 = 4;
+
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  Success
+  1  Fingerprint, trees or checksums don't match
+  2  Usage error
+  3  I/O error
+  4  Malformed fingerprint or checksum file";
+
+/// Version of the JSON output schema, increased on incompatible changes.
+const JSON_SCHEMA_VERSION: u32 = 1;
+
+fn exit_code(e: &DirHashError) -> i32 {
+    match e {
+        DirHashError::NotADirectory(_)
+        | DirHashError::NotAFile(_)
+        | DirHashError::OutsideRoot(_)
+        | DirHashError::ConflictingOptions(_)
+        | DirHashError::UnknownAlgorithm(_)
+        | DirHashError::UnknownManifestFormat(_) => EXIT_USAGE,
+        DirHashError::Filter(e) if !e.is_io() => EXIT_USAGE,
+        DirHashError::HashTableEntry(_)
+        | DirHashError::ParseEntry(..)
+        | DirHashError::ParseMetadata(..)
+        | DirHashError::ParseFingerprint(..)
+        | DirHashError::ParseManifest(..)
+        | DirHashError::UnsupportedVersion(_)
+        | DirHashError::UnknownIgnoreReason(_)
+        | DirHashError::MerkleTree(_) => EXIT_MALFORMED,
+        _ => EXIT_IO,
+    }
+}
 
+// The defaults are the values without any flag.
+#[derive(Debug, Default, Args, Clone)]
+struct WalkArgs {
     /// Follow symbolic links
     #[arg(short = 'L', long = "follow")]
     follow_symlinks: bool,
 
+    /// Hash symbolic links as links (i.e. their target path) instead of ignoring them
+    #[arg(long = "hash-links", conflicts_with = "follow_symlinks")]
+    hash_symlinks: bool,
+
     /// Include hidden files
     #[arg(short = 'H', long = "hidden")]
     include_hidden_files: bool,
@@ -39,23 +110,758 @@ struct WalkOptions {
     /// Ignore invalid filetypes
     #[arg(short = 'I', long = "ignore_invalid")]
     ignore_invalid_filetypes: bool,
+
+    /// Add a marker entry for each empty directory
+    #[arg(long)]
+    empty_dirs: bool,
+
+    /// Add a marker entry for each directory
+    #[arg(long, conflicts_with = "empty_dirs")]
+    all_dirs: bool,
+
+    /// Only include entries at least N levels below the directory (1 for its direct children)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    min_depth: usize,
+
+    /// Don't descend more than N levels below the directory
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Don't descend into directories on other file systems
+    #[arg(short = 'x', long)]
+    one_file_system: bool,
+
+    /// Skip files smaller than SIZE bytes (e.g. 4K)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// Skip files larger than SIZE bytes (e.g. 100M)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Skip files modified before TIME (seconds since the epoch, or an age like 2d)
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    newer: Option<i64>,
+
+    /// Skip files modified at or after TIME (seconds since the epoch, or an age like 2d)
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    older: Option<i64>,
+
+    /// Exclude paths matching the gitignore-style pattern (can be repeated)
+    #[arg(short = 'e', long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+
+    /// Only include files matching the gitignore-style pattern (can be repeated)
+    #[arg(short = 'i', long, value_name = "PATTERN")]
+    include: Vec<String>,
+
+    /// Respect .gitignore, .ignore and .dirhashignore files
+    #[arg(long)]
+    ignore_files: bool,
+}
+
+impl From<WalkArgs> for WalkConfig {
+    fn from(args: WalkArgs) -> Self {
+        WalkConfig::new()
+            .with_symlinks(match (args.follow_symlinks, args.hash_symlinks) {
+                (true, _) => SymlinkMode::Follow,
+                (false, true) => SymlinkMode::Hash,
+                (false, false) => SymlinkMode::Ignore,
+            })
+            .with_include_hidden_files(args.include_hidden_files)
+            .with_ignore_invalid_filetypes(args.ignore_invalid_filetypes)
+            .with_directories(match (args.empty_dirs, args.all_dirs) {
+                (_, true) => DirectoryMode::All,
+                (true, false) => DirectoryMode::Empty,
+                (false, false) => DirectoryMode::None,
+            })
+            .with_min_depth(args.min_depth)
+            .with_max_depth(args.max_depth)
+            .with_one_file_system(args.one_file_system)
+            .with_min_size(args.min_size)
+            .with_max_size(args.max_size)
+            .with_newer(args.newer)
+            .with_older(args.older)
+            .with_filter(FilterOptions {
+                exclude: args.exclude,
+                include: args.include,
+                ignore_files: args.ignore_files,
+            })
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+struct FilesFromArgs {
+    /// Use the paths listed in FILE (- for stdin, one per line) instead of walking the directory.
+    /// Relative paths are relative to the directory
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// The paths of --files-from are separated by NUL bytes (like find -print0)
+    #[arg(short = '0', long, requires = "files_from")]
+    null: bool,
+}
+
+impl FilesFromArgs {
+    // Reads the listed paths, if any. Empty lines are skipped.
+    fn read(self, cwd: &Path) -> Result<Option<Vec<PathBuf>>> {
+        let Some(source) = self.files_from else {
+            return Ok(None);
+        };
+
+        let list = match source.as_os_str() == "-" {
+            true => {
+                let mut list = vec![];
+                io::stdin().lock().read_to_end(&mut list)?;
+                list
+            }
+            false => {
+                let path = cwd.join(source);
+                fs::read(&path).map_err(|e| DirHashError::PathIo(path, e))?
+            }
+        };
+
+        let separator = match self.null {
+            true => b'\0',
+            false => b'\n',
+        };
+        let paths = list
+            .split(|&byte| byte == separator)
+            .filter(|path| !path.is_empty())
+            .map(|path| PathBuf::from(OsString::from_vec(path.to_vec())))
+            .collect();
+        Ok(Some(paths))
+    }
+}
+
+#[derive(Debug, Default, Args, Clone)]
+struct MetadataArgs {
+    /// Include the permission bits in the hashtable
+    #[arg(long)]
+    mode: bool,
+
+    /// Include the numeric user and group ID in the hashtable
+    #[arg(long)]
+    owner: bool,
+
+    /// Include the modification time in the hashtable
+    #[arg(long)]
+    mtime: bool,
+
+    /// Include the file size in the hashtable
+    #[arg(long)]
+    size: bool,
+
+    /// Include the extended attributes in the hashtable
+    #[arg(long)]
+    xattrs: bool,
+}
+
+impl From<MetadataArgs> for MetadataOptions {
+    fn from(args: MetadataArgs) -> Self {
+        MetadataOptions {
+            mode: args.mode,
+            owner: args.owner,
+            mtime: args.mtime,
+            size: args.size,
+            xattrs: args.xattrs,
+        }
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+struct ReadArgs {
+    /// Size of the buffer used for streaming file contents (e.g. 64K, 1M)
+    #[arg(long, value_parser = parse_size, default_value = "64K")]
+    buffer_size: u64,
+
+    /// Memory-map files of at least this size instead of streaming them (e.g. 256M)
+    #[arg(long, value_parser = parse_size)]
+    mmap_threshold: Option<u64>,
+
+    /// Number of files hashed in parallel (1: one after the other, 0: one per CPU)
+    #[arg(short, long, value_name = "N", default_value_t = 0)]
+    jobs: usize,
+}
+
+impl ReadArgs {
+    // Returns the number of threads to hash with, resolving 0 to the number of CPUs.
+    fn threads(&self) -> usize {
+        match self.jobs {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            jobs => jobs,
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl From<ReadArgs> for ReadOptions {
+    fn from(args: ReadArgs) -> Self {
+        ReadOptions {
+            buffer_size: args.buffer_size.try_into().unwrap_or(usize::MAX),
+            mmap_threshold: args.mmap_threshold,
+        }
+    }
+}
+
+#[derive(Debug, Args, Clone)]
+struct CacheArgs {
+    /// Cache file digests by inode, size and timestamps to skip rehashing unchanged files
+    /// (default: $XDG_CACHE_HOME/dirhash/hashes)
+    #[arg(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+    cache: Option<Option<PathBuf>>,
+
+    /// Don't use the cache, even if --cache is given
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Rehash all files, but still update the cache
+    #[arg(long)]
+    rehash: bool,
+}
+
+impl CacheArgs {
+    fn open(self) -> Result<HashCache> {
+        match (self.no_cache, self.cache) {
+            (false, Some(path)) => {
+                let path = path.or_else(default_cache_path).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "can't determine cache directory, use --cache=PATH",
+                    )
+                })?;
+                Ok(HashCache::open(path)?.with_rehash(self.rehash))
+            }
+            _ => Ok(HashCache::new()),
+        }
+    }
+}
+
+fn default_cache_path() -> Option<PathBuf> {
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_dir.join("dirhash").join("hashes"))
+}
+
+/// Parses a size in bytes with an optional binary suffix (K, M, G, T).
+fn parse_size(s: &str) -> std::result::Result<u64, String> {
+    let (digits, factor) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&s[..i], 1 << 30),
+        Some((i, 'T' | 't')) => (&s[..i], 1 << 40),
+        _ => (s, 1),
+    };
+
+    digits
+        .parse::<u64>()
+        .map_err(|e| format!("invalid size \"{s}\": {e}"))?
+        .checked_mul(factor)
+        .ok_or_else(|| format!("size \"{s}\" is too large"))
+}
+
+/// Parses a point in time as seconds since the Unix epoch, or as an age relative to now with a
+/// suffix (s, m, h, d, w), e.g. `2d` for two days ago.
+fn parse_time(s: &str) -> std::result::Result<i64, String> {
+    let invalid = |e| format!("invalid time \"{s}\": {e}");
+    let (digits, unit) = match s.char_indices().last() {
+        Some((i, 's')) => (&s[..i], 1),
+        Some((i, 'm')) => (&s[..i], 60),
+        Some((i, 'h')) => (&s[..i], 60 * 60),
+        Some((i, 'd')) => (&s[..i], 24 * 60 * 60),
+        Some((i, 'w')) => (&s[..i], 7 * 24 * 60 * 60),
+        _ => return s.parse::<i64>().map_err(invalid),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| format!("invalid system time: {e}"))?
+        .as_secs();
+    digits
+        .parse::<i64>()
+        .map_err(invalid)?
+        .checked_mul(unit)
+        .and_then(|age| (now as i64).checked_sub(age))
+        .ok_or_else(|| format!("time \"{s}\" is out of range"))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 struct FingerprintMetadata {
     version: u8,
+    // Like in the JSON output, a path that isn't valid UTF-8 is written as an array of bytes.
+    #[serde(with = "path_serde")]
     path: PathBuf,
+    // Omitted for the default, so fingerprints created before the algorithm was selectable stay
+    // valid (and byte-identical).
+    #[serde(default, skip_serializing_if = "is_default_algorithm")]
+    algorithm: HashAlgorithm,
+    #[serde(flatten)]
+    walk: WalkConfig,
+    #[serde(default, skip_serializing_if = "MetadataOptions::is_empty")]
+    file_metadata: MetadataOptions,
+    // Set if the files were listed (--files-from) instead of found by walking the path, so
+    // verify checks the paths of the fingerprint again instead of walking.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    listed: bool,
+    #[serde(skip)]
+    files: Option<Vec<PathBuf>>,
+}
+
+impl FingerprintMetadata {
+    fn new(
+        path: PathBuf,
+        algorithm: HashAlgorithm,
+        walk: WalkConfig,
+        file_metadata: MetadataOptions,
+    ) -> Self {
+        Self {
+            version: 1,
+            path,
+            algorithm,
+            walk,
+            file_metadata,
+            listed: false,
+            files: None,
+        }
+    }
+
+    fn with_files(mut self, files: Option<Vec<PathBuf>>) -> Self {
+        self.listed = files.is_some();
+        self.files = files;
+        self
+    }
+
+    // Adds the listed files to `dh`, or the files found by walking the path.
+    fn add_files(&self, dh: DirHash<PathHash>) -> Result<DirHash<PathHash>> {
+        match &self.files {
+            Some(files) => dh.with_files_from_list(&self.path, files, &self.walk),
+            None => dh.with_files_from_dir(&self.path, &self.walk),
+        }
+    }
+}
+
+// (De)serializes a path losslessly as printed by `path_json()`: a string, or its bytes if it isn't
+// valid UTF-8.
+mod path_serde {
+    use std::{
+        ffi::OsString,
+        os::unix::ffi::OsStringExt,
+        path::{Path, PathBuf},
+    };
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawPath {
+        Text(String),
+        Bytes(Vec<u8>),
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        super::path_json(path.as_os_str()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        Ok(match RawPath::deserialize(deserializer)? {
+            RawPath::Text(path) => PathBuf::from(path),
+            RawPath::Bytes(bytes) => PathBuf::from(OsString::from_vec(bytes)),
+        })
+    }
+}
+
+fn is_default_algorithm(algorithm: &HashAlgorithm) -> bool {
+    *algorithm == HashAlgorithm::default()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// A single JSON object
+    Json,
+    /// One JSON object per line, starting with a header and ending with the result
+    Jsonl,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum ProgressMode {
+    /// Only if stderr is a terminal
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ProgressMode {
+    fn display(self) -> Option<Arc<ProgressDisplay>> {
+        let enabled = match self {
+            ProgressMode::Auto => io::stderr().is_terminal(),
+            ProgressMode::Always => true,
+            ProgressMode::Never => false,
+        };
+        enabled.then(|| Arc::new(ProgressDisplay::default()))
+    }
+}
+
+/// Minimum time between two redraws of the progress line.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Single line on stderr, redrawn in place with the number of files found while walking, then
+/// with the files and bytes hashed, the throughput and the estimated time left.
+#[derive(Debug, Default)]
+struct ProgressDisplay {
+    found: AtomicUsize,
+    files: AtomicUsize,
+    total_files: AtomicUsize,
+    bytes: AtomicU64,
+    total_bytes: AtomicU64,
+    state: Mutex<ProgressState>,
+}
+
+#[derive(Debug, Default)]
+struct ProgressState {
+    // Start of hashing, `None` while walking.
+    started: Option<Instant>,
+    last_draw: Option<Instant>,
+}
+
+impl ProgressDisplay {
+    // Redraws the line, unless `force` isn't set and it was drawn recently.
+    fn draw(&self, force: bool) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if !force
+            && state
+                .last_draw
+                .is_some_and(|last| now - last < PROGRESS_INTERVAL)
+        {
+            return;
+        }
+        state.last_draw = Some(now);
+        eprint!("\r\x1b[2K{}", self.line(state.started));
+    }
+
+    fn line(&self, started: Option<Instant>) -> String {
+        let Some(started) = started else {
+            return format!("Scanning: {} files", self.found.load(Ordering::Relaxed));
+        };
+
+        let files = self.files.load(Ordering::Relaxed);
+        let total_files = self.total_files.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let total_bytes = self.total_bytes.load(Ordering::Relaxed);
+        let elapsed = started.elapsed().as_secs_f64();
+        let rate = match elapsed > 0.0 {
+            true => bytes as f64 / elapsed,
+            false => 0.0,
+        };
+
+        // Hashing while walking, so there are no totals to estimate the time left.
+        if total_files == 0 {
+            return format!(
+                "Hashing: {files}/{} files found, {} ({}/s)",
+                self.found.load(Ordering::Relaxed),
+                human_bytes(bytes),
+                human_bytes(rate as u64),
+            );
+        }
+        let eta = match rate > 0.0 {
+            true => human_duration((total_bytes.saturating_sub(bytes) as f64 / rate) as u64),
+            false => "--".to_owned(),
+        };
+
+        format!(
+            "Hashing: {files}/{total_files} files, {} / {} ({}/s), ETA {eta}",
+            human_bytes(bytes),
+            human_bytes(total_bytes),
+            human_bytes(rate as u64),
+        )
+    }
+
+    // Removes the line, if it was drawn.
+    fn clear(&self) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if state.last_draw.take().is_some() {
+            eprint!("\r\x1b[2K");
+        }
+        state.started = None;
+    }
+}
+
+impl Progress for ProgressDisplay {
+    fn file_found(&self, _path: &Path) {
+        self.found.fetch_add(1, Ordering::Relaxed);
+        self.draw(false);
+    }
+
+    fn hashing_started(&self, files: usize, bytes: u64) {
+        self.files.store(0, Ordering::Relaxed);
+        self.bytes.store(0, Ordering::Relaxed);
+        self.total_files.store(files, Ordering::Relaxed);
+        self.total_bytes.store(bytes, Ordering::Relaxed);
+        // Commands walking a second directory start counting again.
+        self.found.store(0, Ordering::Relaxed);
+        if let Ok(mut state) = self.state.lock() {
+            state.started = Some(Instant::now());
+        }
+        self.draw(true);
+    }
+
+    fn file_hashed(&self, _path: &Path, bytes: u64) {
+        self.files.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.draw(false);
+    }
+
+    fn hashing_finished(&self) {
+        self.clear();
+    }
+}
+
+impl Drop for ProgressDisplay {
+    // Errors abort hashing without `hashing_finished()`, but their message needs its own line.
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// Formats a number of bytes with a binary unit (e.g. 1.5 MiB).
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Formats a number of seconds like 42s, 3m05s or 2h10m.
+fn human_duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs / 60 % 60),
+    }
+}
+
+/// How the commands hashing files read, cache and report them.
+struct Hashing {
+    read_options: ReadOptions,
+    threads: usize,
+    cache: HashCache,
+    progress: Option<Arc<ProgressDisplay>>,
+}
+
+impl Hashing {
+    fn new(
+        read: ReadArgs,
+        cache: CacheArgs,
+        progress: &Option<Arc<ProgressDisplay>>,
+    ) -> Result<Self> {
+        Ok(Hashing {
+            threads: read.threads(),
+            read_options: read.into(),
+            cache: cache.open()?,
+            progress: progress.clone(),
+        })
+    }
+
+    // Returns a DirHash configured like `meta`, without any files yet.
+    fn dirhash(&self, meta: &FingerprintMetadata) -> DirHash<PathHash> {
+        let strategy = match self.threads {
+            1 => Strategy::Serial,
+            _ => Strategy::Rayon1,
+        };
+        let dh = DirHash::new()
+            .with_algorithm(meta.algorithm)
+            .with_read_options(self.read_options)
+            .with_metadata_options(meta.file_metadata)
+            .with_strategy(strategy)
+            .with_threads(self.threads);
+        match &self.progress {
+            Some(progress) => dh.with_progress(progress.clone()),
+            None => dh,
+        }
+    }
+}
+
+/// Machine-readable output of a command.
+///
+/// With `--format json`, it's printed as a single object containing the fields and lists. With
+/// `--format jsonl`, a `header` record is followed by one record per list item (with the `type` of
+/// the list) and a `result` record containing the fields.
+///
+/// Values that can't be serialized are reported by [`JsonOutput::print()`].
+struct JsonOutput {
+    command: &'static str,
+    fields: serde_json::Map<String, Value>,
+    // (name, record type, items)
+    lists: Vec<(&'static str, &'static str, Vec<Value>)>,
+    // First value that couldn't be serialized.
+    error: Option<DirHashError>,
+}
+
+impl JsonOutput {
+    fn new(command: &'static str) -> Self {
+        Self {
+            command,
+            fields: serde_json::Map::new(),
+            lists: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn field(mut self, name: &str, value: impl Serialize) -> Self {
+        match to_json(value) {
+            Ok(value) => {
+                self.fields.insert(name.to_owned(), value);
+            }
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+        self
+    }
+
+    fn list<T: Serialize>(
+        mut self,
+        name: &'static str,
+        record_type: &'static str,
+        items: impl IntoIterator<Item = T>,
+    ) -> Self {
+        match items.into_iter().map(to_json).collect() {
+            Ok(items) => self.lists.push((name, record_type, items)),
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+        self
+    }
+
+    fn print(self, format: OutputFormat) -> Result<()> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        let header = json!({
+            "schema_version": JSON_SCHEMA_VERSION,
+            "command": self.command,
+        });
+
+        match format {
+            OutputFormat::Text => unreachable!("text output isn't JSON"),
+            OutputFormat::Json => {
+                let Value::Object(mut document) = header else {
+                    unreachable!()
+                };
+                document.extend(self.fields);
+                for (name, _, items) in self.lists {
+                    document.insert(name.to_owned(), Value::Array(items));
+                }
+                println!("{}", Value::Object(document));
+            }
+            OutputFormat::Jsonl => {
+                println!("{}", json!({"type": "header"}).merged(header));
+                for (_, record_type, items) in self.lists {
+                    for item in items {
+                        println!("{}", json!({ "type": record_type }).merged(item));
+                    }
+                }
+                println!(
+                    "{}",
+                    json!({"type": "result"}).merged(Value::Object(self.fields))
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_json(value: impl Serialize) -> Result<Value> {
+    serde_json::to_value(value).map_err(DirHashError::SerializeOutput)
+}
+
+trait MergeJson {
+    // Adds the fields of the `other` object.
+    fn merged(self, other: Value) -> Value;
+}
+
+impl MergeJson for Value {
+    fn merged(mut self, other: Value) -> Value {
+        if let (Value::Object(object), Value::Object(other)) = (&mut self, other) {
+            object.extend(other);
+        }
+        self
+    }
+}
+
+// Ignored path (relative, if not absolute) with its reason, as printed in JSON.
+#[derive(Serialize)]
+struct IgnoredJson<'a> {
+    path: Value,
     #[serde(flatten)]
-    walk: WalkOptions,
+    reason: &'a IgnoreReason,
+}
+
+// Path as printed in JSON: a string, or an array of bytes if it isn't valid UTF-8 (like the paths
+// of hashtable entries).
+fn path_json(path: &OsStr) -> Value {
+    match path.to_str() {
+        Some(path) => Value::from(path),
+        None => Value::from(path.as_bytes()),
+    }
+}
+
+// Path escaped like in the hashtable (e.g. of an ignored file or a subtree), as printed in JSON
+// by `path_json()`. Invalid escape sequences are kept as they are.
+fn escaped_path_json(escaped: &str) -> Value {
+    match unescape_path(escaped) {
+        Some(path) => path_json(&path),
+        None => Value::from(escaped),
+    }
+}
+
+// Path escaped like in the hashtable, with the marker of a line it starts (see `line_marker()`).
+fn marked(escaped: &str) -> String {
+    format!("{}{escaped}", line_marker(escaped))
+}
+
+// Pair of hashtable entries, as printed in JSON.
+#[derive(Serialize)]
+struct ChangedJson<'a> {
+    old: &'a HashTableEntry,
+    new: &'a HashTableEntry,
 }
 
 #[derive(Debug, Parser)]
 #[command(name = "DirHash")]
 #[command(version)]
+#[command(after_help = EXIT_CODES_HELP)]
 #[command(about = "Compute a fingerprint over all files in a directory recursively", long_about = None)]
 struct DirhashCli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Output format
+    #[arg(long, global = true, value_enum, default_value_t)]
+    format: OutputFormat,
+
+    /// Show the progress of hashing on stderr (auto: if stderr is a terminal)
+    #[arg(long, global = true, value_enum, default_value_t)]
+    progress: ProgressMode,
 }
 
 #[derive(Debug, Subcommand)]
@@ -64,8 +870,13 @@ enum Commands {
     List {
         /// Path to list files from (default: cwd)
         path: Option<PathBuf>,
+        /// Use absolute paths (instead of relative)
+        #[arg(short, long)]
+        absolute: bool,
         #[command(flatten)]
-        walk: WalkOptions,
+        walk: WalkArgs,
+        #[command(flatten)]
+        files_from: FilesFromArgs,
         /// Display the type of the listed files
         #[arg(short = 't', long = "type")]
         display_type: bool,
@@ -79,33 +890,150 @@ enum Commands {
     Analyze {
         /// Path to analyze (default: cwd)
         path: Option<PathBuf>,
+        /// Use absolute paths (instead of relative)
+        #[arg(short, long)]
+        absolute: bool,
         #[command(flatten)]
-        walk: WalkOptions,
+        walk: WalkArgs,
+        #[command(flatten)]
+        files_from: FilesFromArgs,
         /// Path to fingerprint file
         #[arg(short, long)]
         fingerprint: Option<PathBuf>,
+        /// Also write the hashes of the files to a checksum file, as created by sha256sum
+        #[arg(short, long)]
+        manifest: Option<PathBuf>,
+        /// Format of the checksum file (gnu, bsd)
+        #[arg(long, default_value_t = ManifestFormat::Gnu, requires = "manifest")]
+        manifest_format: ManifestFormat,
+        /// Hash algorithm (sha256, sha512, sha1, md5, blake2b, blake3)
+        #[arg(long, default_value_t = HashAlgorithm::Sha256)]
+        algorithm: HashAlgorithm,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+        #[command(flatten)]
+        read: ReadArgs,
+        #[command(flatten)]
+        cache: CacheArgs,
     },
     /// Verify the fingerprint of files recursively
     Verify {
         /// Path to fingerprint file
         fingerprint: PathBuf,
+        #[command(flatten)]
+        read: ReadArgs,
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+    /// Print the subtree hash of each directory (Merkle tree) or compare two trees
+    Tree {
+        /// Path to analyze (default: cwd)
+        path: Option<PathBuf>,
+        #[command(flatten)]
+        walk: WalkArgs,
+        #[command(flatten)]
+        files_from: FilesFromArgs,
+        /// Compare with another directory, descending only into differing subtrees
+        #[arg(short, long)]
+        compare: Option<PathBuf>,
+        /// Hash algorithm (sha256, sha512, sha1, md5, blake2b, blake3)
+        #[arg(long, default_value_t = HashAlgorithm::Sha256)]
+        algorithm: HashAlgorithm,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+        #[command(flatten)]
+        read: ReadArgs,
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+    /// Compare two directories or fingerprint files
+    ///
+    /// If a side is a fingerprint file, its walk, algorithm and metadata options (and its list of
+    /// files, if created with --files-from) are used for the other side. Other options than these
+    /// are rejected, and so are fingerprints created with different algorithms.
+    Diff {
+        /// Old directory or fingerprint file
+        old: PathBuf,
+        /// New directory or fingerprint file
+        new: PathBuf,
+        /// Use absolute paths (instead of relative)
+        #[arg(short, long)]
+        absolute: bool,
+        #[command(flatten)]
+        walk: WalkArgs,
+        /// Hash algorithm (sha256, sha512, sha1, md5, blake2b, blake3)
+        #[arg(long, default_value_t = HashAlgorithm::Sha256)]
+        algorithm: HashAlgorithm,
+        #[command(flatten)]
+        metadata: MetadataArgs,
+        #[command(flatten)]
+        read: ReadArgs,
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+    /// Find files with identical contents
+    ///
+    /// Empty files are skipped unless --min-size 0 is given.
+    Dupes {
+        /// Path to search (default: cwd)
+        path: Option<PathBuf>,
+        /// Use absolute paths (instead of relative)
+        #[arg(short, long)]
+        absolute: bool,
+        #[command(flatten)]
+        walk: WalkArgs,
+        #[command(flatten)]
+        files_from: FilesFromArgs,
+        /// Only hash files whose size equals the size of another file
+        #[arg(long)]
+        by_size: bool,
+        /// Hash algorithm (sha256, sha512, sha1, md5, blake2b, blake3)
+        #[arg(long, default_value_t = HashAlgorithm::Sha256)]
+        algorithm: HashAlgorithm,
+        #[command(flatten)]
+        read: ReadArgs,
+        #[command(flatten)]
+        cache: CacheArgs,
+    },
+    /// Verify the files listed in a checksum file (as created by sha256sum, with or without --tag)
+    #[command(mut_group("WalkArgs", |group| group.requires("unlisted")))]
+    Check {
+        /// Path to checksum file
+        manifest: PathBuf,
+        /// Directory the listed paths are relative to (default: cwd)
+        #[arg(short, long)]
+        root: Option<PathBuf>,
+        /// Hash algorithm of lines without a tag (sha256, sha512, sha1, md5, blake2b, blake3)
+        #[arg(long, default_value_t = HashAlgorithm::Sha256)]
+        algorithm: HashAlgorithm,
+        /// Also report files in the root that aren't listed in the checksum file, found with the
+        /// walk options
+        #[arg(short, long)]
+        unlisted: bool,
+        #[command(flatten)]
+        walk: WalkArgs,
+        /// Don't print a line for each matching file
+        #[arg(short, long)]
+        quiet: bool,
+        #[command(flatten)]
+        read: ReadArgs,
     },
 }
 
-fn parse_user_path(cwd: &Path, user_path: Option<PathBuf>) -> PathBuf {
+fn parse_user_path(cwd: &Path, user_path: Option<PathBuf>) -> Result<PathBuf> {
     info!("path param: {:?}", &user_path);
     let path = cwd.join(user_path.unwrap_or(PathBuf::from(".")));
     debug!("path before canonicalize: {:?}", &path);
     let canon_path = path.canonicalize();
     info!("canon path: {:?}", canon_path);
 
-    let canon_path = canon_path.expect("Supplied path doesn't exist");
+    let canon_path = canon_path.map_err(|e| DirHashError::PathIo(path, e))?;
 
     if !canon_path.is_dir() {
-        panic!("Supplied path is not a directory");
+        return Err(DirHashError::NotADirectory(canon_path));
     }
 
-    canon_path
+    Ok(canon_path)
 }
 
 fn main() {
@@ -115,90 +1043,234 @@ fn main() {
     //     .with_target(false)
     //     .init();
 
-    let cwd = current_dir().expect("Can't get current working directory");
+    // Panics are bugs, but users should still get a plain message instead of a backtrace hint.
+    std::panic::set_hook(Box::new(|info| eprintln!("Internal error: {info}")));
 
     let args = DirhashCli::parse();
 
     debug!("parsed args: {:?}", args);
 
+    let code = run(args).unwrap_or_else(|e| {
+        eprintln!("Error: {e}");
+        exit_code(&e)
+    });
+
+    process::exit(code);
+}
+
+// Runs the command and returns the exit code (errors excluded).
+fn run(args: DirhashCli) -> Result<i32> {
+    let cwd = current_dir()?;
+    let format = args.format;
+    let progress = args.progress.display();
+
     match args.command {
         Commands::List {
             path,
+            absolute,
             walk,
+            files_from,
             display_type,
         } => {
-            let path = parse_user_path(&cwd, path);
-            list_files(path, display_type, walk);
+            let path = parse_user_path(&cwd, path)?;
+            let meta = FingerprintMetadata::new(
+                path,
+                HashAlgorithm::default(),
+                WalkConfig::from(walk).with_absolute(absolute),
+                MetadataOptions::default(),
+            )
+            .with_files(files_from.read(&cwd)?);
+            list_files(meta, display_type, format)
         }
         Commands::Summary { path } => {
-            let path = parse_user_path(&cwd, path);
-            summary(path);
+            let path = parse_user_path(&cwd, path)?;
+            summary(path, format)
         }
         Commands::Analyze {
             path,
+            absolute,
             walk,
+            files_from,
             fingerprint,
+            manifest,
+            manifest_format,
+            algorithm,
+            metadata,
+            read,
+            cache,
+        } => {
+            let path = parse_user_path(&cwd, path)?;
+            analyze_files(
+                FingerprintMetadata::new(
+                    path,
+                    algorithm,
+                    WalkConfig::from(walk).with_absolute(absolute),
+                    metadata.into(),
+                )
+                .with_files(files_from.read(&cwd)?),
+                fingerprint,
+                manifest.map(|manifest| (manifest, manifest_format)),
+                Hashing::new(read, cache, &progress)?,
+                format,
+            )
+        }
+        Commands::Verify {
+            fingerprint,
+            read,
+            cache,
+        } => verify_files(fingerprint, Hashing::new(read, cache, &progress)?, format),
+        Commands::Tree {
+            path,
+            walk,
+            files_from,
+            compare,
+            algorithm,
+            metadata,
+            read,
+            cache,
+        } => {
+            let path = parse_user_path(&cwd, path)?;
+            let compare = compare
+                .map(|compare| parse_user_path(&cwd, Some(compare)))
+                .transpose()?;
+            merkle_tree(
+                FingerprintMetadata::new(path, algorithm, walk.into(), metadata.into())
+                    .with_files(files_from.read(&cwd)?),
+                compare,
+                Hashing::new(read, cache, &progress)?,
+                format,
+            )
+        }
+        Commands::Diff {
+            old,
+            new,
+            absolute,
+            walk,
+            algorithm,
+            metadata,
+            read,
+            cache,
         } => {
-            let path = parse_user_path(&cwd, path);
-            analyze_files(path, fingerprint, walk);
+            let old = DiffSide::new(&cwd, old)?;
+            let new = DiffSide::new(&cwd, new)?;
+            // The path is replaced by the directory of each side.
+            let settings = FingerprintMetadata::new(
+                cwd.clone(),
+                algorithm,
+                WalkConfig::from(walk).with_absolute(absolute),
+                metadata.into(),
+            );
+            let hashing = Hashing::new(read, cache, &progress)?;
+            diff_trees(old, new, settings, hashing, format)
         }
-        Commands::Verify { fingerprint } => {
-            verify_files(fingerprint);
+        Commands::Dupes {
+            path,
+            absolute,
+            walk,
+            files_from,
+            by_size,
+            algorithm,
+            read,
+            cache,
+        } => {
+            let path = parse_user_path(&cwd, path)?;
+            let min_size = walk.min_size.unwrap_or(1);
+            let meta = FingerprintMetadata::new(
+                path,
+                algorithm,
+                WalkConfig::from(walk).with_absolute(absolute),
+                MetadataOptions {
+                    size: true,
+                    ..Default::default()
+                },
+            )
+            .with_files(files_from.read(&cwd)?);
+            let hashing = Hashing::new(read, cache, &progress)?;
+            find_dupes(meta, min_size, by_size, hashing, format)
+        }
+        Commands::Check {
+            manifest,
+            root,
+            algorithm,
+            unlisted,
+            walk,
+            quiet,
+            read,
+        } => {
+            let root = parse_user_path(&cwd, root)?;
+            let walk = unlisted.then(|| walk.into());
+            check_manifest(
+                &cwd.join(manifest),
+                root,
+                algorithm,
+                walk,
+                quiet,
+                read,
+                format,
+            )
         }
     }
 }
 
-fn list_files(path: PathBuf, display_type: bool, walk: WalkOptions) {
+fn list_files(meta: FingerprintMetadata, display_type: bool, format: OutputFormat) -> Result<i32> {
     info!("Listing files:");
-    debug!("Path: {:?}", path);
+    debug!("Path: {:?}", meta.path);
     debug!("Display file types: {:?}", display_type);
-    debug!("Absolute paths: {:?}", walk.absolute);
-    debug!("Follow symlinks: {:?}", walk.follow_symlinks);
-    debug!("Include hidden files: {:?}", walk.include_hidden_files);
-    debug!(
-        "Ignore invalid filetypes: {:?}",
-        walk.ignore_invalid_filetypes
-    );
+    debug!("Walk config: {:?}", meta.walk);
 
-    let dh = DirHash::new()
-        .with_files_from_dir(
-            &path,
-            !walk.absolute,
-            walk.follow_symlinks,
-            walk.include_hidden_files,
-            walk.ignore_invalid_filetypes,
-        )
-        .expect("Can't create DirHash");
+    let dh = meta.add_files(DirHash::new())?;
+
+    if format != OutputFormat::Text {
+        JsonOutput::new("list")
+            .field("root", path_json(meta.path.as_os_str()))
+            .list(
+                "files",
+                "file",
+                dh.list_paths()?
+                    .into_iter()
+                    .map(|path| json!({ "path": path_json(path.as_os_str()) })),
+            )
+            .list("ignored", "ignored", ignored_json(&dh, &meta)?)
+            .print(format)?;
+        return Ok(EXIT_OK);
+    }
 
-    for path in dh
-        .list_paths()
-        .expect("Can't get the paths from the dirhash")
-    {
-        println!("{}", path.display());
+    for path in dh.list_paths()? {
+        println!("{}", marked(&escape_path(path.as_os_str())));
     }
 
     if !dh.ignored().is_empty() {
-        let meta = FingerprintMetadata {
-            version: 1,
-            path: path.clone(),
-            walk: walk.clone(),
-        };
-        print!("{}", ignored_files_printout(&dh, &meta));
+        print!("{}", ignored_files_printout(&dh, &meta)?);
     }
+
+    Ok(EXIT_OK)
+}
+
+// Number of files found by `summary`, by type.
+#[derive(Debug, Serialize)]
+struct FileTypeCounts {
+    regular_files: usize,
+    hidden_files: usize,
+    symlinks: usize,
+    block_devices: usize,
+    char_devices: usize,
+    fifos: usize,
+    sockets: usize,
 }
 
-fn summary(path: PathBuf) {
+fn summary(path: PathBuf, format: OutputFormat) -> Result<i32> {
     info!("Printing summary:");
     debug!("Path: {:?}", path);
 
-    let dh = DirHash::new()
-        .with_files_from_dir(&path, false, false, false, true)
-        .expect("Can't create DirHash");
+    let dh = DirHash::new().with_files_from_dir(
+        &path,
+        &WalkConfig::new()
+            .with_absolute(true)
+            .with_include_hidden_files(false)
+            .with_ignore_invalid_filetypes(true),
+    )?;
 
-    let regular_files = dh
-        .list_paths()
-        .expect("Can't get the paths from the dirhash")
-        .len();
+    let regular_files = dh.list_paths()?.len();
     let hidden_files = dh
         .ignored()
         .iter()
@@ -230,6 +1302,23 @@ fn summary(path: PathBuf) {
         .filter(|f| f.1 == IgnoreReason::Socket)
         .count();
 
+    if format != OutputFormat::Text {
+        let counts = FileTypeCounts {
+            regular_files,
+            hidden_files,
+            symlinks,
+            block_devices: block_devs,
+            char_devices: char_devs,
+            fifos,
+            sockets,
+        };
+        JsonOutput::new("summary")
+            .field("root", path_json(path.as_os_str()))
+            .field("counts", counts)
+            .print(format)?;
+        return Ok(EXIT_OK);
+    }
+
     println!("Regular files: {regular_files}");
     println!("Hidden files: {hidden_files}");
     println!("Symlinks: {symlinks}");
@@ -237,161 +1326,936 @@ fn summary(path: PathBuf) {
     println!("Char devices: {char_devs}");
     println!("FIFOs: {fifos}");
     println!("Sockets: {sockets}");
+
+    Ok(EXIT_OK)
+}
+
+// Returns the (relative, if not absolute) path and the reason of each ignored file.
+fn ignored_files<'a, T: PathHashProvider + Send>(
+    dh: &'a DirHash<T>,
+    meta: &FingerprintMetadata,
+) -> Result<Vec<(String, &'a IgnoreReason)>> {
+    dh.ignored()
+        .iter()
+        .map(|(ignored_path, reason)| {
+            let relative_path = match meta.walk.absolute() {
+                true => None,
+                false => Some(PathBuf::from(".").join(ignored_path.strip_prefix(&meta.path)?)),
+            };
+
+            let ignored_path = relative_path.as_deref().unwrap_or(ignored_path.as_path());
+
+            Ok((escape_path(ignored_path.as_os_str()).into_owned(), reason))
+        })
+        .collect()
+}
+
+fn ignored_json<'a, T: PathHashProvider + Send>(
+    dh: &'a DirHash<T>,
+    meta: &FingerprintMetadata,
+) -> Result<Vec<IgnoredJson<'a>>> {
+    Ok(ignored_files(dh, meta)?
+        .into_iter()
+        .map(|(path, reason)| IgnoredJson {
+            path: escaped_path_json(&path),
+            reason,
+        })
+        .collect())
 }
 
 fn ignored_files_printout<T: PathHashProvider + Send>(
     dh: &DirHash<T>,
     meta: &FingerprintMetadata,
-) -> String {
+) -> Result<String> {
     let mut ignore_string = String::new();
     writeln!(&mut ignore_string, "\nIgnored files:")
         .expect("Can't write ignored files header to string buffer");
 
-    for (ignored_path, reason) in dh.ignored() {
-        let relative_path = (!meta.walk.absolute).then(|| {
-            PathBuf::from(".").join(
-                diff_paths(ignored_path, &meta.path)
-                    .expect("Can't create relative path for ignored file"),
-            )
-        });
-
-        let ignored_path = relative_path.as_deref().unwrap_or(ignored_path.as_path());
-
+    for (ignored_path, reason) in ignored_files(dh, meta)? {
         writeln!(
             &mut ignore_string,
             "{}: {:?}",
-            ignored_path.display(),
+            marked(&ignored_path),
             reason
         )
         .expect("Can't write ignored files to string buffer");
     }
-    ignore_string
+    Ok(ignore_string)
 }
 
-fn calculate_fingerprint(meta: FingerprintMetadata) -> String {
-    let mut fingerprint = String::new();
+fn compute_dirhash(
+    meta: &FingerprintMetadata,
+    hashing: &Hashing,
+) -> Result<DirHash<CachedPathHash>> {
+    let cache = &hashing.cache;
+    let dh = hashing.dirhash(meta);
 
-    let meta_serialized = serde_json::to_string_pretty(&meta).expect("Can't serialize metadata");
+    // In parallel, hashing already starts while walking.
+    let dh = match (hashing.threads, &meta.files) {
+        (_, Some(_)) | (1, None) => {
+            let mut dh = meta.add_files(dh)?.with_cache(cache);
+            dh.compute_hash()?;
+            dh
+        }
+        _ => dh.compute_cached_hash_from_dir(&meta.path, &meta.walk, cache)?,
+    };
 
-    let mut commented_meta = String::new();
+    debug!("Cache: {} hits, {} misses", cache.hits(), cache.misses());
+    cache.save()?;
+
+    Ok(dh)
+}
 
-    for line in meta_serialized.lines() {
-        commented_meta.push_str("# ");
-        commented_meta.push_str(line);
-        commented_meta.push('\n');
+fn create_fingerprint<T: PathHashProvider + Send>(
+    dh: &DirHash<T>,
+    meta: &FingerprintMetadata,
+) -> Result<Fingerprint> {
+    Fingerprint::new(
+        serde_json::to_string_pretty(meta).map_err(DirHashError::SerializeMetadata)?,
+        dh.hashtable()
+            .expect("DirHash::compute_hash() sets the hashtable")
+            .clone(),
+        dh.hash().expect("DirHash::compute_hash() sets the hash"),
+        ignored_files(dh, meta)?
+            .into_iter()
+            .map(|(path, reason)| (path, reason.clone()))
+            .collect(),
+    )
+}
+
+// Lines only present in `old` are prefixed with "-", lines only present in `new` with "+".
+fn lines_diff_printout(old: &[String], new: &[String]) -> String {
+    let mut printout = String::new();
+
+    for line in old.iter().filter(|line| !new.contains(line)) {
+        writeln!(&mut printout, "- {line}").expect("Can't write diff to string buffer");
+    }
+    for line in new.iter().filter(|line| !old.contains(line)) {
+        writeln!(&mut printout, "+ {line}").expect("Can't write diff to string buffer");
     }
 
-    writeln!(&mut fingerprint, "{commented_meta}")
-        .expect("Can't write commented metadata to string buffer");
+    printout
+}
+
+fn verify_report(stored: &Fingerprint, calculated: &Fingerprint) -> String {
+    let mut report = String::new();
 
-    let mut dh = DirHash::new()
-        .with_files_from_dir(
-            &meta.path,
-            !meta.walk.absolute,
-            meta.walk.follow_symlinks,
-            meta.walk.include_hidden_files,
-            meta.walk.ignore_invalid_filetypes,
+    let to_lines = |s: &str| s.lines().map(str::to_owned).collect::<Vec<_>>();
+    let metadata_diff = lines_diff_printout(
+        &to_lines(stored.metadata()),
+        &to_lines(calculated.metadata()),
+    );
+    if !metadata_diff.is_empty() {
+        write!(&mut report, "\nMetadata:\n{metadata_diff}")
+            .expect("Can't write report to string buffer");
+    }
+
+    report += &diff_report(stored, calculated);
+
+    if stored.hash() != calculated.hash() {
+        write!(
+            &mut report,
+            "\nHash:\n{}",
+            lines_diff_printout(
+                &[hex::encode(stored.hash())],
+                &[hex::encode(calculated.hash())]
+            )
         )
-        .expect("Can't create DirHash");
+        .expect("Can't write report to string buffer");
+    }
 
-    dh.compute_hash().expect("Error while computing hash");
+    report
+}
 
-    write!(
-        &mut fingerprint,
-        "{}\n{}\n",
-        dh.hashtable().expect("Can't get hashtable"),
-        hex::encode(dh.hash().expect("Can't get hash string"))
-    )
-    .expect("Can't write fingerprint to string buffer");
+// Lists the added, removed, moved and modified files as well as the changes of the ignored files.
+fn diff_report(old: &Fingerprint, new: &Fingerprint) -> String {
+    let mut report = String::new();
 
-    if !dh.ignored().is_empty() {
-        write!(&mut fingerprint, "{}", ignored_files_printout(&dh, &meta))
-            .expect("Can't write ignored files to string buffer");
+    let diff = HashTableDiff::new(old.hashtable(), new.hashtable());
+
+    if !diff.added.is_empty() {
+        writeln!(&mut report, "\nAdded files:").expect("Can't write report to string buffer");
+        for entry in &diff.added {
+            writeln!(&mut report, "{}", marked(&entry.escaped_path()))
+                .expect("Can't write report to string buffer");
+        }
+    }
+
+    if !diff.removed.is_empty() {
+        writeln!(&mut report, "\nRemoved files:").expect("Can't write report to string buffer");
+        for entry in &diff.removed {
+            writeln!(&mut report, "{}", marked(&entry.escaped_path()))
+                .expect("Can't write report to string buffer");
+        }
+    }
+
+    if !diff.moved.is_empty() {
+        writeln!(&mut report, "\nMoved files:").expect("Can't write report to string buffer");
+        for (old, new) in &diff.moved {
+            // Marked if either path is escaped
+            let (old, new) = (old.escaped_path(), new.escaped_path());
+            let marker = match line_marker(&old) {
+                "" => line_marker(&new),
+                marker => marker,
+            };
+            writeln!(&mut report, "{marker}{old} -> {new}")
+                .expect("Can't write report to string buffer");
+        }
+    }
+
+    if !diff.modified.is_empty() {
+        writeln!(&mut report, "\nModified files:").expect("Can't write report to string buffer");
+        for (entry, _) in &diff.modified {
+            writeln!(&mut report, "{}", marked(&entry.escaped_path()))
+                .expect("Can't write report to string buffer");
+        }
+    }
+
+    if !diff.metadata_changed.is_empty() {
+        writeln!(&mut report, "\nChanged file metadata:")
+            .expect("Can't write report to string buffer");
+        for (old, new) in &diff.metadata_changed {
+            let old_columns = old.metadata().columns();
+            let new_columns = new.metadata().columns();
+            let only_in = |columns: &[String], other: &[String]| {
+                columns
+                    .iter()
+                    .filter(|column| !other.contains(column))
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            writeln!(
+                &mut report,
+                "{}: {} -> {}",
+                marked(&old.escaped_path()),
+                only_in(&old_columns, &new_columns),
+                only_in(&new_columns, &old_columns)
+            )
+            .expect("Can't write report to string buffer");
+        }
     }
 
-    fingerprint
+    let ignored_lines = |fp: &Fingerprint| {
+        fp.ignored()
+            .iter()
+            .map(|(path, reason)| format!("{}: {reason:?}", marked(path)))
+            .collect::<Vec<_>>()
+    };
+    let ignored_diff = lines_diff_printout(&ignored_lines(old), &ignored_lines(new));
+    if !ignored_diff.is_empty() {
+        write!(&mut report, "\nIgnored files:\n{ignored_diff}")
+            .expect("Can't write report to string buffer");
+    }
+
+    report
+}
+
+fn verify_json(
+    fingerprint_path: &Path,
+    stored: &Fingerprint,
+    calculated: &Fingerprint,
+    file_contents: &str,
+    format: OutputFormat,
+) -> Result<i32> {
+    let verified = calculated.to_string() == file_contents;
+
+    let output = JsonOutput::new("verify")
+        .field("fingerprint", path_json(fingerprint_path.as_os_str()))
+        .field("verified", verified)
+        .field("hash", hex::encode(stored.hash()))
+        .field("calculated_hash", hex::encode(calculated.hash()))
+        .field(
+            "metadata_matches",
+            stored.metadata() == calculated.metadata(),
+        );
+    diff_json(output, stored, calculated)?.print(format)?;
+
+    match verified {
+        true => Ok(EXIT_OK),
+        false => Ok(EXIT_MISMATCH),
+    }
 }
 
-fn analyze_files(path: PathBuf, fingerprint_path: Option<PathBuf>, walk: WalkOptions) {
+// Adds the lists of added, removed, moved and modified files as well as the changes of the ignored
+// files.
+fn diff_json(output: JsonOutput, old: &Fingerprint, new: &Fingerprint) -> Result<JsonOutput> {
+    let diff = HashTableDiff::new(old.hashtable(), new.hashtable());
+
+    let only_in = |ignored: &[(String, IgnoreReason)], other: &[(String, IgnoreReason)]| {
+        ignored
+            .iter()
+            .filter(|entry| !other.contains(entry))
+            .map(|(path, reason)| {
+                to_json(IgnoredJson {
+                    path: escaped_path_json(path),
+                    reason,
+                })
+            })
+            .collect::<Result<Vec<_>>>()
+    };
+    let changed = |pairs: &[(HashTableEntry, HashTableEntry)]| {
+        pairs
+            .iter()
+            .map(|(old, new)| to_json(ChangedJson { old, new }))
+            .collect::<Result<Vec<_>>>()
+    };
+
+    Ok(output
+        .list("added", "added", &diff.added)
+        .list("removed", "removed", &diff.removed)
+        .list("moved", "moved", changed(&diff.moved)?)
+        .list("modified", "modified", changed(&diff.modified)?)
+        .list(
+            "metadata_changed",
+            "metadata_changed",
+            changed(&diff.metadata_changed)?,
+        )
+        .list(
+            "ignored_added",
+            "ignored_added",
+            only_in(new.ignored(), old.ignored())?,
+        )
+        .list(
+            "ignored_removed",
+            "ignored_removed",
+            only_in(old.ignored(), new.ignored())?,
+        ))
+}
+
+fn analyze_files(
+    meta: FingerprintMetadata,
+    fingerprint_path: Option<PathBuf>,
+    manifest: Option<(PathBuf, ManifestFormat)>,
+    hashing: Hashing,
+    format: OutputFormat,
+) -> Result<i32> {
     info!("Analyzing files:");
-    debug!("Path: {:?}", path);
+    debug!("Path: {:?}", meta.path);
     debug!("Fingerprint path: {:?}", fingerprint_path);
-    debug!("Absolute paths: {:?}", walk.absolute);
-    debug!("Follow symlinks: {:?}", walk.follow_symlinks);
-    debug!("Include hidden files: {:?}", walk.include_hidden_files);
-    debug!(
-        "Ignore invalid filetypes: {:?}",
-        walk.ignore_invalid_filetypes
-    );
+    debug!("Manifest: {:?}", manifest);
+    debug!("Algorithm: {:?}", meta.algorithm);
+    debug!("File metadata: {:?}", meta.file_metadata);
+    debug!("Read options: {:?}", hashing.read_options);
+    debug!("Walk config: {:?}", meta.walk);
+
+    let dh = compute_dirhash(&meta, &hashing)?;
+    let fingerprint = create_fingerprint(&dh, &meta)?.to_string();
+
+    if format == OutputFormat::Text {
+        print!("{}", fingerprint);
+    } else {
+        JsonOutput::new("analyze")
+            .field("root", path_json(meta.path.as_os_str()))
+            .field("metadata", &meta)
+            .field(
+                "hash",
+                hex::encode(dh.hash().expect("DirHash::compute_hash() sets the hash")),
+            )
+            .list(
+                "entries",
+                "entry",
+                dh.hashtable()
+                    .expect("DirHash::compute_hash() sets the hashtable")
+                    .entries(),
+            )
+            .list("ignored", "ignored", ignored_json(&dh, &meta)?)
+            .print(format)?;
+    }
+
+    if let Some(path) = fingerprint_path {
+        fs::write(&path, fingerprint).map_err(|e| DirHashError::PathIo(path, e))?;
+    }
+
+    if let Some((path, manifest_format)) = manifest {
+        let hashtable = dh
+            .hashtable()
+            .expect("DirHash::compute_hash() sets the hashtable");
+        let lines = Manifest::from_hashtable(hashtable, meta.algorithm).to_lines(manifest_format);
+        fs::write(&path, lines).map_err(|e| DirHashError::PathIo(path, e))?;
+    }
+
+    Ok(EXIT_OK)
+}
 
-    let meta = FingerprintMetadata {
-        version: 1,
-        path: path.clone(),
-        walk: walk.clone(),
+fn compute_merkle_tree(meta: &FingerprintMetadata, hashing: &Hashing) -> Result<MerkleTree> {
+    let dh = compute_dirhash(meta, hashing)?;
+    let hashtable = dh
+        .hashtable()
+        .expect("DirHash::compute_hash() sets the hashtable");
+    MerkleTree::new(hashtable, meta.algorithm)
+}
+
+fn merkle_tree(
+    mut meta: FingerprintMetadata,
+    compare: Option<PathBuf>,
+    hashing: Hashing,
+    format: OutputFormat,
+) -> Result<i32> {
+    info!("Building Merkle tree:");
+    debug!("Path: {:?}", meta.path);
+    debug!("Compare with: {:?}", compare);
+    debug!("Walk config: {:?}", meta.walk);
+    debug!("Algorithm: {:?}", meta.algorithm);
+    debug!("File metadata: {:?}", meta.file_metadata);
+    debug!("Read options: {:?}", hashing.read_options);
+
+    let path = meta.path.clone();
+
+    let tree = compute_merkle_tree(&meta, &hashing)?;
+
+    let Some(compare) = compare else {
+        if format != OutputFormat::Text {
+            JsonOutput::new("tree")
+                .field("root", path_json(path.as_os_str()))
+                .field("hash", hex::encode(tree.hash()))
+                .list(
+                    "subtrees",
+                    "subtree",
+                    tree.subtree_hashes()
+                        .into_iter()
+                        .map(|(path, hash)| {
+                            json!({"path": escaped_path_json(&path), "hash": hex::encode(hash)})
+                        }),
+                )
+                .print(format)?;
+            return Ok(EXIT_OK);
+        }
+
+        for (path, hash) in tree.subtree_hashes() {
+            println!("{}{}  {path}", line_marker(&path), hex::encode(hash));
+        }
+        return Ok(EXIT_OK);
     };
 
-    let fingerprint = calculate_fingerprint(meta);
+    meta.path = compare.clone();
+    let other = compute_merkle_tree(&meta, &hashing)?;
+
+    let diff = tree.diff(&other);
+    let exit_code = match diff.is_empty() {
+        true => EXIT_OK,
+        false => EXIT_MISMATCH,
+    };
+
+    if format != OutputFormat::Text {
+        let paths = |paths: &[String]| {
+            paths
+                .iter()
+                .map(|path| json!({ "path": escaped_path_json(path) }))
+                .collect::<Vec<_>>()
+        };
+        JsonOutput::new("tree")
+            .field("root", path_json(path.as_os_str()))
+            .field("compare", path_json(compare.as_os_str()))
+            .field("matches", diff.is_empty())
+            .field("hash", hex::encode(tree.hash()))
+            .field("compare_hash", hex::encode(other.hash()))
+            .list("added", "added", paths(&diff.added))
+            .list("removed", "removed", paths(&diff.removed))
+            .list("modified", "modified", paths(&diff.modified))
+            .print(format)?;
+        return Ok(exit_code);
+    }
+
+    if diff.is_empty() {
+        println!("Trees match: {}", hex::encode(tree.hash()));
+        return Ok(EXIT_OK);
+    }
+
+    let mut report = String::new();
+    for (header, paths) in [
+        ("Added", &diff.added),
+        ("Removed", &diff.removed),
+        ("Modified", &diff.modified),
+    ] {
+        if !paths.is_empty() {
+            writeln!(&mut report, "\n{header}:").expect("Can't write report to string buffer");
+            for path in paths {
+                writeln!(&mut report, "{}", marked(path))
+                    .expect("Can't write report to string buffer");
+            }
+        }
+    }
+    print!("Trees differ!\n{report}");
+
+    Ok(exit_code)
+}
 
-    print!("{}", fingerprint);
+// Returns the parsed fingerprint file with its metadata and contents.
+fn read_fingerprint(fingerprint_path: &Path) -> Result<(Fingerprint, FingerprintMetadata, String)> {
+    let filetype = fs::metadata(fingerprint_path)
+        .map_err(|e| DirHashError::PathIo(fingerprint_path.to_owned(), e))?
+        .file_type();
 
-    if let Some(path) = fingerprint_path.as_ref() {
-        fs::write(path, fingerprint).expect("Can't write to fingerprint file");
+    if !filetype.is_file() {
+        return Err(DirHashError::NotAFile(fingerprint_path.to_owned()));
+    }
+
+    let file_contents = fs::read_to_string(fingerprint_path)
+        .map_err(|e| DirHashError::PathIo(fingerprint_path.to_owned(), e))?;
+
+    let fingerprint: Fingerprint = file_contents.parse()?;
+
+    let meta: FingerprintMetadata = fingerprint.metadata_as()?;
+
+    debug!("meta = {meta:?}");
+
+    // Currently, only fingerprints with version "1" are supported.
+    if meta.version != 1 {
+        return Err(DirHashError::UnsupportedVersion(meta.version));
     }
+
+    Ok((fingerprint, meta, file_contents))
 }
 
-fn verify_files(fingerprint_path: PathBuf) {
+fn verify_files(fingerprint_path: PathBuf, hashing: Hashing, format: OutputFormat) -> Result<i32> {
     info!("Verifying files:");
     debug!("Fingerprint path: {:?}", fingerprint_path);
+    debug!("Read options: {:?}", hashing.read_options);
 
-    let filetype = fs::metadata(&fingerprint_path)
-        .expect("Can't read metadata of fingerprint file")
-        .file_type();
+    let (stored, mut meta, file_contents) = read_fingerprint(&fingerprint_path)?;
+    if meta.listed {
+        meta.files = Some(fingerprint_paths(&stored));
+    }
 
-    if !filetype.is_file() {
-        panic!("Fingerprint path is not a file!");
+    let dh = compute_dirhash(&meta, &hashing)?;
+    let calculated = create_fingerprint(&dh, &meta)?;
+
+    if format != OutputFormat::Text {
+        return verify_json(
+            &fingerprint_path,
+            &stored,
+            &calculated,
+            &file_contents,
+            format,
+        );
     }
 
-    let f = File::open(&fingerprint_path).expect("Can't open fingerprint file");
-    let reader = BufReader::new(f);
+    if calculated.to_string() == file_contents {
+        println!("Fingerprint verified: {}", hex::encode(stored.hash()));
+        return Ok(EXIT_OK);
+    }
 
-    let meta_serialized = reader
-        .lines()
-        .map(|line| line.expect("Can't read line"))
-        .take_while(|line| line.starts_with("# "))
-        // .map(|line| line.strip_prefix("# ").unwrap())
-        .map(|line| line.strip_prefix("# ").unwrap().to_owned())
-        .collect::<Vec<_>>()
-        .join("\n");
+    let report = verify_report(&stored, &calculated);
 
-    // let meta = reader
-    //     .lines()
-    //     .map(|line| line.expect("Can't read line").strip_prefix("# "))
-    //     .take_while(Option::is_some)
-    //     .flat_map(Option::unwrap)
-    //     // .map(|line| line.strip_prefix("# ").unwrap())
-    //     // .map(|line| line.strip_prefix("# ").unwrap().to_owned())
-    //     .collect::<Vec<_>>()
-    //     .join("\n");
+    if report.is_empty() {
+        println!("Fingerprint mismatch!\n\nThe fingerprint file differs in its formatting only.");
+    } else {
+        print!("Fingerprint mismatch!\n{report}");
+    }
 
-    debug!("meta_serialized = {meta_serialized}");
+    Ok(EXIT_MISMATCH)
+}
 
-    let meta: FingerprintMetadata = serde_json::from_str(&meta_serialized).unwrap();
+// Returns the paths of the hashtable entries and ignored files of `fingerprint`, i.e. the paths that
+// were listed when it was created. Ignored paths with invalid escape sequences are left out, so they
+// show up as a mismatch.
+fn fingerprint_paths(fingerprint: &Fingerprint) -> Vec<PathBuf> {
+    let entries = fingerprint
+        .hashtable()
+        .entries()
+        .iter()
+        .map(|entry| PathBuf::from(entry.path()));
+    let ignored = fingerprint
+        .ignored()
+        .iter()
+        .filter_map(|(path, _)| unescape_path(path))
+        .map(PathBuf::from);
+    entries.chain(ignored).collect()
+}
 
-    debug!("meta = {meta:?}");
+// Side of `diff`, either a directory or a fingerprint file.
+enum DiffSide {
+    Dir(PathBuf),
+    Fingerprint(PathBuf, Box<(Fingerprint, FingerprintMetadata)>),
+}
 
-    if meta.version != 1 {
-        panic!("Currently, only fingerprints with version \"1\" are supported!")
+impl DiffSide {
+    fn new(cwd: &Path, user_path: PathBuf) -> Result<Self> {
+        let path = cwd.join(&user_path);
+        if path.is_file() {
+            let (fingerprint, meta, _) = read_fingerprint(&path)?;
+            return Ok(DiffSide::Fingerprint(path, Box::new((fingerprint, meta))));
+        }
+        Ok(DiffSide::Dir(parse_user_path(cwd, Some(user_path))?))
     }
 
-    let fingerprint = calculate_fingerprint(meta);
+    fn path(&self) -> &Path {
+        match self {
+            DiffSide::Dir(path) | DiffSide::Fingerprint(path, _) => path,
+        }
+    }
 
-    print!("Calculated fingerprint:\n{}", fingerprint);
+    fn stored(&self) -> Option<(&Fingerprint, &FingerprintMetadata)> {
+        match self {
+            DiffSide::Dir(_) => None,
+            DiffSide::Fingerprint(_, stored) => Some((&stored.0, &stored.1)),
+        }
+    }
 
-    let file_contents = fs::read_to_string(fingerprint_path).expect("Can't read fingerprint file");
+    // Returns the fingerprint of the side with relative paths, computing it for a directory.
+    fn fingerprint(self, settings: &FingerprintMetadata, hashing: &Hashing) -> Result<Fingerprint> {
+        match self {
+            DiffSide::Dir(path) => {
+                let meta = FingerprintMetadata {
+                    path,
+                    ..settings.clone()
+                };
+                let dh = compute_dirhash(&meta, hashing)?;
+                create_fingerprint(&dh, &meta)
+            }
+            DiffSide::Fingerprint(_, stored) => {
+                let (fingerprint, meta) = *stored;
+                relative_fingerprint(fingerprint, &meta)
+            }
+        }
+    }
+}
+
+// Strips the root from the paths of a fingerprint created with absolute paths (and recomputes the
+// hash), so it's comparable to one with relative paths.
+fn relative_fingerprint(
+    fingerprint: Fingerprint,
+    meta: &FingerprintMetadata,
+) -> Result<Fingerprint> {
+    if !meta.walk.absolute() {
+        return Ok(fingerprint);
+    }
+
+    // Ignored files are listed with escaped paths, the hashtable entries aren't escaped.
+    let root = meta.path.as_os_str().as_bytes();
+    let root = root.strip_suffix(b"/").unwrap_or(root);
+    let escaped_root = escape_path(OsStr::from_bytes(root));
+    let relative =
+        |path: &[u8], root: &[u8]| match path.strip_prefix(root).and_then(|p| p.strip_prefix(b"/"))
+        {
+            Some(path) => [b"./", path].concat(),
+            None => path.to_owned(),
+        };
+
+    let mut hashtable = HashTable::new();
+    for entry in fingerprint.hashtable().entries() {
+        let path = OsString::from_vec(relative(entry.path().as_bytes(), root));
+        hashtable.add(
+            HashTableEntry::for_algorithm(meta.algorithm, entry.hash(), path)?
+                .with_kind(entry.kind())
+                .with_metadata(entry.metadata().clone()),
+        );
+    }
+    hashtable.sort();
 
-    print!("Fingerprint file:\n{}", file_contents);
+    let ignored = fingerprint
+        .ignored()
+        .iter()
+        .map(|(path, reason)| {
+            let path = relative(path.as_bytes(), escaped_root.as_bytes());
+            let path = String::from_utf8(path).expect("Stripped path isn't valid UTF-8");
+            (path, reason.clone())
+        })
+        .collect();
+
+    Fingerprint::new(
+        fingerprint.metadata(),
+        hashtable.clone(),
+        meta.algorithm.digest(hashtable.to_string()),
+        ignored,
+    )
+}
+
+// Returns an error if the walk, algorithm or metadata options given to `diff` are neither the
+// defaults nor the ones of the fingerprint `meta`, as they would be replaced by these.
+fn check_diff_settings(settings: &FingerprintMetadata, meta: &FingerprintMetadata) -> Result<()> {
+    let default_walk = WalkConfig::from(WalkArgs::default());
+    let given_walk = settings.walk.clone().with_absolute(false);
+    let conflicts = [
+        (
+            "walk",
+            given_walk != default_walk && given_walk != meta.walk.clone().with_absolute(false),
+        ),
+        (
+            "algorithm",
+            settings.algorithm != HashAlgorithm::default() && settings.algorithm != meta.algorithm,
+        ),
+        (
+            "metadata",
+            settings.file_metadata != MetadataOptions::from(MetadataArgs::default())
+                && settings.file_metadata != meta.file_metadata,
+        ),
+    ];
+
+    match conflicts.iter().find(|(_, conflict)| *conflict) {
+        Some((options, _)) => Err(DirHashError::ConflictingOptions(format!(
+            "the {options} options differ from the ones of the fingerprint"
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn diff_trees(
+    old: DiffSide,
+    new: DiffSide,
+    mut settings: FingerprintMetadata,
+    hashing: Hashing,
+    format: OutputFormat,
+) -> Result<i32> {
+    info!("Comparing trees:");
+    debug!("Old: {:?}", old.path());
+    debug!("New: {:?}", new.path());
+    debug!("Read options: {:?}", hashing.read_options);
+
+    if let (Some((_, old_meta)), Some((_, new_meta))) = (old.stored(), new.stored()) {
+        if old_meta.algorithm != new_meta.algorithm {
+            return Err(DirHashError::ConflictingOptions(format!(
+                "the fingerprints use different algorithms ({} and {})",
+                old_meta.algorithm, new_meta.algorithm
+            )));
+        }
+    }
+
+    // A directory is walked and hashed like the fingerprint on the other side.
+    if let Some((fingerprint, meta)) = old.stored().or(new.stored()) {
+        check_diff_settings(&settings, meta)?;
+        settings.algorithm = meta.algorithm;
+        settings.walk = meta.walk.clone();
+        settings.file_metadata = meta.file_metadata;
+        if meta.listed {
+            // Listed with absolute paths, they are below the root of the fingerprint.
+            let files = fingerprint_paths(fingerprint)
+                .into_iter()
+                .map(|path| match path.strip_prefix(&meta.path) {
+                    Ok(relative) => relative.to_owned(),
+                    Err(_) => path,
+                })
+                .collect();
+            settings = settings.with_files(Some(files));
+        }
+    }
+    settings.walk = settings.walk.with_absolute(false);
+    debug!("Settings: {:?}", settings);
+
+    let old_path = old.path().to_owned();
+    let new_path = new.path().to_owned();
+    let old = old.fingerprint(&settings, &hashing)?;
+    let new = new.fingerprint(&settings, &hashing)?;
+
+    let report = diff_report(&old, &new);
+    let matches = report.is_empty();
+
+    if format != OutputFormat::Text {
+        let output = JsonOutput::new("diff")
+            .field("old", path_json(old_path.as_os_str()))
+            .field("new", path_json(new_path.as_os_str()))
+            .field("matches", matches)
+            .field("old_hash", hex::encode(old.hash()))
+            .field("new_hash", hex::encode(new.hash()));
+        diff_json(output, &old, &new)?.print(format)?;
+    } else if matches {
+        println!("Trees match: {}", hex::encode(new.hash()));
+    } else {
+        print!("Trees differ!\n{report}");
+    }
+
+    match matches {
+        true => Ok(EXIT_OK),
+        false => Ok(EXIT_MISMATCH),
+    }
+}
+
+fn find_dupes(
+    meta: FingerprintMetadata,
+    min_size: u64,
+    by_size: bool,
+    hashing: Hashing,
+    format: OutputFormat,
+) -> Result<i32> {
+    info!("Finding duplicates:");
+    debug!("Path: {:?}", meta.path);
+    debug!("Walk config: {:?}", meta.walk);
+    debug!("Minimum size: {:?}", min_size);
+    debug!("Group by size first: {:?}", by_size);
+    debug!("Algorithm: {:?}", meta.algorithm);
+    debug!("Read options: {:?}", hashing.read_options);
+
+    let dh = match by_size {
+        false => compute_dirhash(&meta, &hashing)?,
+        // All files must be found before hashing, to skip the ones with a unique size.
+        true => {
+            let dh = meta.add_files(hashing.dirhash(&meta))?;
+            let files = dh
+                .files()
+                .iter()
+                .filter(|ph| ph.kind() == EntryKind::File)
+                .map(|ph| ph.path());
+            let candidates = size_collisions(files, min_size)?;
+            debug!("Files with size collisions: {}", candidates.len());
+
+            let mut dh = dh
+                .retain_files(|ph| candidates.contains(ph.path()))
+                .with_cache(&hashing.cache);
+            dh.compute_hash()?;
+            hashing.cache.save()?;
+            dh
+        }
+    };
+
+    let groups = find_duplicates(
+        dh.hashtable()
+            .expect("DirHash::compute_hash() sets the hashtable"),
+        min_size,
+    );
+    let wasted: u64 = groups.iter().map(|group| group.wasted()).sum();
+
+    if format != OutputFormat::Text {
+        JsonOutput::new("dupes")
+            .field("root", path_json(meta.path.as_os_str()))
+            .field("wasted", wasted)
+            .list(
+                "groups",
+                "group",
+                groups.iter().map(|group| {
+                    json!({
+                        "hash": hex::encode(group.hash()),
+                        "size": group.size(),
+                        "wasted": group.wasted(),
+                        "paths": group.paths().iter().map(|path| path_json(path)).collect::<Vec<_>>(),
+                    })
+                }),
+            )
+            .print(format)?;
+        return Ok(EXIT_OK);
+    }
+
+    for group in &groups {
+        println!(
+            "{} files of {} bytes ({} bytes wasted):",
+            group.paths().len(),
+            group.size(),
+            group.wasted()
+        );
+        for path in group.paths() {
+            println!("{}", marked(&escape_path(path)));
+        }
+        println!();
+    }
+
+    println!(
+        "{} groups of duplicates, {wasted} bytes wasted",
+        groups.len()
+    );
+
+    Ok(EXIT_OK)
+}
+
+fn check_manifest(
+    manifest_path: &Path,
+    root: PathBuf,
+    algorithm: HashAlgorithm,
+    unlisted_walk: Option<WalkConfig>,
+    quiet: bool,
+    read: ReadArgs,
+    format: OutputFormat,
+) -> Result<i32> {
+    let threads = read.threads();
+    let read_options = ReadOptions::from(read);
+
+    info!("Checking manifest:");
+    debug!("Manifest path: {:?}", manifest_path);
+    debug!("Root: {:?}", root);
+    debug!("Algorithm: {:?}", algorithm);
+    debug!("Walk config for unlisted files: {:?}", unlisted_walk);
+    debug!("Read options: {:?}", read_options);
+    debug!("Threads: {:?}", threads);
+
+    let file = fs::File::open(manifest_path)
+        .map_err(|e| DirHashError::PathIo(manifest_path.to_owned(), e))?;
+    let manifest = Manifest::from_reader(io::BufReader::new(file), algorithm)
+        .map_err(|e| e.with_path(manifest_path))?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    let results = pool.install(|| manifest.check(&root, read_options));
+
+    let dh = unlisted_walk
+        .map(|walk| DirHash::new().with_files_from_dir(&root, &walk))
+        .transpose()?;
+    let unlisted = match &dh {
+        Some(dh) => manifest.unlisted(&root, &dh.list_paths()?),
+        None => vec![],
+    };
+
+    let count = |status| results.iter().filter(|(_, s)| *s == status).count();
+    let failed = count(CheckStatus::Failed);
+    let missing = count(CheckStatus::Missing);
+    let unreadable = count(CheckStatus::Unreadable);
+    let passed = failed == 0 && missing == 0 && unreadable == 0 && unlisted.is_empty();
+
+    if format != OutputFormat::Text {
+        JsonOutput::new("check")
+            .field("manifest", path_json(manifest_path.as_os_str()))
+            .field("root", path_json(root.as_os_str()))
+            .field("passed", passed)
+            .list(
+                "files",
+                "file",
+                results.iter().map(|(entry, status)| {
+                    json!({
+                        "path": path_json(entry.path()),
+                        "algorithm": entry.algorithm(),
+                        "status": status,
+                    })
+                }),
+            )
+            .list(
+                "unlisted",
+                "unlisted",
+                unlisted
+                    .iter()
+                    .map(|path| json!({ "path": path_json(path.as_os_str()) })),
+            )
+            .print(format)?;
+    } else {
+        for (entry, status) in &results {
+            if !quiet || *status != CheckStatus::Ok {
+                println!("{}: {status}", marked(&entry.escaped_path()));
+            }
+        }
+        for path in &unlisted {
+            println!("{}: UNLISTED", marked(&escape_path(path.as_os_str())));
+        }
+
+        let plural = |n: usize, singular: &str, plural: &str| match n {
+            1 => format!("1 {singular}"),
+            n => format!("{n} {plural}"),
+        };
+        if failed > 0 {
+            eprintln!(
+                "WARNING: {} did NOT match",
+                plural(failed, "computed checksum", "computed checksums")
+            );
+        }
+        if missing > 0 {
+            eprintln!(
+                "WARNING: {} missing",
+                plural(missing, "listed file is", "listed files are")
+            );
+        }
+        if unreadable > 0 {
+            eprintln!(
+                "WARNING: {} could not be read",
+                plural(unreadable, "listed file", "listed files")
+            );
+        }
+        if !unlisted.is_empty() {
+            eprintln!(
+                "WARNING: {} not listed",
+                plural(unlisted.len(), "file is", "files are")
+            );
+        }
+    }
 
-    if fingerprint != file_contents {
-        panic!("Calculated fingerprint doesn't match fingerprint file!");
+    match passed {
+        true => Ok(EXIT_OK),
+        false => Ok(EXIT_MISMATCH),
     }
 }
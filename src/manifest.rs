@@ -0,0 +1,567 @@
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    fmt::Display,
+    io::{self, BufRead},
+    os::unix::ffi::OsStringExt,
+    path::Path,
+    str::FromStr,
+};
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::algorithm::HashAlgorithm;
+use crate::error::{DirHashError, Result};
+use crate::escape::{escape_path, unescape_path};
+use crate::hashtable::{EntryKind, HashTable};
+use crate::pathhash::{PathHash, PathHashProvider, ReadOptions};
+
+/// Line format of a checksum file ([`Manifest`]).
+#[derive(
+    Clone, Copy, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestFormat {
+    /// `<hash>  <path>` as printed by `sha256sum` (`<hash> *<path>` in binary mode)
+    #[default]
+    Gnu,
+    /// `SHA256 (<path>) = <hash>` as printed by `sha256sum --tag` and the BSD tools
+    Bsd,
+}
+
+impl ManifestFormat {
+    pub const ALL: [ManifestFormat; 2] = [ManifestFormat::Gnu, ManifestFormat::Bsd];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ManifestFormat::Gnu => "gnu",
+            ManifestFormat::Bsd => "bsd",
+        }
+    }
+}
+
+impl Display for ManifestFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ManifestFormat {
+    type Err = DirHashError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ManifestFormat::ALL
+            .into_iter()
+            .find(|format| format.name() == s)
+            .ok_or_else(|| DirHashError::UnknownManifestFormat(s.to_owned()))
+    }
+}
+
+/// Single line of a [`Manifest`].
+#[derive(Clone, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+pub struct ManifestEntry {
+    algorithm: HashAlgorithm,
+    hash: Vec<u8>,
+    path: OsString,
+    binary: bool,
+}
+
+impl ManifestEntry {
+    /// Creates an entry for a hash computed with `algorithm`. Returns an
+    /// [`DirHashError::HashTableEntry`] if the length of the hash doesn't match the digest length of
+    /// the algorithm.
+    pub fn new<P, H>(algorithm: HashAlgorithm, hash: H, path: P) -> Result<Self>
+    where
+        P: Into<OsString>,
+        H: AsRef<[u8]>,
+    {
+        let hash = hash.as_ref();
+        if hash.len() != algorithm.output_len() {
+            return Err(DirHashError::HashTableEntry(hash.len()));
+        }
+
+        Ok(Self {
+            algorithm,
+            hash: hash.to_vec(),
+            path: path.into(),
+            binary: false,
+        })
+    }
+
+    /// Marks the entry as read in binary mode (`*` before the path in the GNU format). This makes
+    /// no difference for hashing.
+    pub fn with_binary(mut self, binary: bool) -> Self {
+        self.binary = binary;
+        self
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn hash(&self) -> &[u8] {
+        &self.hash
+    }
+
+    /// Returns the path as listed, which is relative to the root the manifest is checked against
+    /// (unless absolute).
+    pub fn path(&self) -> &OsStr {
+        &self.path
+    }
+
+    /// Returns the path as written in the checksum file, see [`escape_path()`].
+    pub fn escaped_path(&self) -> Cow<'_, str> {
+        escape_path(&self.path)
+    }
+
+    pub fn binary(&self) -> bool {
+        self.binary
+    }
+
+    /// Parses a line in either format. Lines in the GNU format are expected to be hashed with
+    /// `algorithm`, lines in the BSD format name their algorithm. Lines starting with a backslash
+    /// contain an escaped path. Like `sha256sum --check`, the path is taken as is, so it doesn't
+    /// need to be valid UTF-8. `line_number` is only used for the error.
+    pub fn parse(
+        line: impl AsRef<[u8]>,
+        line_number: usize,
+        algorithm: HashAlgorithm,
+    ) -> Result<Self> {
+        let error = |message: String| DirHashError::ParseManifest(line_number, message);
+        let line = line.as_ref();
+
+        let (escaped, line) = match line.strip_prefix(b"\\") {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+
+        let (algorithm, hash, path, binary) = match parse_bsd(line) {
+            Some((tag, path, hash)) => {
+                let algorithm = HashAlgorithm::ALL
+                    .into_iter()
+                    .find(|algorithm| algorithm.tag() == tag)
+                    .ok_or_else(|| error(format!("unknown algorithm tag \"{tag}\"")))?;
+                (algorithm, hash, path, false)
+            }
+            None => {
+                let (hash, rest) = split_once(line, b" ")
+                    .ok_or_else(|| error(String::from("missing separator")))?;
+                let (binary, path) = match rest.split_first() {
+                    Some((b' ', path)) => (false, path),
+                    Some((b'*', path)) => (true, path),
+                    _ => return Err(error(String::from("missing separator"))),
+                };
+                (algorithm, hash, path, binary)
+            }
+        };
+
+        let hash = hex::decode(hash).map_err(|e| error(format!("invalid hash: {e}")))?;
+
+        if path.is_empty() {
+            return Err(error(String::from("empty path")));
+        }
+
+        let path = match escaped {
+            true => {
+                unescape_path(path).ok_or_else(|| error(String::from("invalid escape sequence")))?
+            }
+            false => OsString::from_vec(path.to_vec()),
+        };
+
+        let entry = Self::new(algorithm, &hash, path).map_err(|_| {
+            error(format!(
+                "hash length {} doesn't match {algorithm} ({})",
+                hash.len(),
+                algorithm.output_len()
+            ))
+        })?;
+
+        Ok(entry.with_binary(binary))
+    }
+
+    /// Formats the entry as a line (without newline) in `format`. Escaped paths are marked with a
+    /// backslash at the start of the line.
+    pub fn to_line(&self, format: ManifestFormat) -> String {
+        let path = self.escaped_path();
+        let marker = match path {
+            Cow::Borrowed(_) => "",
+            Cow::Owned(_) => "\\",
+        };
+        match format {
+            ManifestFormat::Gnu => format!(
+                "{marker}{} {}{path}",
+                hex::encode(&self.hash),
+                if self.binary { '*' } else { ' ' },
+            ),
+            ManifestFormat::Bsd => format!(
+                "{marker}{} ({path}) = {}",
+                self.algorithm.tag(),
+                hex::encode(&self.hash)
+            ),
+        }
+    }
+}
+
+// Splits a line in the BSD format into tag, path and hash.
+fn parse_bsd(line: &[u8]) -> Option<(&str, &[u8], &[u8])> {
+    let (tag, rest) = split_once(line, b" (")?;
+    // The path may contain ") = " as well, but the hash can't.
+    let (path, hash) = rsplit_once(rest, b") = ")?;
+    let tag = std::str::from_utf8(tag).ok()?;
+    let is_tag = !tag.is_empty() && tag.chars().all(|c| c.is_ascii_alphanumeric());
+    is_tag.then_some((tag, path, hash))
+}
+
+// Splits `bytes` at the first occurrence of `separator`, like `str::split_once()`.
+fn split_once<'a>(bytes: &'a [u8], separator: &[u8]) -> Option<(&'a [u8], &'a [u8])> {
+    let i = bytes
+        .windows(separator.len())
+        .position(|window| window == separator)?;
+    Some((&bytes[..i], &bytes[i + separator.len()..]))
+}
+
+// Splits `bytes` at the last occurrence of `separator`, like `str::rsplit_once()`.
+fn rsplit_once<'a>(bytes: &'a [u8], separator: &[u8]) -> Option<(&'a [u8], &'a [u8])> {
+    let i = bytes
+        .windows(separator.len())
+        .rposition(|window| window == separator)?;
+    Some((&bytes[..i], &bytes[i + separator.len()..]))
+}
+
+/// Result of checking a single [`ManifestEntry`] against the filesystem.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    /// The hash of the file matches.
+    Ok,
+    /// The hash of the file differs.
+    Failed,
+    /// The file doesn't exist.
+    Missing,
+    /// The file exists, but can't be hashed (e.g. because it's a directory or not readable).
+    Unreadable,
+}
+
+impl Display for CheckStatus {
+    /// Formats the status like `sha256sum --check`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckStatus::Ok => write!(f, "OK"),
+            CheckStatus::Failed => write!(f, "FAILED"),
+            CheckStatus::Missing => write!(f, "MISSING"),
+            CheckStatus::Unreadable => write!(f, "FAILED open or read"),
+        }
+    }
+}
+
+/// Checksum file as created by `sha256sum` and the related coreutils tools, in the GNU or BSD
+/// format ([`ManifestFormat`]).
+///
+/// Unlike a [`crate::fingerprint::Fingerprint`], a manifest only lists the files and can't
+/// tell whether files were added.
+#[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+pub struct Manifest {
+    entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Creates a manifest of the regular files of `hashtable`, which was computed with
+    /// `algorithm`, sorted by path. Symlinks and directory markers are skipped, as they can't be
+    /// checked by the coreutils tools, and so is the file metadata.
+    pub fn from_hashtable(hashtable: &HashTable, algorithm: HashAlgorithm) -> Self {
+        let mut entries: Vec<_> = hashtable
+            .entries()
+            .iter()
+            .filter(|entry| entry.kind() == EntryKind::File)
+            .map(|entry| ManifestEntry {
+                algorithm,
+                hash: entry.hash().to_vec(),
+                path: entry.path().to_owned(),
+                binary: false,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Self { entries }
+    }
+
+    /// Reads a checksum file, which may mix both formats. Lines in the GNU format are expected to
+    /// be hashed with `algorithm`. Empty lines are skipped. The lines are read as bytes, as the
+    /// coreutils tools write paths that aren't valid UTF-8 unchanged.
+    pub fn from_reader(reader: impl BufRead, algorithm: HashAlgorithm) -> Result<Self> {
+        let mut manifest = Manifest::new();
+
+        for (i, line) in reader.split(b'\n').enumerate() {
+            let mut line = line?;
+            // Like `BufRead::lines()`, a line may end with "\r\n".
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+            if !line.is_empty() {
+                manifest.add(ManifestEntry::parse(&line, i + 1, algorithm)?);
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn add(&mut self, entry: ManifestEntry) {
+        self.entries.push(entry);
+    }
+
+    pub fn entries(&self) -> &[ManifestEntry] {
+        self.entries.as_slice()
+    }
+
+    /// Formats all entries in `format`, one per line.
+    pub fn to_lines(&self, format: ManifestFormat) -> String {
+        self.entries
+            .iter()
+            .map(|entry| entry.to_line(format) + "\n")
+            .collect()
+    }
+
+    /// Hashes the listed files (relative to the absolute `root`, unless listed with an absolute
+    /// path) and compares them with the listed hashes. The results are in the order of the
+    /// entries.
+    pub fn check(
+        &self,
+        root: &Path,
+        read_options: ReadOptions,
+    ) -> Vec<(&ManifestEntry, CheckStatus)> {
+        self.entries
+            .par_iter()
+            .map(|entry| (entry, check_entry(entry, root, read_options)))
+            .collect()
+    }
+
+    /// Returns the `paths` (absolute, or relative to `root`) that aren't listed in the manifest.
+    pub fn unlisted<'a>(&self, root: &Path, paths: &[&'a Path]) -> Vec<&'a Path> {
+        // Paths are hashed and compared by their components, which ignores `.` components, so
+        // `./a` and `a` are the same file.
+        let listed: HashSet<_> = self
+            .entries
+            .iter()
+            .map(|entry| root.join(&entry.path))
+            .collect();
+
+        paths
+            .iter()
+            .filter(|path| !listed.contains(&root.join(path)))
+            .copied()
+            .collect()
+    }
+}
+
+fn check_entry(entry: &ManifestEntry, root: &Path, read_options: ReadOptions) -> CheckStatus {
+    let hash = PathHash::new(root.join(&entry.path)).and_then(|ph| {
+        let mut ph = ph
+            .with_algorithm(entry.algorithm)
+            .with_read_options(read_options);
+        ph.compute_hash()?;
+        Ok(ph.hash().map(<[u8]>::to_vec))
+    });
+
+    match hash {
+        Ok(hash) if hash.as_deref() == Some(&entry.hash[..]) => CheckStatus::Ok,
+        Ok(_) => CheckStatus::Failed,
+        Err(DirHashError::Io(e)) if e.kind() == io::ErrorKind::NotFound => CheckStatus::Missing,
+        Err(_) => CheckStatus::Unreadable,
+    }
+}
+
+impl FromStr for Manifest {
+    type Err = DirHashError;
+
+    /// Parses a checksum file with hashes computed by the default algorithm.
+    fn from_str(s: &str) -> Result<Self> {
+        Self::from_reader(s.as_bytes(), HashAlgorithm::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::ffi::OsStrExt};
+
+    use super::*;
+    use crate::hashtable::HashTableEntry;
+
+    const HASH_A: &str = "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb";
+
+    #[test]
+    fn parse_gnu() {
+        let entry = ManifestEntry::parse(&format!("{HASH_A}  ./a b"), 1, HashAlgorithm::Sha256)
+            .expect("Can't parse line");
+        assert_eq!(entry.algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(hex::encode(entry.hash()), HASH_A);
+        assert_eq!(entry.path(), "./a b");
+        assert!(!entry.binary());
+
+        let entry = ManifestEntry::parse(&format!("{HASH_A} *a"), 1, HashAlgorithm::Sha256)
+            .expect("Can't parse line");
+        assert_eq!(entry.path(), "a");
+        assert!(entry.binary());
+    }
+
+    #[test]
+    fn parse_non_utf8() {
+        // As written by sha256sum, escaped or not
+        for (line, path) in [
+            (
+                [format!("{HASH_A}  ./a").as_bytes(), b"\xff"].concat(),
+                &b"./a\xff"[..],
+            ),
+            (
+                [b"SHA256 (a\xff", format!(") = {HASH_A}").as_bytes()].concat(),
+                &b"a\xff"[..],
+            ),
+            (
+                [b"\\", format!("{HASH_A}  a\\nb").as_bytes(), b"\xff"].concat(),
+                &b"a\nb\xff"[..],
+            ),
+        ] {
+            let entry =
+                ManifestEntry::parse(&line, 1, HashAlgorithm::Sha256).expect("Can't parse line");
+            assert_eq!(entry.path().as_bytes(), path);
+        }
+
+        let text = [
+            format!("{HASH_A}  a\n").as_bytes(),
+            format!("{HASH_A}  b").as_bytes(),
+            b"\x80\r\n",
+        ]
+        .concat();
+        let manifest =
+            Manifest::from_reader(&text[..], HashAlgorithm::Sha256).expect("Can't parse manifest");
+        assert_eq!(manifest.entries()[1].path().as_bytes(), b"b\x80");
+        assert_eq!(
+            manifest.to_lines(ManifestFormat::Gnu),
+            format!("{HASH_A}  a\n\\{HASH_A}  b\\x80\n")
+        );
+    }
+
+    #[test]
+    fn parse_bsd() {
+        let line = format!("SHA256 (dir/a (1)) = b) = {HASH_A}");
+        let entry = ManifestEntry::parse(&line, 1, HashAlgorithm::Md5).expect("Can't parse line");
+        assert_eq!(entry.algorithm(), HashAlgorithm::Sha256);
+        assert_eq!(entry.path(), "dir/a (1)) = b");
+
+        let line = format!("BLAKE2b (a) = {}", "00".repeat(64));
+        let entry = ManifestEntry::parse(&line, 1, HashAlgorithm::Sha256).unwrap();
+        assert_eq!(entry.algorithm(), HashAlgorithm::Blake2b);
+    }
+
+    #[test]
+    fn parse_malformed() {
+        for line in [
+            String::from("no-separator"),
+            format!("{HASH_A} -a"),
+            format!("{HASH_A}  "),
+            String::from("xyz  a"),
+            format!("{}  a", &HASH_A[2..]),
+            format!("SHA3 (a) = {HASH_A}"),
+            format!("MD5 (a) = {HASH_A}"),
+        ] {
+            let err = ManifestEntry::parse(&line, 7, HashAlgorithm::Sha256).unwrap_err();
+            assert!(
+                matches!(err, DirHashError::ParseManifest(7, _)),
+                "{line}: {err:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let text = format!("{HASH_A}  ./a\n{HASH_A} *b\n");
+        let manifest: Manifest = text.parse().expect("Can't parse manifest");
+        assert_eq!(manifest.to_lines(ManifestFormat::Gnu), text);
+        assert_eq!(
+            manifest.to_lines(ManifestFormat::Bsd),
+            format!("SHA256 (./a) = {HASH_A}\nSHA256 (b) = {HASH_A}\n")
+        );
+
+        let bsd: Manifest = manifest
+            .to_lines(ManifestFormat::Bsd)
+            .parse()
+            .expect("Can't parse manifest");
+        assert_eq!(bsd.entries()[0], manifest.entries()[0]);
+    }
+
+    #[test]
+    fn from_hashtable_only_files() {
+        let mut ht = HashTable::new();
+        ht.add(HashTableEntry::new(hex::decode(HASH_A).unwrap(), "./a").unwrap());
+        ht.add(
+            HashTableEntry::new(hex::decode(HASH_A).unwrap(), "./link")
+                .unwrap()
+                .with_kind(EntryKind::Symlink),
+        );
+
+        let manifest = Manifest::from_hashtable(&ht, HashAlgorithm::Sha256);
+        assert_eq!(
+            manifest.to_lines(ManifestFormat::Gnu),
+            format!("{HASH_A}  ./a\n")
+        );
+    }
+
+    #[test]
+    fn check_and_unlisted() {
+        let dir = tempfile::tempdir().expect("Can't create tempdir");
+        fs::write(dir.path().join("a"), "a").unwrap();
+        fs::write(dir.path().join("b"), "modified").unwrap();
+        fs::write(dir.path().join("c"), "c").unwrap();
+        fs::create_dir(dir.path().join("d")).unwrap();
+
+        let manifest: Manifest =
+            format!("{HASH_A}  ./a\n{HASH_A}  b\n{HASH_A}  missing\n{HASH_A}  d\n")
+                .parse()
+                .unwrap();
+
+        let statuses: Vec<_> = manifest
+            .check(dir.path(), ReadOptions::default())
+            .into_iter()
+            .map(|(entry, status)| (entry.path().to_str().unwrap(), status))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("./a", CheckStatus::Ok),
+                ("b", CheckStatus::Failed),
+                ("missing", CheckStatus::Missing),
+                ("d", CheckStatus::Unreadable),
+            ]
+        );
+
+        let paths = [Path::new("a"), Path::new("b"), Path::new("c")];
+        assert_eq!(manifest.unlisted(dir.path(), &paths), [Path::new("c")]);
+    }
+
+    #[test]
+    fn unlisted_dot_components() {
+        let root = Path::new("/root");
+        let manifest: Manifest = format!("{HASH_A}  ./a\n{HASH_A}  d/./b\n{HASH_A}  /root/c\n")
+            .parse()
+            .unwrap();
+
+        let paths = [
+            Path::new("a"),
+            Path::new("./d/b"),
+            Path::new("c"),
+            Path::new("./e"),
+            Path::new("/root/f"),
+        ];
+        assert_eq!(
+            manifest.unlisted(root, &paths),
+            [Path::new("./e"), Path::new("/root/f")]
+        );
+    }
+}
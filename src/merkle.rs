@@ -0,0 +1,360 @@
+use std::collections::BTreeMap;
+
+use crate::algorithm::HashAlgorithm;
+use crate::error::{DirHashError, Result};
+use crate::escape::unescape_path;
+use crate::hashtable::{EntryKind, HashTable, HashTableEntry};
+
+/// Node of a [`MerkleTree`]. Children are named as escaped in the hashtable, see
+/// [`crate::escape::escape_path()`].
+#[derive(Clone, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+pub enum MerkleNode {
+    /// Entry of the hashtable, with its name as path
+    Leaf(HashTableEntry),
+    /// Directory with its subtree hash and children by name
+    Dir {
+        hash: Vec<u8>,
+        children: BTreeMap<String, MerkleNode>,
+    },
+}
+
+impl MerkleNode {
+    pub fn hash(&self) -> &[u8] {
+        match self {
+            MerkleNode::Leaf(entry) => entry.hash(),
+            MerkleNode::Dir { hash, .. } => hash,
+        }
+    }
+
+    fn children(&self) -> Option<&BTreeMap<String, MerkleNode>> {
+        match self {
+            MerkleNode::Leaf(_) => None,
+            MerkleNode::Dir { children, .. } => Some(children),
+        }
+    }
+
+    // Line of the node in the listing of its parent directory. Escaped names always contain a
+    // backslash, which marks the line like in the hashtable.
+    fn listing_line(&self, name: &str) -> String {
+        match self {
+            MerkleNode::Leaf(entry) => entry.to_string(),
+            MerkleNode::Dir { hash, .. } => format!(
+                "{}{} {}  {name}/",
+                if name.contains('\\') { "\\" } else { "" },
+                hex::encode(hash),
+                EntryKind::Dir.marker().unwrap()
+            ),
+        }
+    }
+}
+
+/// Tree of subtree hashes built from a [`HashTable`].
+///
+/// The hash of a directory is the digest of the listing of its children, sorted by name, in the
+/// format of the hashtable (with the name instead of the path):
+///
+/// ```text
+/// <hash>  file
+/// <hash> symlink  link
+/// <hash> dir  subdir/
+/// ```
+///
+/// Directory markers ([`EntryKind::Dir`]) only make sure that the (possibly empty) directory is
+/// part of the tree.
+#[derive(Clone, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+pub struct MerkleTree {
+    algorithm: HashAlgorithm,
+    root: MerkleNode,
+}
+
+/// Difference between two [`MerkleTree`]s. Only subtrees with differing hashes are descended into,
+/// so added or removed directories are listed as a whole.
+///
+/// All lists contain relative paths (directories with a trailing `/`), escaped like in the
+/// hashtable and sorted by path.
+#[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+pub struct MerkleDiff {
+    /// Paths only present in the new tree.
+    pub added: Vec<String>,
+    /// Paths only present in the old tree.
+    pub removed: Vec<String>,
+    /// Entries present in both trees, but with different hashes (or a file that became a directory
+    /// and vice versa).
+    pub modified: Vec<String>,
+}
+
+impl MerkleDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+}
+
+// Directory while building the tree, before its hash is known.
+#[derive(Default)]
+struct DirBuilder {
+    dirs: BTreeMap<String, DirBuilder>,
+    leaves: BTreeMap<String, HashTableEntry>,
+}
+
+impl MerkleTree {
+    /// Builds the tree from the paths of `hashtable`, which are either relative (`./a/b`) or
+    /// absolute. `algorithm` must be the one used for the hashtable.
+    ///
+    /// Returns a [`DirHashError::MerkleTree`] if a path is used for a file and a directory.
+    pub fn new(hashtable: &HashTable, algorithm: HashAlgorithm) -> Result<Self> {
+        let mut root = DirBuilder::default();
+
+        for entry in hashtable.entries() {
+            let path = entry.escaped_path();
+            let mut components = split_path(&path);
+            let name = match entry.kind() {
+                EntryKind::Dir => None,
+                _ => components.pop(),
+            };
+
+            let mut dir = &mut root;
+            for (i, component) in components.iter().enumerate() {
+                if dir.leaves.contains_key(*component) {
+                    return Err(DirHashError::MerkleTree(components[..=i].join("/")));
+                }
+                dir = dir.dirs.entry(component.to_string()).or_default();
+            }
+
+            if let Some(name) = name {
+                if dir.dirs.contains_key(name) {
+                    components.push(name);
+                    return Err(DirHashError::MerkleTree(components.join("/")));
+                }
+                let unescaped = unescape_path(name).expect("Escaped path can't be unescaped");
+                let leaf = HashTableEntry::for_algorithm(algorithm, entry.hash(), unescaped)?
+                    .with_kind(entry.kind())
+                    .with_metadata(entry.metadata().clone());
+                dir.leaves.insert(name.to_owned(), leaf);
+            }
+        }
+
+        Ok(Self {
+            algorithm,
+            root: build_node(root, algorithm),
+        })
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn root(&self) -> &MerkleNode {
+        &self.root
+    }
+
+    /// Returns the hash of the root directory.
+    pub fn hash(&self) -> &[u8] {
+        self.root.hash()
+    }
+
+    /// Returns the node at `path` (relative to the root, e.g. `./a/b`, `a/b/` or `.` for the root).
+    pub fn node(&self, path: &str) -> Option<&MerkleNode> {
+        split_path(path)
+            .into_iter()
+            .try_fold(&self.root, |node, name| node.children()?.get(name))
+    }
+
+    /// Returns the hash of the file or directory at `path`. See [`MerkleTree::node()`].
+    pub fn subtree_hash(&self, path: &str) -> Option<&[u8]> {
+        self.node(path).map(MerkleNode::hash)
+    }
+
+    /// Returns the hashes of all directories (including the root), depth-first and sorted by name.
+    /// The paths are relative and end with a `/`.
+    pub fn subtree_hashes(&self) -> Vec<(String, &[u8])> {
+        let mut hashes = vec![];
+        collect_subtree_hashes(&self.root, String::from("./"), &mut hashes);
+        hashes
+    }
+
+    /// Compares the trees, descending only into subtrees with different hashes.
+    pub fn diff(&self, new: &MerkleTree) -> MerkleDiff {
+        let mut diff = MerkleDiff::default();
+        diff_nodes(&self.root, &new.root, "./", &mut diff);
+        diff.added.sort();
+        diff.removed.sort();
+        diff.modified.sort();
+        diff
+    }
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect()
+}
+
+fn build_node(dir: DirBuilder, algorithm: HashAlgorithm) -> MerkleNode {
+    let mut children: BTreeMap<String, MerkleNode> = dir
+        .dirs
+        .into_iter()
+        .map(|(name, dir)| (name, build_node(dir, algorithm)))
+        .collect();
+    children.extend(
+        dir.leaves
+            .into_iter()
+            .map(|(name, entry)| (name, MerkleNode::Leaf(entry))),
+    );
+
+    let listing: String = children
+        .iter()
+        .map(|(name, node)| node.listing_line(name) + "\n")
+        .collect();
+
+    MerkleNode::Dir {
+        hash: algorithm.digest(listing),
+        children,
+    }
+}
+
+fn collect_subtree_hashes<'a>(
+    node: &'a MerkleNode,
+    path: String,
+    hashes: &mut Vec<(String, &'a [u8])>,
+) {
+    if let MerkleNode::Dir { hash, children } = node {
+        hashes.push((path.clone(), hash));
+        for (name, child) in children {
+            collect_subtree_hashes(child, format!("{path}{name}/"), hashes);
+        }
+    }
+}
+
+// Compares the directories `old` and `new` found at `path` (ending with a `/`).
+fn diff_nodes(old: &MerkleNode, new: &MerkleNode, path: &str, diff: &mut MerkleDiff) {
+    let (Some(old_children), Some(new_children)) = (old.children(), new.children()) else {
+        return;
+    };
+
+    let display_path = |name: &str, node: &MerkleNode| match node {
+        MerkleNode::Leaf(_) => format!("{path}{name}"),
+        MerkleNode::Dir { .. } => format!("{path}{name}/"),
+    };
+
+    for (name, old_child) in old_children {
+        match new_children.get(name) {
+            None => diff.removed.push(display_path(name, old_child)),
+            Some(new_child) if old_child.listing_line(name) != new_child.listing_line(name) => {
+                match (old_child, new_child) {
+                    (MerkleNode::Dir { .. }, MerkleNode::Dir { .. }) => {
+                        diff_nodes(old_child, new_child, &format!("{path}{name}/"), diff)
+                    }
+                    _ => diff.modified.push(display_path(name, new_child)),
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    for (name, new_child) in new_children {
+        if !old_children.contains_key(name) {
+            diff.added.push(display_path(name, new_child));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashtable(entries: &[(&str, &str)]) -> HashTable {
+        let mut ht = HashTable::new();
+        for (content, path) in entries {
+            ht.add(HashTableEntry::new(HashAlgorithm::Sha256.digest(content), *path).unwrap());
+        }
+        ht.sort();
+        ht
+    }
+
+    fn digest(listing: &str) -> Vec<u8> {
+        HashAlgorithm::Sha256.digest(listing)
+    }
+
+    #[test]
+    fn subtree_hashes() {
+        let ht = hashtable(&[("0", "./0"), ("1", "./a/1"), ("2", "./a/b/2")]);
+        let tree = MerkleTree::new(&ht, HashAlgorithm::Sha256).expect("Can't build MerkleTree");
+
+        let hash_0 = hex::encode(digest("0"));
+        let hash_1 = hex::encode(digest("1"));
+        let hash_2 = hex::encode(digest("2"));
+
+        let hash_b = digest(&format!("{hash_2}  2\n"));
+        let hash_a = digest(&format!("{hash_1}  1\n{} dir  b/\n", hex::encode(&hash_b)));
+        let hash_root = digest(&format!("{hash_0}  0\n{} dir  a/\n", hex::encode(&hash_a)));
+
+        assert_eq!(tree.hash(), hash_root);
+        assert_eq!(tree.subtree_hash("."), Some(&hash_root[..]));
+        assert_eq!(tree.subtree_hash("./a"), Some(&hash_a[..]));
+        assert_eq!(tree.subtree_hash("a/b/"), Some(&hash_b[..]));
+        assert_eq!(tree.subtree_hash("./a/b/2"), Some(&digest("2")[..]));
+        assert_eq!(tree.subtree_hash("./c"), None);
+        assert_eq!(tree.subtree_hash("./0/x"), None);
+
+        assert_eq!(
+            tree.subtree_hashes(),
+            vec![
+                (String::from("./"), &hash_root[..]),
+                (String::from("./a/"), &hash_a[..]),
+                (String::from("./a/b/"), &hash_b[..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn absolute_paths_and_dir_markers() {
+        let mut ht = hashtable(&[("0", "/x/0")]);
+        ht.add(
+            HashTableEntry::new(digest(""), "/x/empty/")
+                .unwrap()
+                .with_kind(EntryKind::Dir),
+        );
+        let tree = MerkleTree::new(&ht, HashAlgorithm::Sha256).expect("Can't build MerkleTree");
+
+        // An empty directory hashes an empty listing
+        assert_eq!(tree.subtree_hash("/x/empty"), Some(&digest("")[..]));
+        assert!(tree.subtree_hash("/x/0").is_some());
+    }
+
+    #[test]
+    fn file_and_dir_conflict() {
+        let ht = hashtable(&[("0", "./a"), ("1", "./a/b")]);
+        let err = MerkleTree::new(&ht, HashAlgorithm::Sha256).unwrap_err();
+        assert!(matches!(err, DirHashError::MerkleTree(path) if path == "a"));
+    }
+
+    #[test]
+    fn diff() {
+        let old = hashtable(&[
+            ("0", "./0"),
+            ("1", "./same/1"),
+            ("2", "./changed/2"),
+            ("3", "./changed/3"),
+            ("4", "./removed/4"),
+            ("5", "./removed/deep/5"),
+        ]);
+        let new = hashtable(&[
+            ("0", "./0"),
+            ("1", "./same/1"),
+            ("2", "./changed/2"),
+            ("modified", "./changed/3"),
+            ("new", "./changed/new"),
+            ("6", "./added/6"),
+        ]);
+
+        let old = MerkleTree::new(&old, HashAlgorithm::Sha256).expect("Can't build MerkleTree");
+        let new = MerkleTree::new(&new, HashAlgorithm::Sha256).expect("Can't build MerkleTree");
+
+        assert!(old.diff(&old).is_empty());
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added, ["./added/", "./changed/new"]);
+        assert_eq!(diff.removed, ["./removed/"]);
+        assert_eq!(diff.modified, ["./changed/3"]);
+    }
+}
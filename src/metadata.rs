@@ -0,0 +1,374 @@
+use std::{fmt::Display, fs, os::unix::fs::MetadataExt, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::algorithm::HashAlgorithm;
+use crate::error::Result;
+
+/// Selects the file metadata added to each [`crate::hashtable::HashTableEntry`] (and thus hashed
+/// along with the file contents). Nothing is selected by default.
+///
+/// Only the selected attributes are serialized.
+#[derive(
+    Clone, Copy, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize,
+)]
+pub struct MetadataOptions {
+    /// Permission bits (including setuid, setgid and sticky bit)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mode: bool,
+    /// Numeric user and group ID
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub owner: bool,
+    /// Modification time with nanosecond resolution
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mtime: bool,
+    /// File size in bytes
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub size: bool,
+    /// Names and values of the extended attributes (as a single digest)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub xattrs: bool,
+}
+
+impl MetadataOptions {
+    /// Returns `true` if no attribute is selected.
+    pub fn is_empty(&self) -> bool {
+        !(self.mode || self.owner || self.mtime || self.size || self.xattrs)
+    }
+}
+
+/// Metadata of a single file. Only the attributes selected by [`MetadataOptions`] are set.
+///
+/// Each attribute is displayed as a `<name>=<value>` column, separated by single spaces:
+///
+/// ```text
+/// mode=0755 owner=1000:1000 mtime=1700000000.123456789 size=42 xattrs=<hex digest>
+/// ```
+///
+/// Serialized as an object of the set attributes, with the owner and mtime as two-element arrays
+/// and the xattrs digest as hex string.
+#[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
+pub struct FileMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    /// (uid, gid)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<(u32, u32)>,
+    /// (seconds, nanoseconds) since the Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<(i64, u32)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// Digest of all extended attributes, sorted by name. Each attribute contributes its name, a
+    /// NUL byte, the length of its value (`u64`, little endian) and the value.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "optional_hex"
+    )]
+    pub xattrs: Option<Vec<u8>>,
+}
+
+impl FileMetadata {
+    /// Reads the attributes selected by `options` from the file at `path`, following symlinks like
+    /// when reading the file contents. The extended attributes are digested with `algorithm`.
+    pub fn read(path: &Path, options: &MetadataOptions, algorithm: HashAlgorithm) -> Result<Self> {
+        Self::read_impl(path, options, algorithm, true)
+    }
+
+    /// Same as [`FileMetadata::read()`], but reads the attributes of the symlink itself.
+    pub fn read_symlink(
+        path: &Path,
+        options: &MetadataOptions,
+        algorithm: HashAlgorithm,
+    ) -> Result<Self> {
+        Self::read_impl(path, options, algorithm, false)
+    }
+
+    fn read_impl(
+        path: &Path,
+        options: &MetadataOptions,
+        algorithm: HashAlgorithm,
+        deref: bool,
+    ) -> Result<Self> {
+        if options.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let metadata = match deref {
+            true => fs::metadata(path)?,
+            false => fs::symlink_metadata(path)?,
+        };
+
+        let xattrs = match options.xattrs {
+            true => Some(xattrs_digest(path, algorithm, deref)?),
+            false => None,
+        };
+
+        Ok(Self {
+            mode: options.mode.then(|| metadata.mode() & 0o7777),
+            owner: options.owner.then(|| (metadata.uid(), metadata.gid())),
+            mtime: options
+                .mtime
+                .then(|| (metadata.mtime(), metadata.mtime_nsec() as u32)),
+            size: options.size.then(|| metadata.size()),
+            xattrs,
+        })
+    }
+
+    /// Returns `true` if no attribute is set.
+    pub fn is_empty(&self) -> bool {
+        self.columns().is_empty()
+    }
+
+    /// Returns the set attributes as `<name>=<value>` columns in a fixed order.
+    pub fn columns(&self) -> Vec<String> {
+        let mut columns = vec![];
+
+        if let Some(mode) = self.mode {
+            columns.push(format!("mode={mode:04o}"));
+        }
+        if let Some((uid, gid)) = self.owner {
+            columns.push(format!("owner={uid}:{gid}"));
+        }
+        if let Some((secs, nanos)) = self.mtime {
+            columns.push(format!("mtime={secs}.{nanos:09}"));
+        }
+        if let Some(size) = self.size {
+            columns.push(format!("size={size}"));
+        }
+        if let Some(xattrs) = &self.xattrs {
+            columns.push(format!("xattrs={}", hex::encode(xattrs)));
+        }
+
+        columns
+    }
+
+    /// Parses the space-separated columns in the format of [`Display`]. Returns a description of
+    /// the problem on error.
+    pub(crate) fn parse(columns: &str) -> std::result::Result<Self, String> {
+        let mut metadata = Self::default();
+
+        for column in columns.split(' ') {
+            let (name, value) = column
+                .split_once('=')
+                .ok_or_else(|| format!("malformed metadata column \"{column}\""))?;
+            let invalid = || format!("invalid {name} \"{value}\"");
+
+            match name {
+                "mode" => {
+                    metadata.mode = Some(u32::from_str_radix(value, 8).map_err(|_| invalid())?)
+                }
+                "owner" => {
+                    let (uid, gid) = value.split_once(':').ok_or_else(invalid)?;
+                    metadata.owner = Some((
+                        uid.parse().map_err(|_| invalid())?,
+                        gid.parse().map_err(|_| invalid())?,
+                    ));
+                }
+                "mtime" => {
+                    let (secs, nanos) = value.split_once('.').ok_or_else(invalid)?;
+                    if nanos.len() != 9 {
+                        return Err(invalid());
+                    }
+                    metadata.mtime = Some((
+                        secs.parse().map_err(|_| invalid())?,
+                        nanos.parse().map_err(|_| invalid())?,
+                    ));
+                }
+                "size" => metadata.size = Some(value.parse().map_err(|_| invalid())?),
+                "xattrs" => metadata.xattrs = Some(hex::decode(value).map_err(|_| invalid())?),
+                _ => return Err(format!("unknown metadata column \"{name}\"")),
+            }
+        }
+
+        Ok(metadata)
+    }
+}
+
+impl Display for FileMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.columns().join(" "))
+    }
+}
+
+// Serializes an optional digest as hex string (see `hex::serde`).
+mod optional_hex {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        bytes: &Option<Vec<u8>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => hex::serde::serialize(bytes, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| hex::decode(s).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+fn xattrs_digest(path: &Path, algorithm: HashAlgorithm, deref: bool) -> Result<Vec<u8>> {
+    let mut names: Vec<_> = match deref {
+        true => xattr::list_deref(path)?.collect(),
+        false => xattr::list(path)?.collect(),
+    };
+    names.sort();
+
+    let mut hasher = algorithm.hasher();
+    for name in names {
+        let value = match deref {
+            true => xattr::get_deref(path, &name)?,
+            false => xattr::get(path, &name)?,
+        };
+        // The attribute might have been removed in the meantime.
+        let value = value.unwrap_or_default();
+        hasher.update(name.as_encoded_bytes());
+        hasher.update(&[0]);
+        hasher.update(&(value.len() as u64).to_le_bytes());
+        hasher.update(&value);
+    }
+
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    #[test]
+    fn nothing_selected() {
+        let file = NamedTempFile::new().expect("Can't create tempfile");
+        let metadata = FileMetadata::read(
+            file.path(),
+            &MetadataOptions::default(),
+            HashAlgorithm::Sha256,
+        )
+        .expect("Can't read metadata");
+        assert!(metadata.is_empty());
+        assert_eq!(metadata.to_string(), "");
+    }
+
+    #[test]
+    fn read_selected() {
+        let file = NamedTempFile::new().expect("Can't create tempfile");
+        fs::write(file.path(), b"four").expect("Can't write to tempfile");
+        fs::set_permissions(file.path(), fs::Permissions::from_mode(0o4750))
+            .expect("Can't set permissions");
+
+        let options = MetadataOptions {
+            mode: true,
+            size: true,
+            ..Default::default()
+        };
+        let metadata = FileMetadata::read(file.path(), &options, HashAlgorithm::Sha256)
+            .expect("Can't read metadata");
+        assert_eq!(metadata.mode, Some(0o4750));
+        assert_eq!(metadata.size, Some(4));
+        assert!(metadata.owner.is_none());
+        assert!(metadata.mtime.is_none());
+        assert!(metadata.xattrs.is_none());
+        assert_eq!(metadata.to_string(), "mode=4750 size=4");
+    }
+
+    #[test]
+    fn xattrs_without_attributes() {
+        let file = NamedTempFile::new().expect("Can't create tempfile");
+        let options = MetadataOptions {
+            xattrs: true,
+            ..Default::default()
+        };
+        let metadata = FileMetadata::read(file.path(), &options, HashAlgorithm::Md5)
+            .expect("Can't read metadata");
+        assert_eq!(metadata.xattrs, Some(HashAlgorithm::Md5.digest("")));
+    }
+
+    #[test]
+    fn read_symlink() {
+        let dir = tempfile::tempdir().expect("Can't create tempdir");
+        fs::write(dir.path().join("target"), b"contents").expect("Can't write to file");
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink("target", &link).expect("Error while creating symlink");
+
+        let options = MetadataOptions {
+            size: true,
+            ..Default::default()
+        };
+        let followed = FileMetadata::read(&link, &options, HashAlgorithm::Sha256)
+            .expect("Can't read metadata");
+        assert_eq!(followed.size, Some(8));
+        let not_followed = FileMetadata::read_symlink(&link, &options, HashAlgorithm::Sha256)
+            .expect("Can't read metadata");
+        assert_eq!(not_followed.size, Some(6));
+    }
+
+    #[test]
+    fn display_and_parse() {
+        let metadata = FileMetadata {
+            mode: Some(0o644),
+            owner: Some((1000, 100)),
+            mtime: Some((1700000000, 1234)),
+            size: Some(42),
+            xattrs: Some(vec![0xab; 4]),
+        };
+        let columns = "mode=0644 owner=1000:100 mtime=1700000000.000001234 size=42 xattrs=abababab";
+        assert_eq!(metadata.to_string(), columns);
+        assert_eq!(FileMetadata::parse(columns), Ok(metadata));
+
+        assert_eq!(
+            FileMetadata::parse("mtime=-1.000000005"),
+            Ok(FileMetadata {
+                mtime: Some((-1, 5)),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_malformed() {
+        for columns in [
+            "",
+            "mode",
+            "mode=0999",
+            "owner=1000",
+            "mtime=1700000000",
+            "mtime=0.5",
+            "mtime=0.1000000000",
+            "size=-1",
+            "xattrs=xyz",
+            "color=blue",
+        ] {
+            assert!(FileMetadata::parse(columns).is_err(), "{columns:?}");
+        }
+    }
+
+    #[test]
+    fn serialize_options() {
+        let json = serde_json::to_string(&MetadataOptions::default())
+            .expect("Can't serialize MetadataOptions");
+        assert_eq!(json, "{}");
+
+        let options = MetadataOptions {
+            mode: true,
+            xattrs: true,
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&options).expect("Can't serialize MetadataOptions");
+        assert_eq!(json, r#"{"mode":true,"xattrs":true}"#);
+        assert_eq!(
+            serde_json::from_str::<MetadataOptions>(&json).expect("Can't deserialize"),
+            options
+        );
+    }
+}
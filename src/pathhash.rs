@@ -1,25 +1,70 @@
 use std::{
-    fs, io,
+    fs::{self, File},
+    io::{self, Read},
     os::unix::fs::FileTypeExt,
     path::{Path, PathBuf},
 };
 
-use sha2::{Digest, Sha256};
+use memmap2::Mmap;
+use tracing::debug;
 
+use crate::algorithm::HashAlgorithm;
 use crate::error::{DirHashError, InvalidFileTypeKind, Result};
+use crate::hashtable::EntryKind;
+use crate::metadata::{FileMetadata, MetadataOptions};
 
 // TODO: Rename this!!
 pub trait PathHashProvider {
     fn path(&self) -> &Path;
-    fn hash(&self) -> Option<&[u8; 32]>;
+    fn hash(&self) -> Option<&[u8]>;
     fn compute_hash(&mut self) -> Result<()>;
+
+    /// Returns the file metadata added to the hashtable entry. Providers without metadata don't
+    /// need to implement this.
+    fn metadata(&self) -> Option<&FileMetadata> {
+        None
+    }
+
+    /// Returns what the hash was computed from. Defaults to [`EntryKind::File`].
+    fn kind(&self) -> EntryKind {
+        EntryKind::File
+    }
+}
+
+/// Controls how the file contents are read when computing the hash.
+///
+/// Files are streamed through a buffer of `buffer_size` bytes, so the memory usage doesn't depend
+/// on the file size. If `mmap_threshold` is set, files of at least this size are memory-mapped
+/// instead.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
+pub struct ReadOptions {
+    pub buffer_size: usize,
+    pub mmap_threshold: Option<u64>,
+}
+
+impl ReadOptions {
+    pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            buffer_size: Self::DEFAULT_BUFFER_SIZE,
+            mmap_threshold: None,
+        }
+    }
 }
 
 /// Struct containing a path and hash from a file on the filesystem.
 #[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
 pub struct PathHash {
     path: PathBuf,
-    hash: Option<[u8; 32]>,
+    kind: EntryKind,
+    hash: Option<Vec<u8>>,
+    algorithm: HashAlgorithm,
+    read_options: ReadOptions,
+    metadata_options: MetadataOptions,
+    metadata: Option<FileMetadata>,
 }
 
 impl PathHash {
@@ -76,31 +121,194 @@ impl PathHash {
 
         Ok(PathHash {
             path: path.as_ref().to_owned(),
+            kind: EntryKind::File,
+            hash: Default::default(),
+            algorithm: Default::default(),
+            read_options: Default::default(),
+            metadata_options: Default::default(),
+            metadata: Default::default(),
+        })
+    }
+
+    /// Creates a [`PathHash`] from a path to a symlink, whose hash is computed from the target path
+    /// of the link (without following it).
+    ///
+    /// Returns an [`DirHashError::Io`] if the path isn't absolute or isn't a symlink.
+    pub fn symlink(path: impl AsRef<Path>) -> Result<Self> {
+        if !path.as_ref().is_absolute() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path not absolute").into());
+        }
+
+        if !fs::symlink_metadata(&path)?.file_type().is_symlink() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path not a symlink").into());
+        }
+
+        Ok(PathHash {
+            path: path.as_ref().to_owned(),
+            kind: EntryKind::Symlink,
             hash: Default::default(),
+            algorithm: Default::default(),
+            read_options: Default::default(),
+            metadata_options: Default::default(),
+            metadata: Default::default(),
         })
     }
+
+    /// Creates a [`PathHash`] marking a directory (see [`EntryKind::Dir`]).
+    ///
+    /// Returns an [`DirHashError::Io`] if the path isn't absolute or isn't a directory.
+    pub fn directory(path: impl AsRef<Path>) -> Result<Self> {
+        if !path.as_ref().is_absolute() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path not absolute").into());
+        }
+
+        if !fs::metadata(&path)?.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path not a directory").into());
+        }
+
+        Ok(PathHash {
+            path: path.as_ref().to_owned(),
+            kind: EntryKind::Dir,
+            hash: Default::default(),
+            algorithm: Default::default(),
+            read_options: Default::default(),
+            metadata_options: Default::default(),
+            metadata: Default::default(),
+        })
+    }
+
+    /// Sets the algorithm used by [`PathHashProvider::compute_hash()`]. Defaults to
+    /// [`HashAlgorithm::Sha256`].
+    pub fn with_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// Sets how the file is read by [`PathHashProvider::compute_hash()`]. See [`ReadOptions`].
+    pub fn with_read_options(mut self, read_options: ReadOptions) -> Self {
+        self.read_options = read_options;
+        self
+    }
+
+    pub fn read_options(&self) -> ReadOptions {
+        self.read_options
+    }
+
+    /// Sets the file metadata read by [`PathHashProvider::compute_hash()`]. See
+    /// [`MetadataOptions`].
+    pub fn with_metadata_options(mut self, metadata_options: MetadataOptions) -> Self {
+        self.metadata_options = metadata_options;
+        self
+    }
+
+    pub fn metadata_options(&self) -> MetadataOptions {
+        self.metadata_options
+    }
+
+    fn hash_streamed(&self, file: &mut File) -> Result<Vec<u8>> {
+        if self.read_options.buffer_size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer size is zero").into());
+        }
+
+        let mut hasher = self.algorithm.hasher();
+        let mut buffer = vec![0; self.read_options.buffer_size];
+
+        loop {
+            match file.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => hasher.update(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(hasher.finalize())
+    }
+
+    fn hash_mmapped(&self, file: &File) -> Result<Vec<u8>> {
+        // SAFETY: The mapping is only read while hashing. If the file gets truncated by another
+        // process in the meantime, reading may fault (SIGBUS), which is the accepted tradeoff for
+        // opting into memory-mapping.
+        let mmap = unsafe { Mmap::map(file)? };
+        Ok(self.algorithm.digest(&mmap[..]))
+    }
 }
 
 impl PathHashProvider for PathHash {
-    /// Computes the SHA256 hash of the contents of the corresponding file and stores it. Calling
-    /// this method again will reread the file and recompute the hash value.
+    /// Computes the hash of the contents of the corresponding file with the configured algorithm
+    /// and stores it. Calling this method again will reread the file and recompute the hash value.
+    ///
+    /// The file is either streamed or memory-mapped, depending on its size and the
+    /// [`ReadOptions`]. The metadata selected by the [`MetadataOptions`] is read as well.
+    ///
+    /// For symlinks created by [`PathHash::symlink()`], the target path is hashed instead, and for
+    /// directories created by [`PathHash::directory()`] no data at all.
     fn compute_hash(&mut self) -> Result<()> {
-        let data = fs::read(&self.path)?;
-        let hash = Sha256::digest(data);
-        self.hash = Some(hash.into());
+        if self.kind == EntryKind::Dir {
+            self.metadata = Some(FileMetadata::read(
+                &self.path,
+                &self.metadata_options,
+                self.algorithm,
+            )?);
+            self.hash = Some(self.algorithm.digest(""));
+            return Ok(());
+        }
+
+        if self.kind == EntryKind::Symlink {
+            let target = fs::read_link(&self.path)?;
+            self.metadata = Some(FileMetadata::read_symlink(
+                &self.path,
+                &self.metadata_options,
+                self.algorithm,
+            )?);
+            self.hash = Some(self.algorithm.digest(target.as_os_str().as_encoded_bytes()));
+            return Ok(());
+        }
+
+        let mut file = File::open(&self.path)?;
+        let len = file.metadata()?.len();
+
+        // Empty files can't be mapped.
+        let hash = match self.read_options.mmap_threshold {
+            Some(threshold) if len > 0 && len >= threshold => {
+                debug!("Memory-mapping {:?} ({} bytes)", self.path, len);
+                self.hash_mmapped(&file)?
+            }
+            _ => self.hash_streamed(&mut file)?,
+        };
+
+        self.metadata = Some(FileMetadata::read(
+            &self.path,
+            &self.metadata_options,
+            self.algorithm,
+        )?);
+        self.hash = Some(hash);
         Ok(())
     }
 
     /// Returns the stored hash of the file contents. If `None`, use [`Self::compute_hash()`] to compute the
     /// hash value.
-    fn hash(&self) -> Option<&[u8; 32]> {
-        self.hash.as_ref()
+    fn hash(&self) -> Option<&[u8]> {
+        self.hash.as_deref()
     }
 
     /// Returns the stored path.
     fn path(&self) -> &Path {
         &self.path
     }
+
+    /// Returns the metadata read along with the hash.
+    fn metadata(&self) -> Option<&FileMetadata> {
+        self.metadata.as_ref()
+    }
+
+    fn kind(&self) -> EntryKind {
+        self.kind
+    }
 }
 
 #[cfg(test)]
@@ -540,6 +748,138 @@ mod tests {
     fn compute_hash_multiline() {
         check_compute_hash(TestFileContent::MultiLine);
     }
+
+    #[test]
+    fn compute_hash_small_buffer() {
+        let testfile = get_testfile(TestFileContent::MultiLine);
+        let mut pathhash = PathHash::new(testfile.file.path())
+            .expect("Can't create PathHash from existing file")
+            .with_read_options(ReadOptions {
+                buffer_size: 3,
+                mmap_threshold: None,
+            });
+        assert!(pathhash.compute_hash().is_ok());
+        assert_eq!(*pathhash.hash().unwrap(), testfile.test_vector.hash);
+    }
+
+    #[test]
+    fn compute_hash_zero_buffer() {
+        let testfile = get_testfile(TestFileContent::MultiLine);
+        let mut pathhash = PathHash::new(testfile.file.path())
+            .expect("Can't create PathHash from existing file")
+            .with_read_options(ReadOptions {
+                buffer_size: 0,
+                mmap_threshold: None,
+            });
+
+        match pathhash.compute_hash().unwrap_err() {
+            DirHashError::Io(io_err) => {
+                assert_eq!(io_err.kind(), io::ErrorKind::InvalidInput);
+            }
+            _ => panic!("Wrong enum variant"),
+        }
+        assert!(pathhash.hash().is_none());
+    }
+
+    #[test]
+    fn compute_hash_mmap() {
+        for content in [
+            TestFileContent::Empty,
+            TestFileContent::SingleLine,
+            TestFileContent::MultiLine,
+        ] {
+            let testfile = get_testfile(content);
+            let mut pathhash = PathHash::new(testfile.file.path())
+                .expect("Can't create PathHash from existing file")
+                .with_read_options(ReadOptions {
+                    buffer_size: ReadOptions::DEFAULT_BUFFER_SIZE,
+                    mmap_threshold: Some(0),
+                });
+            assert!(pathhash.compute_hash().is_ok());
+            assert_eq!(*pathhash.hash().unwrap(), testfile.test_vector.hash);
+        }
+    }
+
+    #[test]
+    fn compute_hash_with_algorithm() {
+        let testfile = get_testfile(TestFileContent::SingleLine);
+        let mut pathhash = PathHash::new(testfile.file.path())
+            .expect("Can't create PathHash from existing file")
+            .with_algorithm(HashAlgorithm::Sha512);
+        assert_eq!(pathhash.algorithm(), HashAlgorithm::Sha512);
+        assert!(pathhash.compute_hash().is_ok());
+        assert_eq!(
+            hex::encode(pathhash.hash().unwrap()),
+            "fa8c6ada35596bb1972fc959df42fedccf4d31f2e9dec602ddfe82ed00a2563a\
+             72b2482c2f2c3035ab9cfa53335d8c8394c5ecf77eaa0a24352fe6f2d3e46130"
+        );
+    }
+
+    #[test]
+    fn create_and_hash_symlink_as_link() {
+        let dir = tempdir().expect("Can't create tempdir");
+        let datafile_path = dir.path().join("datafile");
+        fs::write(&datafile_path, "test data").expect("Can't write to file");
+
+        let symlink_path = dir.path().join("symlink");
+        unix::fs::symlink("datafile", &symlink_path).expect("Error while creating symlink");
+        let broken_path = dir.path().join("broken");
+        unix::fs::symlink("missing", &broken_path).expect("Error while creating symlink");
+
+        let mut pathhash = PathHash::symlink(&symlink_path).unwrap();
+        assert_eq!(pathhash.kind(), EntryKind::Symlink);
+        assert!(pathhash.compute_hash().is_ok());
+        assert_eq!(
+            pathhash.hash().unwrap(),
+            HashAlgorithm::Sha256.digest("datafile")
+        );
+
+        let mut pathhash = PathHash::symlink(&broken_path).unwrap();
+        assert!(pathhash.compute_hash().is_ok());
+        assert_eq!(
+            pathhash.hash().unwrap(),
+            HashAlgorithm::Sha256.digest("missing")
+        );
+
+        let err = PathHash::symlink(&datafile_path).unwrap_err();
+        assert!(matches!(err, DirHashError::Io(e) if e.kind() == io::ErrorKind::InvalidInput));
+
+        dir.close().expect("Can't close tempdir");
+    }
+
+    #[test]
+    fn create_and_hash_directory() {
+        let dir = tempdir().expect("Can't create tempdir");
+        let file_path = dir.path().join("file");
+        fs::write(&file_path, "test data").expect("Can't write to file");
+
+        let mut pathhash = PathHash::directory(dir.path()).unwrap();
+        assert_eq!(pathhash.kind(), EntryKind::Dir);
+        assert!(pathhash.compute_hash().is_ok());
+        assert_eq!(pathhash.hash().unwrap(), HashAlgorithm::Sha256.digest(""));
+
+        let err = PathHash::directory(&file_path).unwrap_err();
+        assert!(matches!(err, DirHashError::Io(e) if e.kind() == io::ErrorKind::InvalidInput));
+
+        dir.close().expect("Can't close tempdir");
+    }
+
+    #[test]
+    fn compute_hash_with_metadata() {
+        let testfile = get_testfile(TestFileContent::SingleLine);
+        let mut pathhash =
+            PathHash::new(testfile.file.path()).expect("Can't create PathHash from existing file");
+        assert!(pathhash.compute_hash().is_ok());
+        assert_eq!(pathhash.metadata(), Some(&FileMetadata::default()));
+
+        let mut pathhash = pathhash.with_metadata_options(MetadataOptions {
+            size: true,
+            ..Default::default()
+        });
+        assert!(pathhash.compute_hash().is_ok());
+        assert_eq!(*pathhash.hash().unwrap(), testfile.test_vector.hash);
+        assert_eq!(pathhash.metadata().unwrap().size, Some(10));
+    }
 }
 
 #[cfg(any(test, feature = "test-utils"))]
@@ -549,8 +889,8 @@ pub mod pathhashspy {
     #[derive(Clone, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord)]
     pub struct PathHashSpy {
         path: PathBuf,
-        hash: Option<[u8; 32]>,
-        next_hash: Option<[u8; 32]>,
+        hash: Option<Vec<u8>>,
+        next_hash: Option<Vec<u8>>,
         call_count_compute_hash: u32,
     }
 
@@ -559,6 +899,19 @@ pub mod pathhashspy {
             path: impl AsRef<Path>,
             hash: Option<[u8; 32]>,
             next_hash: Option<[u8; 32]>,
+        ) -> Self {
+            Self::with_hash_vecs(
+                path,
+                hash.map(|h| h.to_vec()),
+                next_hash.map(|h| h.to_vec()),
+            )
+        }
+
+        /// Same as [`Self::new()`], but for hashes of arbitrary length (i.e., other algorithms).
+        pub fn with_hash_vecs(
+            path: impl AsRef<Path>,
+            hash: Option<Vec<u8>>,
+            next_hash: Option<Vec<u8>>,
         ) -> Self {
             Self {
                 path: path.as_ref().to_owned(),
@@ -577,17 +930,17 @@ pub mod pathhashspy {
         fn compute_hash(&mut self) -> Result<()> {
             self.call_count_compute_hash += 1;
 
-            match self.next_hash {
+            match &self.next_hash {
                 Some(hash) => {
-                    self.hash = Some(hash);
+                    self.hash = Some(hash.clone());
                     Ok(())
                 }
                 None => panic!("Can't compute next hash (next_hash is None)."),
             }
         }
 
-        fn hash(&self) -> Option<&[u8; 32]> {
-            self.hash.as_ref()
+        fn hash(&self) -> Option<&[u8]> {
+            self.hash.as_deref()
         }
 
         fn path(&self) -> &Path {
@@ -656,7 +1009,7 @@ pub mod pathhashspy {
         if spy.hash().unwrap() != b"01234567890123456789012345678901" {
             return;
         }
-        if &spy.next_hash.unwrap() != b"01234567890123456789012345678901" {
+        if spy.next_hash.as_ref().unwrap() != b"01234567890123456789012345678901" {
             return;
         }
         if spy.call_count_compute_hash() != 1 {
@@ -0,0 +1,73 @@
+use std::{fmt::Debug, path::Path, sync::Arc};
+
+/// Observer of the progress of [`crate::dirhash::DirHash::with_files_from_dir()`] and
+/// [`crate::dirhash::DirHash::compute_hash()`], e.g. to display a progress bar.
+///
+/// With the rayon strategies, the methods are called concurrently from the hashing threads, so
+/// they should return quickly. All methods do nothing by default.
+pub trait Progress: Send + Sync {
+    /// Called for each file (or symlink or directory marker) added while walking a directory.
+    fn file_found(&self, _path: &Path) {}
+
+    /// Called before hashing with the number of files and the total size of the regular files in
+    /// bytes. [`crate::dirhash::DirHash::compute_hash_from_dir()`] hashes while walking, so it
+    /// reports 0 files and bytes before the first file is found.
+    fn hashing_started(&self, _files: usize, _bytes: u64) {}
+
+    /// Called after the file at `path` was hashed (or taken from the cache), with its size in
+    /// bytes (0 for symlinks and directory markers).
+    fn file_hashed(&self, _path: &Path, _bytes: u64) {}
+
+    /// Called after all files were hashed successfully.
+    fn hashing_finished(&self) {}
+}
+
+/// Shared [`Progress`] observer, as stored by [`crate::dirhash::DirHash`].
+///
+/// Observers don't take part in comparisons, so all handles are equal.
+#[derive(Clone)]
+pub struct ProgressHandle(Arc<dyn Progress>);
+
+impl ProgressHandle {
+    pub fn new(progress: Arc<dyn Progress>) -> Self {
+        Self(progress)
+    }
+}
+
+impl std::ops::Deref for ProgressHandle {
+    type Target = dyn Progress;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl Debug for ProgressHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressHandle")
+    }
+}
+
+impl PartialEq for ProgressHandle {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for ProgressHandle {}
+
+impl PartialOrd for ProgressHandle {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ProgressHandle {
+    fn cmp(&self, _other: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl std::hash::Hash for ProgressHandle {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+}
@@ -0,0 +1,420 @@
+use serde::{Deserialize, Serialize};
+
+use crate::filter::FilterOptions;
+
+/// How symlinks found while walking are handled.
+#[derive(
+    Clone, Copy, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize,
+)]
+#[serde(try_from = "SymlinkFields", into = "SymlinkFields")]
+pub enum SymlinkMode {
+    /// Skip symlinks with [`crate::dirhash::IgnoreReason::Symlink`].
+    #[default]
+    Ignore,
+    /// Follow symlinks, i.e. hash the contents of the target (and walk linked directories).
+    Follow,
+    /// Hash symlinks as links, i.e. their target path, without following them.
+    Hash,
+}
+
+// Keeps the "follow_symlinks" field of fingerprints created before symlinks could be hashed.
+#[derive(Serialize, Deserialize)]
+struct SymlinkFields {
+    follow_symlinks: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    hash_symlinks: bool,
+}
+
+impl TryFrom<SymlinkFields> for SymlinkMode {
+    type Error = &'static str;
+
+    fn try_from(fields: SymlinkFields) -> Result<Self, Self::Error> {
+        match (fields.follow_symlinks, fields.hash_symlinks) {
+            (false, false) => Ok(SymlinkMode::Ignore),
+            (true, false) => Ok(SymlinkMode::Follow),
+            (false, true) => Ok(SymlinkMode::Hash),
+            (true, true) => Err("follow_symlinks and hash_symlinks are mutually exclusive"),
+        }
+    }
+}
+
+impl From<SymlinkMode> for SymlinkFields {
+    fn from(mode: SymlinkMode) -> Self {
+        SymlinkFields {
+            follow_symlinks: mode == SymlinkMode::Follow,
+            hash_symlinks: mode == SymlinkMode::Hash,
+        }
+    }
+}
+
+/// Which directories get a marker entry in the hashtable. Without markers, directories only
+/// contribute to the hashtable through the paths of the files they contain.
+#[derive(
+    Clone, Copy, Default, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DirectoryMode {
+    #[default]
+    None,
+    /// Directories without any entries on the filesystem (regardless of what is ignored)
+    Empty,
+    All,
+}
+
+impl DirectoryMode {
+    pub fn is_none(&self) -> bool {
+        *self == DirectoryMode::None
+    }
+}
+
+/// Configures how [`crate::dirhash::DirHash::with_files_from_dir()`] walks a directory.
+///
+/// Serializes to the walk options stored in the metadata of a fingerprint, so a directory can be
+/// walked the same way again when verifying.
+#[derive(Clone, Debug, Hash, PartialEq, PartialOrd, Eq, Ord, Serialize, Deserialize)]
+pub struct WalkConfig {
+    absolute: bool,
+    #[serde(flatten)]
+    symlinks: SymlinkMode,
+    include_hidden_files: bool,
+    ignore_invalid_filetypes: bool,
+    #[serde(default, skip_serializing_if = "DirectoryMode::is_none")]
+    directories: DirectoryMode,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    min_depth: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_depth: Option<usize>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    one_file_system: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    newer: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    older: Option<i64>,
+    #[serde(flatten)]
+    filter: FilterOptions,
+}
+
+fn is_zero(n: &usize) -> bool {
+    *n == 0
+}
+
+/// Relative paths, symlinks are ignored, hidden files are included, invalid file types
+/// result in an error, directories are not marked, the depth isn't limited, mount points are
+/// crossed and nothing is filtered (by path, size or modification time).
+impl Default for WalkConfig {
+    fn default() -> Self {
+        Self {
+            absolute: false,
+            symlinks: SymlinkMode::Ignore,
+            include_hidden_files: true,
+            ignore_invalid_filetypes: false,
+            directories: DirectoryMode::None,
+            min_depth: 0,
+            max_depth: None,
+            one_file_system: false,
+            min_size: None,
+            max_size: None,
+            newer: None,
+            older: None,
+            filter: FilterOptions::default(),
+        }
+    }
+}
+
+impl WalkConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses absolute paths in the hashtable. Otherwise, the paths are relative to the walked
+    /// directory (i.e. it is used as the root of the [`crate::dirhash::DirHash`]).
+    pub fn with_absolute(mut self, absolute: bool) -> Self {
+        self.absolute = absolute;
+        self
+    }
+
+    /// Sets how symlinks are handled. See [`SymlinkMode`].
+    pub fn with_symlinks(mut self, symlinks: SymlinkMode) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Shorthand for [`SymlinkMode::Follow`] (`true`) or [`SymlinkMode::Ignore`] (`false`).
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.symlinks = match follow_symlinks {
+            true => SymlinkMode::Follow,
+            false => SymlinkMode::Ignore,
+        };
+        self
+    }
+
+    pub fn with_include_hidden_files(mut self, include_hidden_files: bool) -> Self {
+        self.include_hidden_files = include_hidden_files;
+        self
+    }
+
+    /// Ignores block and char devices, FIFOs and sockets instead of returning an error.
+    pub fn with_ignore_invalid_filetypes(mut self, ignore_invalid_filetypes: bool) -> Self {
+        self.ignore_invalid_filetypes = ignore_invalid_filetypes;
+        self
+    }
+
+    /// Adds marker entries for the selected directories (except the walked directory itself). See
+    /// [`crate::hashtable::EntryKind::Dir`].
+    pub fn with_directories(mut self, directories: DirectoryMode) -> Self {
+        self.directories = directories;
+        self
+    }
+
+    /// Only adds entries at least `min_depth` levels below the walked directory (1 for its direct
+    /// children). Shallower entries are skipped without being listed as ignored, but directories
+    /// are still walked.
+    pub fn with_min_depth(mut self, min_depth: usize) -> Self {
+        self.min_depth = min_depth;
+        self
+    }
+
+    /// Doesn't descend more than `max_depth` levels below the walked directory (`None` for no
+    /// limit). Deeper entries aren't visited at all.
+    pub fn with_max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Skips directories on other file systems than the walked directory with
+    /// [`crate::dirhash::IgnoreReason::MountPoint`].
+    pub fn with_one_file_system(mut self, one_file_system: bool) -> Self {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    /// Skips regular files smaller than `min_size` bytes with
+    /// [`crate::dirhash::IgnoreReason::TooSmall`].
+    pub fn with_min_size(mut self, min_size: Option<u64>) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Skips regular files larger than `max_size` bytes with
+    /// [`crate::dirhash::IgnoreReason::TooLarge`].
+    pub fn with_max_size(mut self, max_size: Option<u64>) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Skips regular files modified before `newer` (seconds since the Unix epoch) with
+    /// [`crate::dirhash::IgnoreReason::TooOld`].
+    pub fn with_newer(mut self, newer: Option<i64>) -> Self {
+        self.newer = newer;
+        self
+    }
+
+    /// Skips regular files modified at or after `older` (seconds since the Unix epoch) with
+    /// [`crate::dirhash::IgnoreReason::TooNew`]. Together with the same [`WalkConfig::with_newer()`]
+    /// timestamp, every file is skipped by exactly one of them.
+    pub fn with_older(mut self, older: Option<i64>) -> Self {
+        self.older = older;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: FilterOptions) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn absolute(&self) -> bool {
+        self.absolute
+    }
+
+    pub fn symlinks(&self) -> SymlinkMode {
+        self.symlinks
+    }
+
+    pub fn follow_symlinks(&self) -> bool {
+        self.symlinks == SymlinkMode::Follow
+    }
+
+    pub fn include_hidden_files(&self) -> bool {
+        self.include_hidden_files
+    }
+
+    pub fn ignore_invalid_filetypes(&self) -> bool {
+        self.ignore_invalid_filetypes
+    }
+
+    pub fn directories(&self) -> DirectoryMode {
+        self.directories
+    }
+
+    pub fn min_depth(&self) -> usize {
+        self.min_depth
+    }
+
+    pub fn max_depth(&self) -> Option<usize> {
+        self.max_depth
+    }
+
+    pub fn one_file_system(&self) -> bool {
+        self.one_file_system
+    }
+
+    pub fn min_size(&self) -> Option<u64> {
+        self.min_size
+    }
+
+    pub fn max_size(&self) -> Option<u64> {
+        self.max_size
+    }
+
+    pub fn newer(&self) -> Option<i64> {
+        self.newer
+    }
+
+    pub fn older(&self) -> Option<i64> {
+        self.older
+    }
+
+    /// Returns `true` if files are filtered by their size or modification time.
+    pub fn filters_metadata(&self) -> bool {
+        self.min_size.is_some()
+            || self.max_size.is_some()
+            || self.newer.is_some()
+            || self.older.is_some()
+    }
+
+    pub fn filter(&self) -> &FilterOptions {
+        &self.filter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default() {
+        let config = WalkConfig::new();
+        assert!(!config.absolute());
+        assert_eq!(config.symlinks(), SymlinkMode::Ignore);
+        assert!(!config.follow_symlinks());
+        assert!(config.include_hidden_files());
+        assert!(!config.ignore_invalid_filetypes());
+        assert_eq!(config.directories(), DirectoryMode::None);
+        assert_eq!(config.min_depth(), 0);
+        assert_eq!(config.max_depth(), None);
+        assert!(!config.one_file_system());
+        assert!(!config.filters_metadata());
+        assert!(config.filter().is_empty());
+    }
+
+    #[test]
+    fn builder_lite() {
+        let filter = FilterOptions {
+            exclude: vec![String::from("target/")],
+            include: vec![],
+            ignore_files: true,
+        };
+        let config = WalkConfig::new()
+            .with_absolute(true)
+            .with_follow_symlinks(true)
+            .with_include_hidden_files(false)
+            .with_ignore_invalid_filetypes(true)
+            .with_directories(DirectoryMode::Empty)
+            .with_min_depth(1)
+            .with_max_depth(Some(3))
+            .with_one_file_system(true)
+            .with_min_size(Some(1))
+            .with_max_size(Some(1024))
+            .with_newer(Some(1700000000))
+            .with_older(Some(1800000000))
+            .with_filter(filter.clone());
+
+        assert!(config.absolute());
+        assert!(config.follow_symlinks());
+        assert!(!config.include_hidden_files());
+        assert!(config.ignore_invalid_filetypes());
+        assert_eq!(config.directories(), DirectoryMode::Empty);
+        assert_eq!(config.min_depth(), 1);
+        assert_eq!(config.max_depth(), Some(3));
+        assert!(config.one_file_system());
+        assert_eq!(config.min_size(), Some(1));
+        assert_eq!(config.max_size(), Some(1024));
+        assert_eq!(config.newer(), Some(1700000000));
+        assert_eq!(config.older(), Some(1800000000));
+        assert!(config.filters_metadata());
+        assert_eq!(config.filter(), &filter);
+    }
+
+    #[test]
+    fn serialize() {
+        let json = serde_json::to_string(&WalkConfig::new()).expect("Can't serialize WalkConfig");
+        assert_eq!(
+            json,
+            r#"{"absolute":false,"follow_symlinks":false,"include_hidden_files":true,"ignore_invalid_filetypes":false}"#
+        );
+
+        let config = WalkConfig::new()
+            .with_directories(DirectoryMode::All)
+            .with_min_depth(2)
+            .with_max_depth(Some(0))
+            .with_one_file_system(true)
+            .with_min_size(Some(0))
+            .with_newer(Some(-1))
+            .with_filter(FilterOptions {
+                exclude: vec![String::from("*.o")],
+                include: vec![String::from("src/")],
+                ignore_files: true,
+            });
+        let json = serde_json::to_string(&config).expect("Can't serialize WalkConfig");
+        assert_eq!(
+            json,
+            r#"{"absolute":false,"follow_symlinks":false,"include_hidden_files":true,"ignore_invalid_filetypes":false,"directories":"all","min_depth":2,"max_depth":0,"one_file_system":true,"min_size":0,"newer":-1,"exclude":["*.o"],"include":["src/"],"ignore_files":true}"#
+        );
+
+        let parsed: WalkConfig = serde_json::from_str(&json).expect("Can't deserialize WalkConfig");
+        assert_eq!(parsed, config);
+    }
+
+    #[test]
+    fn serialize_symlink_modes() {
+        let json = serde_json::to_string(&WalkConfig::new().with_symlinks(SymlinkMode::Follow))
+            .expect("Can't serialize WalkConfig");
+        assert_eq!(
+            json,
+            r#"{"absolute":false,"follow_symlinks":true,"include_hidden_files":true,"ignore_invalid_filetypes":false}"#
+        );
+
+        let config = WalkConfig::new().with_symlinks(SymlinkMode::Hash);
+        let json = serde_json::to_string(&config).expect("Can't serialize WalkConfig");
+        assert_eq!(
+            json,
+            r#"{"absolute":false,"follow_symlinks":false,"hash_symlinks":true,"include_hidden_files":true,"ignore_invalid_filetypes":false}"#
+        );
+        let parsed: WalkConfig = serde_json::from_str(&json).expect("Can't deserialize WalkConfig");
+        assert_eq!(parsed, config);
+
+        serde_json::from_str::<WalkConfig>(
+            r#"{"absolute":false,"follow_symlinks":true,"hash_symlinks":true,"include_hidden_files":true,"ignore_invalid_filetypes":false}"#,
+        )
+        .expect_err("Contradicting symlink modes didn't result in error");
+    }
+
+    #[test]
+    fn deserialize_without_filter() {
+        let config: WalkConfig = serde_json::from_str(
+            r#"{"absolute":true,"follow_symlinks":false,"include_hidden_files":false,"ignore_invalid_filetypes":true}"#,
+        )
+        .expect("Can't deserialize WalkConfig");
+        assert_eq!(
+            config,
+            WalkConfig::new()
+                .with_absolute(true)
+                .with_include_hidden_files(false)
+                .with_ignore_invalid_filetypes(true)
+        );
+    }
+}
@@ -1,5 +1,6 @@
-use std::fs;
+use std::fs::{self, File};
 use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
 use std::process::Command;
 
 use assert_cmd::cargo::cargo_bin_cmd;
@@ -257,11 +258,13 @@ Ignored files:
 "#,
     );
 
-    // Following symlinks -> invalid files found -> panic
+    // Following symlinks -> invalid files found -> IO error
     let mut cmd = cargo_bin_cmd!("dirhash");
     cmd.args(&["list", dir.path().to_str().unwrap(), "-L"]);
-    cmd.assert().failure().stdout("").stderr(
-        predicates::str::contains("panicked").and(predicates::str::contains("InvalidFileType")),
+    cmd.assert().code(3).stdout("").stderr(
+        predicates::str::starts_with("Error: PathHash: Invalid filetype")
+            .and(predicates::str::contains("_link"))
+            .and(predicates::str::contains("panicked").not()),
     );
 
     // Following symlinks -> invalid files found, but ignored
@@ -774,11 +777,13 @@ Ignored files:
 "#,
     );
 
-    // Following symlinks -> invalid files found -> panic
+    // Following symlinks -> invalid files found -> IO error
     let mut cmd = cargo_bin_cmd!("dirhash");
     cmd.args(&["analyze", dir.path().to_str().unwrap(), "-L"]);
-    cmd.assert().failure().stdout("").stderr(
-        predicates::str::contains("panicked").and(predicates::str::contains("InvalidFileType")),
+    cmd.assert().code(3).stdout("").stderr(
+        predicates::str::starts_with("Error: PathHash: Invalid filetype")
+            .and(predicates::str::contains("_link"))
+            .and(predicates::str::contains("panicked").not()),
     );
 
     // Following symlinks -> invalid files found, but ignored
@@ -929,6 +934,105 @@ ec776c447825f880e04c010393c19179b5c472fe32682a941194d1c93a895c6a
     dir.close().expect("Can't close tempdir");
 }
 
+#[test]
+pub fn analyze_algorithm_flag() {
+    let expected_output = r#"# {
+#   "version": 1,
+#   "path": "/tmp/.tmp_cli_analyze_algorithm_flag",
+#   "algorithm": "md5",
+#   "absolute": false,
+#   "follow_symlinks": false,
+#   "include_hidden_files": false,
+#   "ignore_invalid_filetypes": false
+# }
+
+d41d8cd98f00b204e9800998ecf8427e  ./0
+d41d8cd98f00b204e9800998ecf8427e  ./1
+d41d8cd98f00b204e9800998ecf8427e  ./k/0
+d41d8cd98f00b204e9800998ecf8427e  ./k/1
+
+30216ac49398b5b1cd1b9ef2f926dd3e
+"#;
+
+    let dir = common::creating_tempdir(
+        Some(String::from(".tmp_cli_analyze_algorithm_flag")),
+        2,
+        &["k"][..],
+        2,
+        &[][..],
+        0,
+        false,
+    );
+
+    let mut fingerprint_file =
+        NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "--algorithm",
+        "md5",
+    ]);
+    cmd.assert().success().stdout(expected_output);
+
+    // The algorithm is read from the metadata when verifying
+    write!(fingerprint_file, "{}", expected_output).unwrap();
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert().success();
+
+    // Unknown algorithm
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "--algorithm",
+        "sha3",
+    ]);
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("Unknown hash algorithm: sha3"));
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn analyze_read_options() {
+    let dir = common::creating_tempdir(None, 3, &["a", "b"][..], 2, &["c"][..], 2, true);
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["analyze", dir.path().to_str().unwrap()]);
+    let expected_output = cmd.assert().success().get_output().stdout.clone();
+
+    // Reading doesn't affect the fingerprint
+    for read_args in [
+        &["--buffer-size", "1"][..],
+        &["--buffer-size", "1K"][..],
+        &["--mmap-threshold", "0"][..],
+        &["--buffer-size", "7", "--mmap-threshold", "16"][..],
+    ] {
+        let mut cmd = cargo_bin_cmd!("dirhash");
+        cmd.args(["analyze", dir.path().to_str().unwrap()])
+            .args(read_args);
+        cmd.assert().success().stdout(expected_output.clone());
+    }
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "--buffer-size",
+        "1X",
+    ]);
+    cmd.assert()
+        .failure()
+        .stderr(predicates::str::contains("invalid size \"1X\""));
+
+    dir.close().expect("Can't close tempdir");
+}
+
 #[test]
 pub fn verify() {
     let dir = common::creating_tempdir(
@@ -972,6 +1076,218 @@ d54869b935d36b0260556f9d283c92c32d2b44ccba9c0c5f6a7bf69183650b4e
     dir.close().expect("Can't close tempdir");
 }
 
+#[test]
+pub fn verify_mismatch() {
+    let dir = common::creating_tempdir(
+        Some(String::from(".tmp_cli_verify_mismatch")),
+        3,
+        &["d"][..],
+        2,
+        &["e"][..],
+        1,
+        false,
+    );
+    std::fs::write(dir.path().join(".hidden"), b"hidden").expect("Can't write to tempfile");
+
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "-f",
+        fingerprint_file.path().to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with("Fingerprint verified: "));
+
+    fs::write(dir.path().join("d/0"), b"modified").expect("Can't write to tempfile");
+    fs::write(dir.path().join("d/e/new"), b"").expect("Can't write to tempfile");
+    fs::remove_file(dir.path().join("1")).expect("Can't remove tempfile");
+    fs::remove_file(dir.path().join(".hidden")).expect("Can't remove tempfile");
+    fs::write(dir.path().join("d/.other_hidden"), b"").expect("Can't write to tempfile");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert().code(1).stdout(
+        r#"Fingerprint mismatch!
+
+Added files:
+./d/e/new
+
+Removed files:
+./1
+
+Modified files:
+./d/0
+
+Ignored files:
+- ./.hidden: Hidden
++ ./d/.other_hidden: Hidden
+
+Hash:
+- 8ad90c5d2ef706913fc7688021114ae665ad792f91737368fe4507d64b55da3c
++ 40fd7029ddd0c48aba09426ad78b4a1f24c610ccaed7ca1c1f247a1315b81923
+"#,
+    );
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn analyze_hash_links_flag() {
+    let dir = common::creating_tempdir(
+        Some(String::from(".tmp_cli_analyze_hash_links_flag")),
+        1,
+        &[][..],
+        0,
+        &[][..],
+        0,
+        false,
+    );
+    std::os::unix::fs::symlink("0", dir.path().join("link")).expect("Error while creating symlink");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["analyze", dir.path().to_str().unwrap(), "--hash-links"]);
+    cmd.assert().success().stdout(
+        r#"# {
+#   "version": 1,
+#   "path": "/tmp/.tmp_cli_analyze_hash_links_flag",
+#   "absolute": false,
+#   "follow_symlinks": false,
+#   "hash_symlinks": true,
+#   "include_hidden_files": false,
+#   "ignore_invalid_filetypes": false
+# }
+
+5feceb66ffc86f38d952786c6d696c79c2dbc239dd4e91b46729d73a27fb57e9 symlink  ./link
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./0
+
+aefb97a9102911be744049bfa2d663085c80343e470c4d0b717122805ed6b8a4
+"#,
+    );
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "--hash-links",
+        "-L",
+    ]);
+    cmd.assert().code(2);
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn verify_empty_dirs() {
+    let dir = common::creating_tempdir(
+        Some(String::from(".tmp_cli_verify_empty_dirs")),
+        1,
+        &["var"][..],
+        0,
+        &[][..],
+        0,
+        false,
+    );
+
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "--empty-dirs",
+        "-f",
+        fingerprint_file.path().to_str().unwrap(),
+    ]);
+    cmd.assert().success().stdout(predicates::str::contains(
+        r#"#   "directories": "empty"
+# }
+
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./0
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 dir  ./var/
+"#,
+    ));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert().success();
+
+    fs::remove_dir(dir.path().join("var")).expect("Can't remove dir");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert()
+        .code(1)
+        .stdout(predicates::str::contains("\nRemoved files:\n./var/\n"));
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn verify_file_metadata_changed() {
+    let dir = common::creating_tempdir(
+        Some(String::from(".tmp_cli_verify_file_metadata_changed")),
+        2,
+        &[][..],
+        0,
+        &[][..],
+        0,
+        false,
+    );
+    fs::set_permissions(dir.path().join("0"), fs::Permissions::from_mode(0o755))
+        .expect("Can't set permissions");
+    fs::set_permissions(dir.path().join("1"), fs::Permissions::from_mode(0o644))
+        .expect("Can't set permissions");
+
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "--mode",
+        "--size",
+        "-f",
+        fingerprint_file.path().to_str().unwrap(),
+    ]);
+    cmd.assert().success().stdout(predicates::str::contains(
+        r#"#   "file_metadata": {
+#     "mode": true,
+#     "size": true
+#   }
+# }
+
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 mode=0644 size=0  ./1
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 mode=0755 size=0  ./0
+"#,
+    ));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with("Fingerprint verified: "));
+
+    fs::set_permissions(dir.path().join("0"), fs::Permissions::from_mode(0o644))
+        .expect("Can't set permissions");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert().code(1).stdout(
+        predicates::str::contains("\nChanged file metadata:\n./0: mode=0755 -> mode=0644\n")
+            .and(predicates::str::contains("Modified files:").not()),
+    );
+
+    dir.close().expect("Can't close tempdir");
+}
+
 #[test]
 pub fn verify_bad_version() {
     let mut fingerprint_file =
@@ -1001,13 +1317,9 @@ d54869b935d36b0260556f9d283c92c32d2b44ccba9c0c5f6a7bf69183650b4e
     let mut cmd = cargo_bin_cmd!("dirhash");
     cmd.args(&["verify", fingerprint_file.path().to_str().unwrap()]);
     cmd.assert()
-        .failure()
+        .code(4)
         .stdout("")
-        .stderr(
-            predicates::str::contains("panicked").and(predicates::str::contains(
-                "Currently, only fingerprints with version \"1\" are supported!",
-            )),
-        );
+        .stderr("Error: Fingerprint: Unsupported version 2\n");
 }
 
 #[test]
@@ -1038,12 +1350,1140 @@ d54869b935d36b0260556f9d283c92c32d2b44ccba9c0c5f6a7bf69183650b4e
 
     let mut cmd = cargo_bin_cmd!("dirhash");
     cmd.args(&["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert().code(3).stdout("").stderr(
+        predicates::str::starts_with("Error: ")
+            .and(predicates::str::contains("/does/not/exist"))
+            .and(predicates::str::contains("No such file or directory")),
+    );
+}
+
+#[test]
+pub fn analyze_exclude_flag() {
+    let expected_output = r#"# {
+#   "version": 1,
+#   "path": "/tmp/.tmp_cli_analyze_exclude_flag",
+#   "absolute": false,
+#   "follow_symlinks": false,
+#   "include_hidden_files": false,
+#   "ignore_invalid_filetypes": false,
+#   "exclude": [
+#     "k/",
+#     "/1"
+#   ]
+# }
+
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./0
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./l/0
+e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./l/1
+
+2f6ba1656a2a078c4e13fb4b9616b5acc13e4d3fe6e2297023207da232bfbdc5
+
+Ignored files:
+./1: Excluded("/1")
+./k: Excluded("k/")
+"#;
+
+    let dir = common::creating_tempdir(
+        Some(String::from(".tmp_cli_analyze_exclude_flag")),
+        2,
+        &["k", "l"][..],
+        2,
+        &[][..],
+        0,
+        false,
+    );
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "--exclude",
+        "k/",
+        "-e",
+        "/1",
+    ]);
+    cmd.assert().success().stdout(expected_output);
+
+    // The patterns are read from the metadata when verifying, so the excluded files can change
+    let mut fingerprint_file =
+        NamedTempFile::new().expect("Can't create temporary fingerprint file");
+    write!(fingerprint_file, "{}", expected_output).unwrap();
+    fs::write(dir.path().join("k/0"), "changed").expect("Can't write to file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert().success();
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn tree() {
+    let dir = common::creating_tempdir(
+        Some(String::from(".tmp_cli_tree")),
+        1,
+        &["a", "b"][..],
+        1,
+        &["x"][..],
+        1,
+        false,
+    );
+    let other = common::creating_tempdir(
+        Some(String::from(".tmp_cli_tree_other")),
+        1,
+        &["a", "b"][..],
+        1,
+        &["x"][..],
+        1,
+        false,
+    );
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["tree", dir.path().to_str().unwrap()]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let paths: Vec<_> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| line.split_once("  ").unwrap().1.to_owned())
+        .collect();
+    assert_eq!(paths, ["./", "./a/", "./a/x/", "./b/", "./b/x/"]);
+
+    // The subtree paths are always relative
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["tree", dir.path().to_str().unwrap(), "--absolute"]);
+    cmd.assert().code(2).stderr(predicates::str::contains(
+        "unexpected argument '--absolute'",
+    ));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "tree",
+        dir.path().to_str().unwrap(),
+        "--compare",
+        other.path().to_str().unwrap(),
+    ]);
     cmd.assert()
-        .failure()
-        .stdout("")
-        .stderr(
-            predicates::str::contains("panicked").and(predicates::str::contains(
-                "code: 2, kind: NotFound, message: \"No such file or directory\"",
-            )),
-        );
+        .success()
+        .stdout(predicates::str::starts_with("Trees match: "));
+
+    fs::write(other.path().join("b/x/0"), b"changed").expect("Can't write to file");
+    fs::remove_dir_all(other.path().join("a")).expect("Can't remove dir");
+    fs::write(other.path().join("new"), b"new").expect("Can't write to file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "tree",
+        dir.path().to_str().unwrap(),
+        "--compare",
+        other.path().to_str().unwrap(),
+    ]);
+    cmd.assert().code(1).stdout(
+        r#"Trees differ!
+
+Added:
+./new
+
+Removed:
+./a/
+
+Modified:
+./b/x/0
+"#,
+    );
+
+    dir.close().expect("Can't close tempdir");
+    other.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn analyze_and_verify_with_cache() {
+    let dir = common::creating_tempdir(
+        Some(String::from(".tmp_cli_analyze_and_verify_with_cache")),
+        2,
+        &["a"][..],
+        1,
+        &[][..],
+        0,
+        true,
+    );
+    let cache_dir = tempfile::tempdir().expect("Can't create tempdir");
+    let cache_path = cache_dir.path().join("hashes");
+    let cache_arg = format!("--cache={}", cache_path.to_str().unwrap());
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "--no-cache",
+        &cache_arg,
+    ]);
+    cmd.assert().success();
+    assert!(!cache_path.exists());
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        &cache_arg,
+        "-f",
+        fingerprint_file.path().to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+    let cache_contents = fs::read_to_string(&cache_path).expect("Can't read cache file");
+    assert_eq!(cache_contents.lines().count(), 1 + 3);
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "verify",
+        fingerprint_file.path().to_str().unwrap(),
+        &cache_arg,
+    ]);
+    cmd.assert().success();
+
+    // Modified files are rehashed despite the cache
+    fs::write(dir.path().join("a/0"), "modified").expect("Can't write to file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "verify",
+        fingerprint_file.path().to_str().unwrap(),
+        &cache_arg,
+    ]);
+    cmd.assert()
+        .code(1)
+        .stdout(predicates::str::contains("\nModified files:\n./a/0\n"));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "verify",
+        fingerprint_file.path().to_str().unwrap(),
+        &cache_arg,
+        "--rehash",
+    ]);
+    cmd.assert().code(1);
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn exit_codes() {
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    let file = dir.path().join("file");
+    fs::write(&file, "").expect("Can't write to file");
+    let missing = dir.path().join("missing");
+
+    // Usage errors
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["analyze", "--no-such-flag"]);
+    cmd.assert().code(2);
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["analyze", file.to_str().unwrap()]);
+    cmd.assert()
+        .code(2)
+        .stderr(format!("Error: Not a directory: {}\n", file.display()));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", dir.path().to_str().unwrap()]);
+    cmd.assert()
+        .code(2)
+        .stderr(format!("Error: Not a file: {}\n", dir.path().display()));
+
+    // IO errors
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["list", missing.to_str().unwrap()]);
+    cmd.assert().code(3).stderr(
+        predicates::str::starts_with(format!("Error: IO error: {}: ", missing.display()))
+            .and(predicates::str::contains("panicked").not()),
+    );
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", missing.to_str().unwrap()]);
+    cmd.assert()
+        .code(3)
+        .stderr(predicates::str::starts_with(format!(
+            "Error: IO error: {}: ",
+            missing.display()
+        )));
+
+    // Malformed fingerprint
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", file.to_str().unwrap()]);
+    cmd.assert().code(4).stderr(predicates::str::starts_with(
+        "Error: Line 1: Malformed fingerprint",
+    ));
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn json_output() {
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    fs::write(dir.path().join("a"), "a").expect("Can't write to file");
+    fs::write(dir.path().join(".hidden"), "").expect("Can't write to file");
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let run = |args: &[&str], code: i32| {
+        let mut cmd = cargo_bin_cmd!("dirhash");
+        cmd.args(args);
+        let output = cmd.assert().code(code).get_output().stdout.clone();
+        String::from_utf8(output).unwrap()
+    };
+    let parse = |line: &str| -> serde_json::Value {
+        serde_json::from_str(line).expect("Output isn't valid JSON")
+    };
+
+    let list = parse(&run(
+        &["list", dir.path().to_str().unwrap(), "--format", "json"],
+        0,
+    ));
+    assert_eq!(list["schema_version"], 1);
+    assert_eq!(list["command"], "list");
+    assert_eq!(list["files"], serde_json::json!([{"path": "a"}]));
+    assert_eq!(
+        list["ignored"],
+        serde_json::json!([{"path": "./.hidden", "reason": "hidden"}])
+    );
+
+    let summary = parse(&run(
+        &["summary", dir.path().to_str().unwrap(), "--format", "json"],
+        0,
+    ));
+    assert_eq!(summary["counts"]["regular_files"], 1);
+    assert_eq!(summary["counts"]["hidden_files"], 1);
+
+    let analyze = run(
+        &[
+            "analyze",
+            dir.path().to_str().unwrap(),
+            "--format",
+            "jsonl",
+            "-f",
+            fingerprint_file.path().to_str().unwrap(),
+        ],
+        0,
+    );
+    let records: Vec<_> = analyze.lines().map(parse).collect();
+    let types: Vec<_> = records
+        .iter()
+        .map(|r| r["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, ["header", "entry", "ignored", "result"]);
+    assert_eq!(records[1]["path"], "./a");
+    assert_eq!(
+        records[1]["hash"],
+        "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb"
+    );
+    let hash = records[3]["hash"].clone();
+
+    // The fingerprint file is still written as text
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert().success();
+
+    let verify = parse(&run(
+        &[
+            "verify",
+            fingerprint_file.path().to_str().unwrap(),
+            "--format",
+            "json",
+        ],
+        0,
+    ));
+    assert_eq!(verify["verified"], true);
+    assert_eq!(verify["hash"], hash);
+
+    fs::write(dir.path().join("a"), "modified").expect("Can't write to file");
+    fs::write(dir.path().join("b"), "b").expect("Can't write to file");
+
+    let verify = parse(&run(
+        &[
+            "verify",
+            fingerprint_file.path().to_str().unwrap(),
+            "--format",
+            "json",
+        ],
+        1,
+    ));
+    assert_eq!(verify["verified"], false);
+    assert_eq!(verify["added"][0]["path"], "./b");
+    assert_eq!(verify["modified"][0]["old"]["hash"], records[1]["hash"]);
+    assert_eq!(verify["modified"][0]["new"]["path"], "./a");
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn check_manifest() {
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    fs::write(dir.path().join("a"), "a").expect("Can't write to file");
+    fs::write(dir.path().join("b"), "b").expect("Can't write to file");
+    let gnu = dir.path().join("gnu.sums");
+    let bsd = dir.path().join("bsd.sums");
+
+    // Written by analyze, the checksum files are identical to those of sha256sum
+    for (format, manifest, tag) in [("gnu", &gnu, None), ("bsd", &bsd, Some("--tag"))] {
+        let mut cmd = cargo_bin_cmd!("dirhash");
+        cmd.args([
+            "analyze",
+            dir.path().to_str().unwrap(),
+            "--manifest",
+            manifest.to_str().unwrap(),
+            "--manifest-format",
+            format,
+            "--exclude",
+            "*.sums",
+        ]);
+        cmd.assert().success();
+
+        let sha256sum = Command::new("sha256sum")
+            .args(tag.into_iter().chain(["./a", "./b"]))
+            .current_dir(dir.path())
+            .output()
+            .expect("Can't run sha256sum");
+        assert_eq!(
+            fs::read(manifest).expect("Can't read checksum file"),
+            sha256sum.stdout
+        );
+    }
+
+    for manifest in [&gnu, &bsd] {
+        let mut cmd = cargo_bin_cmd!("dirhash");
+        cmd.args(["check", manifest.to_str().unwrap()])
+            .current_dir(dir.path());
+        cmd.assert().success().stdout("./a: OK\n./b: OK\n");
+    }
+
+    fs::write(dir.path().join("a"), "modified").expect("Can't write to file");
+    fs::remove_file(dir.path().join("b")).expect("Can't remove file");
+    fs::write(dir.path().join("c"), "c").expect("Can't write to file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "check",
+        gnu.to_str().unwrap(),
+        "--root",
+        dir.path().to_str().unwrap(),
+        "--unlisted",
+        "--exclude",
+        "*.sums",
+    ]);
+    cmd.assert()
+        .code(1)
+        .stdout("./a: FAILED\n./b: MISSING\nc: UNLISTED\n")
+        .stderr(
+            "WARNING: 1 computed checksum did NOT match\n\
+             WARNING: 1 listed file is missing\n\
+             WARNING: 1 file is not listed\n",
+        );
+
+    fs::write(&gnu, "not a checksum line\n").expect("Can't write to file");
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["check", gnu.to_str().unwrap()]);
+    cmd.assert().code(4).stderr(predicates::str::starts_with(
+        "Error: Line 1: Malformed checksum line",
+    ));
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn check_non_utf8_manifest() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    fs::write(dir.path().join(OsStr::from_bytes(b"a\xff")), "a").expect("Can't write to file");
+    let manifest = dir.path().join("gnu.sums");
+
+    // sha256sum writes the name as it is
+    let sha256sum = Command::new("sha256sum")
+        .arg(OsStr::from_bytes(b"./a\xff"))
+        .current_dir(dir.path())
+        .output()
+        .expect("Can't run sha256sum");
+    assert!(sha256sum.stdout.ends_with(b"  ./a\xff\n"));
+    fs::write(&manifest, sha256sum.stdout).expect("Can't write to file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["check", manifest.to_str().unwrap()])
+        .current_dir(dir.path());
+    cmd.assert().success().stdout("\\./a\\xff: OK\n");
+
+    fs::write(dir.path().join(OsStr::from_bytes(b"a\xff")), "modified")
+        .expect("Can't write to file");
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["check", manifest.to_str().unwrap()])
+        .current_dir(dir.path());
+    cmd.assert().code(1).stdout("\\./a\\xff: FAILED\n");
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn check_walk_options() {
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    fs::write(dir.path().join("a"), "a").expect("Can't write to file");
+    fs::write(dir.path().join("b"), "b").expect("Can't write to file");
+    let manifest = dir.path().join("gnu.sums");
+    let sha256sum = Command::new("sha256sum")
+        .arg("./a")
+        .current_dir(dir.path())
+        .output()
+        .expect("Can't run sha256sum");
+    fs::write(&manifest, sha256sum.stdout).expect("Can't write to file");
+
+    // The walk options only apply to the unlisted files
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["check", manifest.to_str().unwrap(), "--exclude", "*.sums"])
+        .current_dir(dir.path());
+    cmd.assert()
+        .code(2)
+        .stderr(predicates::str::contains("--unlisted"));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["check", manifest.to_str().unwrap(), "--absolute"])
+        .current_dir(dir.path());
+    cmd.assert().code(2).stderr(predicates::str::contains(
+        "unexpected argument '--absolute'",
+    ));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "check",
+        manifest.to_str().unwrap(),
+        "--unlisted",
+        "--exclude",
+        "*.sums",
+    ])
+    .current_dir(dir.path());
+    cmd.assert().code(1).stdout("./a: OK\nb: UNLISTED\n");
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn diff() {
+    let old = tempfile::tempdir().expect("Can't create tempdir");
+    let new = tempfile::tempdir().expect("Can't create tempdir");
+    for dir in [&old, &new] {
+        fs::create_dir(dir.path().join("sub")).expect("Can't create dir");
+        fs::write(dir.path().join("same"), "same").expect("Can't write to file");
+        fs::write(dir.path().join("sub/changed"), "old").expect("Can't write to file");
+    }
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "diff",
+        old.path().to_str().unwrap(),
+        new.path().to_str().unwrap(),
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with("Trees match: "));
+
+    // A fingerprint with absolute paths is compared by the paths relative to its root
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        old.path().to_str().unwrap(),
+        "--absolute",
+        "--hidden",
+        "-f",
+        fingerprint_file.path().to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+
+    fs::write(new.path().join("sub/changed"), "new").expect("Can't write to file");
+    fs::write(new.path().join("added"), "added").expect("Can't write to file");
+    fs::write(new.path().join(".hidden"), "hidden").expect("Can't write to file");
+    fs::remove_file(new.path().join("same")).expect("Can't remove file");
+
+    let expected = "Trees differ!
+
+Added files:
+./.hidden
+./added
+
+Removed files:
+./same
+
+Modified files:
+./sub/changed
+";
+
+    for old in [old.path(), fingerprint_file.path()] {
+        let mut cmd = cargo_bin_cmd!("dirhash");
+        cmd.args([
+            "diff",
+            old.to_str().unwrap(),
+            new.path().to_str().unwrap(),
+            "--hidden",
+        ]);
+        cmd.assert().code(1).stdout(expected);
+    }
+
+    // A tree always matches itself
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "diff",
+        new.path().to_str().unwrap(),
+        new.path().to_str().unwrap(),
+        "--format",
+        "json",
+    ]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).expect("Output isn't valid JSON");
+    assert_eq!(json["command"], "diff");
+    assert_eq!(json["matches"], true);
+    assert_eq!(json["old_hash"], json["new_hash"]);
+
+    old.close().expect("Can't close tempdir");
+    new.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn diff_fingerprint_options() {
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    fs::write(dir.path().join("listed"), "listed").expect("Can't write to file");
+    let root = dir.path().to_str().unwrap();
+    let sha256_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+    let md5_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+    let sha256 = sha256_file.path().to_str().unwrap();
+    let md5 = md5_file.path().to_str().unwrap();
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["analyze", root, "--files-from", "-", "-f", sha256]);
+    cmd.write_stdin("listed\n");
+    cmd.assert().success();
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["analyze", root, "--algorithm", "md5", "-f", md5]);
+    cmd.assert().success();
+
+    // Only the listed files are compared
+    fs::write(dir.path().join("unlisted"), "unlisted").expect("Can't write to file");
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["diff", sha256, root]);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with("Trees match: "));
+
+    // Options of the fingerprint can be repeated, but not changed
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["diff", md5, root, "--algorithm", "md5"]);
+    cmd.assert().code(1);
+
+    for options in [
+        &["--hidden"][..],
+        &["-e", "*.o"],
+        &["--algorithm", "sha1"],
+        &["--mode"],
+    ] {
+        let mut cmd = cargo_bin_cmd!("dirhash");
+        cmd.args(["diff", sha256, root]).args(options);
+        cmd.assert().code(2).stderr(predicates::str::starts_with(
+            "Error: Conflicting options: the ",
+        ));
+    }
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["diff", sha256, md5]);
+    cmd.assert().code(2).stderr(
+        "Error: Conflicting options: the fingerprints use different algorithms (sha256 and md5)\n",
+    );
+}
+
+#[test]
+pub fn verify_moved() {
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    fs::create_dir(dir.path().join("sub")).expect("Can't create dir");
+    fs::write(dir.path().join("a"), "a").expect("Can't write to file");
+    fs::write(dir.path().join("b"), "b").expect("Can't write to file");
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "-f",
+        fingerprint_file.path().to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+
+    fs::rename(dir.path().join("a"), dir.path().join("sub/a")).expect("Can't move file");
+    fs::rename(dir.path().join("b"), dir.path().join("c")).expect("Can't rename file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert().code(1).stdout(predicates::str::contains(
+        "\nMoved files:\n./a -> ./sub/a\n./b -> ./c\n\nHash:\n",
+    ));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "verify",
+        fingerprint_file.path().to_str().unwrap(),
+        "--format",
+        "json",
+    ]);
+    let output = cmd.assert().code(1).get_output().stdout.clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).expect("Output isn't valid JSON");
+    assert_eq!(json["moved"][0]["old"]["path"], "./a");
+    assert_eq!(json["moved"][0]["new"]["path"], "./sub/a");
+    assert_eq!(json["added"], serde_json::json!([]));
+    assert_eq!(json["removed"], serde_json::json!([]));
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn dupes() {
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    fs::create_dir(dir.path().join("sub")).expect("Can't create dir");
+    for (path, contents) in [
+        ("a", "hello"),
+        ("sub/b", "hello"),
+        ("c", "hellp"),
+        ("d", "xx"),
+        ("e", "xx"),
+        ("f", "xx"),
+        ("empty", ""),
+        ("sub/empty", ""),
+    ] {
+        fs::write(dir.path().join(path), contents).expect("Can't write to file");
+    }
+
+    for by_size in [false, true] {
+        let mut cmd = cargo_bin_cmd!("dirhash");
+        cmd.args(["dupes", dir.path().to_str().unwrap()]);
+        if by_size {
+            cmd.arg("--by-size");
+        }
+        cmd.assert().success().stdout(
+            "2 files of 5 bytes (5 bytes wasted):
+./a
+./sub/b
+
+3 files of 2 bytes (4 bytes wasted):
+./d
+./e
+./f
+
+2 groups of duplicates, 9 bytes wasted
+",
+        );
+    }
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "dupes",
+        dir.path().to_str().unwrap(),
+        "--min-size",
+        "0",
+        "--format",
+        "json",
+    ]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).expect("Output isn't valid JSON");
+    assert_eq!(json["wasted"], 9);
+    assert_eq!(json["groups"].as_array().unwrap().len(), 3);
+    assert_eq!(
+        json["groups"][2]["paths"],
+        serde_json::json!(["./empty", "./sub/empty"])
+    );
+    assert_eq!(json["groups"][2]["wasted"], 0);
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn progress() {
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    fs::write(dir.path().join("a"), "hello").expect("Can't write to file");
+    fs::write(dir.path().join("b"), "world").expect("Can't write to file");
+
+    let output = |progress: &str| {
+        let mut cmd = cargo_bin_cmd!("dirhash");
+        cmd.args(["--progress", progress, "tree", dir.path().to_str().unwrap()]);
+        // Hash after walking, so the totals are known
+        cmd.args(["--jobs", "1"]);
+        cmd.output().expect("Can't run dirhash")
+    };
+
+    // The progress is only written to stderr and doesn't change the output
+    let shown = output("always");
+    assert!(shown.status.success());
+    let stderr = String::from_utf8(shown.stderr).unwrap();
+    assert!(stderr.contains("Hashing: 0/2 files, 0 B / 10 B"));
+    assert!(stderr.ends_with("\r\x1b[2K"));
+
+    let hidden = output("never");
+    assert!(hidden.status.success());
+    assert!(hidden.stderr.is_empty());
+    assert_eq!(shown.stdout, hidden.stdout);
+
+    // Not a terminal
+    assert!(output("auto").stderr.is_empty());
+}
+
+#[test]
+pub fn jobs() {
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    for i in 0..20 {
+        fs::write(dir.path().join(format!("file{i}")), i.to_string()).expect("Can't write to file");
+    }
+
+    let analyze = |jobs: &str| {
+        let mut cmd = cargo_bin_cmd!("dirhash");
+        cmd.args(["analyze", dir.path().to_str().unwrap(), "--jobs", jobs]);
+        cmd.output().expect("Can't run dirhash")
+    };
+
+    // The fingerprint doesn't depend on the number of threads
+    let serial = analyze("1");
+    assert!(serial.status.success());
+    for jobs in ["0", "4"] {
+        let parallel = analyze(jobs);
+        assert!(parallel.status.success());
+        assert_eq!(parallel.stdout, serial.stdout);
+    }
+}
+
+#[test]
+pub fn non_utf8_names() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    for name in [&b"a\x80"[..], b"a\x81", b"back\\slash"] {
+        fs::write(dir.path().join(OsStr::from_bytes(name)), name).expect("Can't write to file");
+    }
+
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "-f",
+        fingerprint_file.path().to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+
+    // Both names would be "a\u{FFFD}" if converted lossily
+    let fingerprint = fs::read_to_string(fingerprint_file.path()).expect("Can't read fingerprint");
+    for path in ["./a\\x80", "./a\\x81", "./back\\\\slash"] {
+        assert_eq!(
+            fingerprint
+                .lines()
+                .filter(|line| line.starts_with('\\') && line.ends_with(&format!("  {path}")))
+                .count(),
+            1,
+            "{path}: {fingerprint}"
+        );
+    }
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with("Fingerprint verified: "));
+
+    fs::write(dir.path().join(OsStr::from_bytes(b"a\x81")), "modified")
+        .expect("Can't write to file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert()
+        .code(1)
+        .stdout(predicates::str::contains("Modified files:\n\\./a\\x81\n"));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["list", dir.path().to_str().unwrap()]);
+    cmd.assert()
+        .success()
+        .stdout("\\a\\x80\n\\a\\x81\n\\back\\\\slash\n");
+}
+
+#[test]
+pub fn escaped_line_markers() {
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    fs::create_dir(dir.path().join("d\nx")).expect("Can't create dir");
+    fs::write(dir.path().join("d\nx/f"), "f").expect("Can't write to file");
+    // Looks like an escaped name, but isn't
+    fs::create_dir(dir.path().join("d\\nx")).expect("Can't create dir");
+    fs::write(dir.path().join("d\\nx/f"), "f").expect("Can't write to file");
+    fs::write(dir.path().join("e\re"), "e").expect("Can't write to file");
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["tree", dir.path().to_str().unwrap()]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let lines: Vec<_> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let (hash, path) = line.split_once("  ").unwrap();
+            (hash.starts_with('\\'), path.to_owned())
+        })
+        .collect();
+    assert_eq!(
+        lines,
+        [
+            (false, String::from("./")),
+            (true, String::from("./d\\\\nx/")),
+            (true, String::from("./d\\nx/")),
+        ]
+    );
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["list", dir.path().to_str().unwrap(), "--exclude", "e*"]);
+    cmd.assert()
+        .success()
+        .stdout("\\d\\nx/f\n\\d\\\\nx/f\n\nIgnored files:\n\\./e\\re: Excluded(\"e*\")\n");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "-f",
+        fingerprint_file.path().to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+
+    fs::write(dir.path().join("d\nx/f"), "modified").expect("Can't write to file");
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert()
+        .code(1)
+        .stdout(predicates::str::contains("Modified files:\n\\./d\\nx/f\n"));
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn json_non_utf8_paths() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    fs::create_dir(dir.path().join(OsStr::from_bytes(b"d\xff"))).expect("Can't create dir");
+    for name in [&b"d\xff/a\xff"[..], b".h\xff"] {
+        fs::write(dir.path().join(OsStr::from_bytes(name)), name).expect("Can't write to file");
+    }
+    let bytes = |path: &[u8]| serde_json::to_value(path).unwrap();
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["list", dir.path().to_str().unwrap(), "--format", "json"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).expect("Invalid JSON");
+    assert_eq!(json["files"][0]["path"], bytes(b"d\xff/a\xff"));
+    assert_eq!(json["ignored"][0]["path"], bytes(b"./.h\xff"));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["tree", dir.path().to_str().unwrap(), "--format", "json"]);
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let json: serde_json::Value = serde_json::from_slice(&output).expect("Invalid JSON");
+    assert_eq!(json["subtrees"][1]["path"], bytes(b"./d\xff/"));
+}
+
+#[test]
+pub fn json_non_utf8_root() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    let root = dir.path().join(OsStr::from_bytes(b"r\xff"));
+    fs::create_dir(&root).expect("Can't create dir");
+    fs::write(root.join("a"), "a").expect("Can't write to file");
+    let root_json = serde_json::to_value(root.as_os_str().as_bytes()).unwrap();
+
+    for command in ["list", "summary"] {
+        let mut cmd = cargo_bin_cmd!("dirhash");
+        cmd.arg(command).arg(&root).args(["--format", "json"]);
+        let output = cmd.assert().success().get_output().stdout.clone();
+        let json: serde_json::Value = serde_json::from_slice(&output).expect("Invalid JSON");
+        assert_eq!(json["root"], root_json, "{command}");
+    }
+}
+
+#[test]
+pub fn verify_non_utf8_root() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    let root = dir.path().join(OsStr::from_bytes(b"r\xff"));
+    fs::create_dir(&root).expect("Can't create dir");
+    fs::write(root.join("a"), "a").expect("Can't write to file");
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.arg("analyze")
+        .arg(&root)
+        .arg("-f")
+        .arg(fingerprint_file.path());
+    cmd.assert().success();
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.arg("verify").arg(fingerprint_file.path());
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with("Fingerprint verified: "));
+
+    fs::write(root.join("a"), "modified").expect("Can't write to file");
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.arg("verify").arg(fingerprint_file.path());
+    cmd.assert().code(1);
+}
+
+#[test]
+pub fn depth_options() {
+    let dir = common::creating_tempdir(None, 2, &["a"][..], 2, &["x"][..], 1, false);
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["list", dir.path().to_str().unwrap(), "--min-depth", "2"]);
+    cmd.args(["--max-depth", "2", "-x"]);
+    cmd.assert().success().stdout("a/0\na/1\n");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        dir.path().to_str().unwrap(),
+        "--max-depth",
+        "1",
+        "--one-file-system",
+        "-f",
+        fingerprint_file.path().to_str().unwrap(),
+    ]);
+    cmd.assert().success();
+
+    // The options are persisted, so deeper changes don't matter when verifying
+    let fingerprint = fs::read_to_string(fingerprint_file.path()).expect("Can't read fingerprint");
+    assert!(fingerprint.contains("#   \"max_depth\": 1,\n"));
+    assert!(fingerprint.contains("#   \"one_file_system\": true\n"));
+    assert!(!fingerprint.contains("min_depth"));
+
+    fs::write(dir.path().join("a/x/0"), "modified").expect("Can't write to file");
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with("Fingerprint verified: "));
+
+    fs::write(dir.path().join("0"), "modified").expect("Can't write to file");
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert().code(1);
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+pub fn size_and_time_options() {
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    fs::write(dir.path().join("empty"), "").expect("Can't write to file");
+    fs::write(dir.path().join("large"), "x".repeat(2048)).expect("Can't write to file");
+    fs::write(dir.path().join("new"), "new").expect("Can't write to file");
+    let old = File::create(dir.path().join("old")).expect("Can't create file");
+    old.set_len(10).expect("Can't set file size");
+    old.set_modified(std::time::UNIX_EPOCH)
+        .expect("Can't set modification time");
+
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["analyze", dir.path().to_str().unwrap()]);
+    cmd.args(["--min-size", "1", "--max-size", "1K", "--newer", "1w"]);
+    cmd.args(["-f", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert().success();
+
+    // The age is stored as a timestamp
+    let fingerprint = fs::read_to_string(fingerprint_file.path()).expect("Can't read fingerprint");
+    assert!(fingerprint.contains("#   \"min_size\": 1,\n"));
+    assert!(fingerprint.contains("#   \"max_size\": 1024,\n"));
+    assert!(fingerprint.contains("#   \"newer\": 1"));
+    assert!(fingerprint.contains("  ./new\n"));
+    assert!(fingerprint
+        .contains("Ignored files:\n./empty: TooSmall\n./large: TooLarge\n./old: TooOld\n"));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with("Fingerprint verified: "));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["list", dir.path().to_str().unwrap(), "--older", "60"]);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with("old\n\nIgnored files:\n"));
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["list", dir.path().to_str().unwrap(), "--newer", "yesterday"]);
+    cmd.assert()
+        .code(2)
+        .stderr(predicates::str::contains("invalid time \"yesterday\""));
+}
+
+#[test]
+pub fn files_from() {
+    let dir = tempfile::tempdir().expect("Can't create tempdir");
+    fs::create_dir(dir.path().join("d")).expect("Can't create dir");
+    for name in ["a", "d/b", "unlisted"] {
+        fs::write(dir.path().join(name), name).expect("Can't write to file");
+    }
+    let root = dir.path().to_str().unwrap();
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["list", root, "--files-from", "-", "-0"]);
+    cmd.write_stdin("./d/b\0a\0d\0");
+    cmd.assert().success().stdout("a\nd/b\n");
+
+    // Newline-delimited from a file
+    let list_file = NamedTempFile::new().expect("Can't create temporary list file");
+    fs::write(list_file.path(), "a\nd/b\n").expect("Can't write to file");
+    let fingerprint_file = NamedTempFile::new().expect("Can't create temporary fingerprint file");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args([
+        "analyze",
+        root,
+        "--files-from",
+        list_file.path().to_str().unwrap(),
+    ]);
+    cmd.args(["-f", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert().success();
+
+    let fingerprint = fs::read_to_string(fingerprint_file.path()).expect("Can't read fingerprint");
+    assert!(fingerprint.contains("#   \"listed\": true\n"));
+    assert!(!fingerprint.contains("unlisted"));
+
+    // Verify checks the listed paths only
+    fs::write(dir.path().join("new"), "new").expect("Can't write to file");
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert()
+        .success()
+        .stdout(predicates::str::starts_with("Fingerprint verified: "));
+
+    fs::write(dir.path().join("a"), "changed").expect("Can't write to file");
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["verify", fingerprint_file.path().to_str().unwrap()]);
+    cmd.assert().code(1);
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["list", root, "--files-from", "-"]);
+    cmd.write_stdin("a\n../outside\n");
+    cmd.assert()
+        .code(2)
+        .stderr("Error: Path outside of the root: ../outside\n");
+
+    let mut cmd = cargo_bin_cmd!("dirhash");
+    cmd.args(["list", root, "-0"]);
+    cmd.assert().code(2);
 }
@@ -6,13 +6,26 @@
 use std::{
     fs::{self, File},
     io::Write,
-    os::unix::{self, fs::FileTypeExt},
+    os::unix::{
+        self,
+        fs::{FileTypeExt, MetadataExt},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
 use dirhash_rs::{
-    dirhash::{DirHash, IgnoreReason},
+    cache::HashCache,
+    dirhash::{DirHash, IgnoreReason, Strategy},
     error::{DirHashError, InvalidFileTypeKind},
+    filter::FilterOptions,
+    pathhash::PathHashProvider,
+    progress::Progress,
     test_config,
+    walk::{DirectoryMode, SymlinkMode, WalkConfig},
 };
 use tempfile::tempdir;
 
@@ -24,7 +37,7 @@ fn with_files_from_dir_dont_follow_symlinks() {
     let dir = common::create_tempdir_with_links(None);
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, false, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new())
         .expect("Can't create DirHash");
 
     assert_eq!(
@@ -77,7 +90,7 @@ fn with_files_from_dir_follow_symlinks() {
     let dir = common::create_tempdir_with_links(None);
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, true, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new().with_follow_symlinks(true))
         .expect("Can't create DirHash");
 
     assert_eq!(dh.ignored().len(), 0);
@@ -117,6 +130,119 @@ fn with_files_from_dir_follow_symlinks() {
     dir.close().expect("Can't close tempdir");
 }
 
+#[test]
+fn with_files_from_dir_hash_symlinks() {
+    common::init_tracing();
+    let dir = tempdir().expect("Can't create tempdir");
+    fs::create_dir(dir.path().join("d")).expect("Can't create dir");
+    fs::write(dir.path().join("d/0"), "d/0").expect("Can't write to file");
+    unix::fs::symlink("d/0", dir.path().join("file_link")).expect("Error while creating symlink");
+    unix::fs::symlink("../d", dir.path().join("d/dir_link")).expect("Error while creating symlink");
+    unix::fs::symlink("missing", dir.path().join("broken_link"))
+        .expect("Error while creating symlink");
+
+    let mut dh = DirHash::new()
+        .with_files_from_dir(
+            dir.path(),
+            &WalkConfig::new().with_symlinks(SymlinkMode::Hash),
+        )
+        .expect("Can't create DirHash");
+
+    assert_eq!(dh.ignored().len(), 0);
+    assert!(dh.compute_hash().is_ok());
+
+    // The link to "d/0" has the same hash as the file containing "d/0"
+    assert_eq!(
+        dh.hashtable().unwrap().to_string(),
+        "18dc7ed0e7fce4f7755bba22cc1074cfaf907aaabe5602b2a135b26afd112aff  ./d/0\n\
+         18dc7ed0e7fce4f7755bba22cc1074cfaf907aaabe5602b2a135b26afd112aff symlink  ./file_link\n\
+         330ae54abc36d77510c423bb64f40b670866aa419c2c45e1e1b6dd436300d17d symlink  ./d/dir_link\n\
+         ffa63583dfa6706b87d284b86b0d693a161e4840aad2c5cf6b5d27c3b9621f7d symlink  ./broken_link\n"
+    );
+
+    // Retargeting a link changes the hash
+    let hash = dh.hash().unwrap().to_vec();
+    fs::remove_file(dir.path().join("file_link")).expect("Can't remove symlink");
+    unix::fs::symlink("./d/0", dir.path().join("file_link")).expect("Error while creating symlink");
+
+    let mut dh = DirHash::new()
+        .with_files_from_dir(
+            dir.path(),
+            &WalkConfig::new().with_symlinks(SymlinkMode::Hash),
+        )
+        .expect("Can't create DirHash");
+    assert!(dh.compute_hash().is_ok());
+    assert_ne!(dh.hash().unwrap(), hash);
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+fn with_files_from_dir_directories() {
+    common::init_tracing();
+    let dir = tempdir().expect("Can't create tempdir");
+    fs::create_dir_all(dir.path().join("cache")).expect("Can't create dir");
+    fs::create_dir_all(dir.path().join("var/log")).expect("Can't create dir");
+    fs::write(dir.path().join("var/0"), "").expect("Can't write to file");
+
+    let table = |directories| {
+        let mut dh = DirHash::new()
+            .with_files_from_dir(dir.path(), &WalkConfig::new().with_directories(directories))
+            .expect("Can't create DirHash");
+        assert!(dh.compute_hash().is_ok());
+        dh.hashtable().unwrap().to_string()
+    };
+
+    assert_eq!(
+        table(DirectoryMode::None),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./var/0\n"
+    );
+    assert_eq!(
+        table(DirectoryMode::Empty),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./var/0\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 dir  ./cache/\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 dir  ./var/log/\n"
+    );
+    assert_eq!(
+        table(DirectoryMode::All),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./var/0\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 dir  ./cache/\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 dir  ./var/\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 dir  ./var/log/\n"
+    );
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+fn with_files_from_dir_with_cache() {
+    common::init_tracing();
+    let dir = tempdir().expect("Can't create tempdir");
+    fs::write(dir.path().join("a"), "a").expect("Can't write to file");
+    fs::write(dir.path().join("b"), "b").expect("Can't write to file");
+
+    let cache = HashCache::new();
+    let hash = || {
+        let mut dh = DirHash::new()
+            .with_files_from_dir(dir.path(), &WalkConfig::new())
+            .expect("Can't create DirHash")
+            .with_cache(&cache);
+        assert!(dh.compute_hash().is_ok());
+        dh.hash().unwrap().to_vec()
+    };
+
+    let uncached = hash();
+    assert_eq!((cache.hits(), cache.misses()), (0, 2));
+    assert_eq!(hash(), uncached);
+    assert_eq!((cache.hits(), cache.misses()), (2, 2));
+
+    fs::write(dir.path().join("b"), "modified").expect("Can't write to file");
+    assert_ne!(hash(), uncached);
+    assert_eq!((cache.hits(), cache.misses()), (3, 3));
+
+    dir.close().expect("Can't close tempdir");
+}
+
 #[test]
 fn with_files_from_dir_include_hidden_files() {
     common::init_tracing();
@@ -136,10 +262,9 @@ fn with_files_from_dir_include_hidden_files() {
 
     write!(&mut file, "{}", "test data").expect("Can't write to tempfile");
 
-    // Hidden files shall be included by default when with_files_from_dir is refactored to builder
-    // pattern
+    // Hidden files are included by default
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, false, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new())
         .expect("Can't create DirHash");
 
     assert_eq!(dh.ignored().len(), 0);
@@ -176,7 +301,10 @@ fn with_files_from_dir_ignore_hidden_files() {
     write!(&mut file, "{}", "test data").expect("Can't write to tempfile");
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, false, false, false)
+        .with_files_from_dir(
+            dir.path(),
+            &WalkConfig::new().with_include_hidden_files(false),
+        )
         .expect("Can't create DirHash");
 
     assert_eq!(dh.ignored(), vec![(hidden_path, IgnoreReason::Hidden)]);
@@ -207,7 +335,7 @@ fn with_file_from_dir_no_root_empty_files() {
     );
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), false, false, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new().with_absolute(true))
         .expect("Can't create DirHash");
 
     assert_eq!(dh.ignored().len(), 0);
@@ -254,7 +382,7 @@ fn with_files_from_dir_with_root_empty_files() {
     let dir = common::creating_tempdir(None, 2, &["a", "b"][..], 1, &["x", "y"][..], 2, false);
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, false, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new())
         .expect("Can't create DirHash");
 
     assert_eq!(dh.ignored().len(), 0);
@@ -344,7 +472,7 @@ fn with_file_from_dir_no_root() {
         .expect("Error while adding data to test file");
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), false, false, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new().with_absolute(true))
         .expect("Can't create DirHash");
 
     assert_eq!(dh.ignored().len(), 0);
@@ -426,7 +554,7 @@ fn with_files_from_dir_with_root() {
     fs::write(dir.path().join("c/0"), b"DirHash\n").expect("Error while adding data to test file");
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, false, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new())
         .expect("Can't create DirHash");
 
     assert_eq!(dh.ignored().len(), 0);
@@ -490,7 +618,7 @@ fn with_files_from_dir_invalid_filetypes() {
     unix::fs::symlink(block_dev_path, &block_dev_link).expect("Error while creating symlink");
 
     let err = DirHash::new()
-        .with_files_from_dir(dir.path(), true, true, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new().with_follow_symlinks(true))
         .expect_err("Block device in files didn't result in error");
 
     match err {
@@ -513,7 +641,7 @@ fn with_files_from_dir_invalid_filetypes() {
     unix::fs::symlink(char_dev_path, &char_dev_link).expect("Error while creating symlink");
 
     let err = DirHash::new()
-        .with_files_from_dir(dir.path(), true, true, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new().with_follow_symlinks(true))
         .expect_err("Char device in files didn't result in error");
 
     match err {
@@ -535,7 +663,7 @@ fn with_files_from_dir_invalid_filetypes() {
     unix::fs::symlink(fifo_path, &fifo_link).expect("Error while creating symlink");
 
     let err = DirHash::new()
-        .with_files_from_dir(dir.path(), true, true, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new().with_follow_symlinks(true))
         .expect_err("Fifo in files didn't result in error");
 
     match err {
@@ -557,7 +685,7 @@ fn with_files_from_dir_invalid_filetypes() {
     unix::fs::symlink(socket_path, &socket_link).expect("Error while creating symlink");
 
     let err = DirHash::new()
-        .with_files_from_dir(dir.path(), true, true, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new().with_follow_symlinks(true))
         .expect_err("Socket in files didn't result in error");
 
     match err {
@@ -617,7 +745,12 @@ fn with_files_from_dir_invalid_filetypes_ignore() {
     unix::fs::symlink(socket_path, &socket_link).expect("Error while creating symlink");
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, true, true, true)
+        .with_files_from_dir(
+            dir.path(),
+            &WalkConfig::new()
+                .with_follow_symlinks(true)
+                .with_ignore_invalid_filetypes(true),
+        )
         .expect("Can't create DirHash");
 
     assert_eq!(
@@ -693,3 +826,565 @@ fn with_files_from_dir_invalid_filetypes_ignore() {
 
     dir.close().expect("Can't close tempdir");
 }
+
+#[test]
+fn with_files_from_dir_filter() {
+    common::init_tracing();
+    let dir = common::creating_tempdir(None, 2, &["src", "target"][..], 2, &["x"][..], 1, false);
+
+    File::create(dir.path().join("src/debug.log")).expect("Error while creating file");
+    File::create(dir.path().join("src/x/keep.log")).expect("Error while creating file");
+    fs::write(dir.path().join("src/.gitignore"), "*.log\n").expect("Can't write ignore file");
+    fs::write(dir.path().join("src/x/.dirhashignore"), "!keep.log\n")
+        .expect("Can't write ignore file");
+
+    let mut dh = DirHash::new()
+        .with_files_from_dir(
+            dir.path(),
+            &WalkConfig::new().with_filter(FilterOptions {
+                exclude: vec![String::from("target/"), String::from("/1")],
+                include: vec![],
+                ignore_files: true,
+            }),
+        )
+        .expect("Can't create DirHash");
+
+    assert_eq!(
+        dh.ignored(),
+        vec![
+            (
+                dir.path().join("1"),
+                IgnoreReason::Excluded(String::from("/1"))
+            ),
+            (
+                dir.path().join("src/debug.log"),
+                IgnoreReason::Excluded(String::from("*.log"))
+            ),
+            (
+                dir.path().join("target"),
+                IgnoreReason::Excluded(String::from("target/"))
+            ),
+        ]
+    );
+
+    assert!(dh.compute_hash().is_ok());
+
+    assert_eq!(
+        dh.hashtable().unwrap().to_string(),
+        "318d9a16533732a69cda7bb7b174ee392fdd15be3d72a114cd8f2d51f3eab510  ./src/.gitignore\n\
+         aa89e3df20e4d4aa74ce92db2f889c07348de9b7009e73fd3f09e4adadf42f23  ./src/x/.dirhashignore\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./0\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./src/0\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./src/1\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./src/x/0\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./src/x/keep.log\n"
+    );
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+fn with_files_from_dir_filter_include() {
+    common::init_tracing();
+    let dir = common::creating_tempdir(None, 2, &["a", "b"][..], 2, &["x"][..], 1, false);
+
+    let dh = DirHash::new()
+        .with_files_from_dir(
+            dir.path(),
+            &WalkConfig::new().with_filter(FilterOptions {
+                exclude: vec![],
+                include: vec![String::from("/a/"), String::from("0")],
+                ignore_files: false,
+            }),
+        )
+        .expect("Can't create DirHash");
+
+    assert_eq!(
+        dh.list_paths().unwrap(),
+        ["0", "a/0", "a/1", "a/x/0", "b/0", "b/x/0"].map(std::path::Path::new)
+    );
+    assert_eq!(
+        dh.ignored(),
+        vec![
+            (dir.path().join("1"), IgnoreReason::NotIncluded),
+            (dir.path().join("b/1"), IgnoreReason::NotIncluded),
+        ]
+    );
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+fn with_files_from_dir_depth() {
+    common::init_tracing();
+    let dir = common::creating_tempdir(None, 2, &["a", "b"][..], 2, &["x"][..], 1, false);
+
+    let list = |config: WalkConfig| {
+        let dh = DirHash::new()
+            .with_files_from_dir(dir.path(), &config)
+            .expect("Can't create DirHash");
+        assert!(dh.ignored().is_empty());
+        let paths: Vec<_> = dh
+            .list_paths()
+            .unwrap()
+            .iter()
+            .map(|p| p.to_path_buf())
+            .collect();
+        paths
+    };
+
+    assert_eq!(
+        list(WalkConfig::new().with_max_depth(Some(1))),
+        ["0", "1"].map(PathBuf::from)
+    );
+    assert_eq!(
+        list(WalkConfig::new().with_min_depth(2).with_max_depth(Some(2))),
+        ["a/0", "a/1", "b/0", "b/1"].map(PathBuf::from)
+    );
+    assert_eq!(
+        list(WalkConfig::new().with_min_depth(3)),
+        ["a/x/0", "b/x/0"].map(PathBuf::from)
+    );
+    assert!(list(WalkConfig::new().with_max_depth(Some(0))).is_empty());
+
+    // Directories above the min depth don't get a marker
+    let mut dh = DirHash::new()
+        .with_files_from_dir(
+            dir.path(),
+            &WalkConfig::new()
+                .with_min_depth(2)
+                .with_max_depth(Some(2))
+                .with_directories(DirectoryMode::All),
+        )
+        .expect("Can't create DirHash");
+    assert!(dh.compute_hash().is_ok());
+    assert_eq!(
+        dh.hashtable().unwrap().to_string(),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./a/0\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./a/1\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./b/0\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  ./b/1\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 dir  ./a/x/\n\
+         e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 dir  ./b/x/\n"
+    );
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+fn with_files_from_dir_size_and_mtime() {
+    common::init_tracing();
+    let dir = tempdir().expect("Can't create tempdir");
+
+    let time = |secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+    for (name, size, mtime) in [
+        ("empty", 0, 1000),
+        ("small", 10, 2000),
+        ("large", 1000, 2000),
+        ("new", 10, 3000),
+    ] {
+        let file = File::create(dir.path().join(name)).expect("Can't create file");
+        file.set_len(size).expect("Can't set file size");
+        file.set_modified(time(mtime))
+            .expect("Can't set modification time");
+    }
+    unix::fs::symlink("empty", dir.path().join("link")).expect("Error while creating symlink");
+
+    let config = WalkConfig::new()
+        .with_symlinks(SymlinkMode::Hash)
+        .with_min_size(Some(1))
+        .with_max_size(Some(100))
+        .with_newer(Some(2000))
+        .with_older(Some(3000));
+
+    // Only regular files are filtered
+    for dh in [
+        DirHash::new().with_files_from_dir(dir.path(), &config),
+        DirHash::new().compute_hash_from_dir(dir.path(), &config),
+    ] {
+        let dh = dh.expect("Can't create DirHash");
+        assert_eq!(
+            dh.list_paths().unwrap(),
+            [Path::new("link"), Path::new("small")]
+        );
+        assert_eq!(
+            dh.ignored(),
+            vec![
+                (dir.path().join("empty"), IgnoreReason::TooSmall),
+                (dir.path().join("large"), IgnoreReason::TooLarge),
+                (dir.path().join("new"), IgnoreReason::TooNew),
+            ]
+        );
+    }
+
+    let dh = DirHash::new()
+        .with_files_from_dir(dir.path(), &WalkConfig::new().with_newer(Some(2001)))
+        .expect("Can't create DirHash");
+    assert_eq!(dh.list_paths().unwrap(), [Path::new("new")]);
+    assert_eq!(dh.ignored().len(), 4);
+    assert!(dh
+        .ignored()
+        .iter()
+        .all(|(path, reason)| *reason == IgnoreReason::TooOld || path.ends_with("link")));
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+fn with_files_from_dir_one_file_system() {
+    common::init_tracing();
+
+    // Needs a mount point directly below the root, e.g. /proc
+    let root = Path::new("/");
+    let device = |path: &Path| fs::metadata(path).map(|m| m.dev()).ok();
+    let Some(mount_point) = fs::read_dir(root)
+        .expect("Can't read /")
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.is_dir() && !path.is_symlink() && device(path) != device(root))
+    else {
+        return;
+    };
+
+    // Only the directories below the root are checked, nothing is hashed
+    let config = WalkConfig::new()
+        .with_min_depth(2)
+        .with_max_depth(Some(1))
+        .with_one_file_system(true);
+    let dh = DirHash::new()
+        .with_files_from_dir(root, &config)
+        .expect("Can't create DirHash");
+    assert!(dh.files().is_empty());
+    assert!(dh
+        .ignored()
+        .contains(&(mount_point.clone(), IgnoreReason::MountPoint)));
+    assert!(dh
+        .ignored()
+        .iter()
+        .all(|(path, reason)| *reason == IgnoreReason::MountPoint && device(path) != device(root)));
+
+    let pipelined = DirHash::new()
+        .compute_hash_from_dir(root, &config)
+        .expect("Can't compute hash");
+    assert_eq!(pipelined.ignored(), dh.ignored());
+
+    let dh = DirHash::new()
+        .with_files_from_dir(root, &config.with_one_file_system(false))
+        .expect("Can't create DirHash");
+    assert!(dh.ignored().is_empty());
+}
+
+#[test]
+fn with_files_from_list() {
+    common::init_tracing();
+    let dir = tempdir().expect("Can't create tempdir");
+    fs::create_dir_all(dir.path().join("d/sub")).expect("Can't create dir");
+    fs::create_dir(dir.path().join("target")).expect("Can't create dir");
+    for name in ["a", "d/b", "d/sub/c", "target/x.o", ".hidden"] {
+        fs::write(dir.path().join(name), name).expect("Can't write to file");
+    }
+
+    let config = WalkConfig::new()
+        .with_include_hidden_files(false)
+        .with_filter(FilterOptions {
+            exclude: vec!["target/".to_owned()],
+            ..Default::default()
+        });
+    let listed = [
+        PathBuf::from("./a"),
+        PathBuf::from("d/b"),
+        dir.path().join("d/sub/c"),
+        PathBuf::from("d/../a"),
+        PathBuf::from("target/x.o"),
+        PathBuf::from(".hidden"),
+        PathBuf::from("."),
+    ];
+
+    let mut dh = DirHash::new()
+        .with_files_from_list(dir.path(), &listed, &config)
+        .expect("Can't create DirHash");
+    assert_eq!(
+        dh.list_paths().unwrap(),
+        [Path::new("a"), Path::new("d/b"), Path::new("d/sub/c")]
+    );
+    assert_eq!(
+        dh.ignored(),
+        vec![
+            (dir.path().join(".hidden"), IgnoreReason::Hidden),
+            (
+                dir.path().join("target/x.o"),
+                IgnoreReason::Excluded("target/".to_owned())
+            ),
+        ]
+    );
+
+    // Same files, same hash as walking
+    let mut walked = DirHash::new()
+        .with_files_from_dir(dir.path(), &config)
+        .expect("Can't create DirHash");
+    dh.compute_hash().expect("Can't compute hash");
+    walked.compute_hash().expect("Can't compute hash");
+    assert_eq!(dh.hash(), walked.hash());
+
+    // Listed directories only get a marker
+    let dh = DirHash::new()
+        .with_files_from_list(
+            dir.path(),
+            ["d"],
+            &WalkConfig::new().with_directories(DirectoryMode::All),
+        )
+        .expect("Can't create DirHash");
+    assert_eq!(dh.list_paths().unwrap(), [Path::new("d/")]);
+
+    for outside in [
+        PathBuf::from("../a"),
+        PathBuf::from("d/../../a"),
+        PathBuf::from("/a"),
+    ] {
+        let err = DirHash::new()
+            .with_files_from_list(dir.path(), [&outside], &config)
+            .unwrap_err();
+        assert!(matches!(err, DirHashError::OutsideRoot(path) if path == outside));
+    }
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+fn with_files_from_dir_filter_invalid_pattern() {
+    let dir = tempdir().expect("Can't create tempdir");
+
+    let err = DirHash::new()
+        .with_files_from_dir(
+            dir.path(),
+            &WalkConfig::new().with_filter(FilterOptions {
+                exclude: vec![String::from("{unclosed")],
+                include: vec![],
+                ignore_files: false,
+            }),
+        )
+        .unwrap_err();
+    assert!(matches!(err, DirHashError::Filter(_)));
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[derive(Default)]
+struct CountingProgress {
+    found: AtomicUsize,
+    total_files: AtomicUsize,
+    total_bytes: AtomicU64,
+    hashed: AtomicUsize,
+    hashed_bytes: AtomicU64,
+    finished: AtomicUsize,
+}
+
+impl Progress for CountingProgress {
+    fn file_found(&self, _path: &Path) {
+        self.found.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn hashing_started(&self, files: usize, bytes: u64) {
+        self.total_files.store(files, Ordering::Relaxed);
+        self.total_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    fn file_hashed(&self, _path: &Path, bytes: u64) {
+        self.hashed.fetch_add(1, Ordering::Relaxed);
+        self.hashed_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn hashing_finished(&self) {
+        self.finished.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn with_files_from_dir_progress() {
+    let dir = tempdir().expect("Can't create tempdir");
+    fs::create_dir(dir.path().join("empty")).unwrap();
+    fs::write(dir.path().join("a"), "a").unwrap();
+    fs::write(dir.path().join("b"), "bbb").unwrap();
+
+    for strategy in [Strategy::Serial, Strategy::Rayon1, Strategy::Rayon2] {
+        let progress = Arc::new(CountingProgress::default());
+        let mut dh = DirHash::new()
+            .with_strategy(strategy)
+            .with_threads(2)
+            .with_progress(progress.clone())
+            .with_files_from_dir(
+                dir.path(),
+                &WalkConfig::new().with_directories(DirectoryMode::Empty),
+            )
+            .expect("Can't create DirHash");
+        assert_eq!(progress.found.load(Ordering::Relaxed), 3);
+        assert_eq!(progress.hashed.load(Ordering::Relaxed), 0);
+
+        dh.compute_hash().expect("Can't compute hash");
+        assert_eq!(progress.total_files.load(Ordering::Relaxed), 3);
+        assert_eq!(progress.total_bytes.load(Ordering::Relaxed), 4);
+        assert_eq!(progress.hashed.load(Ordering::Relaxed), 3);
+        assert_eq!(progress.hashed_bytes.load(Ordering::Relaxed), 4);
+        assert_eq!(progress.finished.load(Ordering::Relaxed), 1);
+    }
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+fn compute_hash_from_dir() {
+    common::init_tracing();
+    let dir = common::create_tempdir_with_links(None);
+    fs::create_dir(dir.path().join("a/empty")).unwrap();
+    fs::write(dir.path().join("b/.hidden"), "hidden").unwrap();
+    fs::write(dir.path().join("b/x/.gitignore"), "0\n").unwrap();
+    fs::write(dir.path().join("b/debug.log"), "log").unwrap();
+
+    let filter = FilterOptions {
+        exclude: vec![String::from("*.log")],
+        include: vec![],
+        ignore_files: true,
+    };
+    let configs = [
+        WalkConfig::new(),
+        WalkConfig::new().with_absolute(true),
+        WalkConfig::new().with_follow_symlinks(true),
+        WalkConfig::new().with_symlinks(SymlinkMode::Hash),
+        WalkConfig::new().with_include_hidden_files(false),
+        WalkConfig::new().with_directories(DirectoryMode::Empty),
+        WalkConfig::new().with_directories(DirectoryMode::All),
+        WalkConfig::new()
+            .with_follow_symlinks(true)
+            .with_filter(filter),
+        WalkConfig::new().with_min_depth(2),
+        WalkConfig::new()
+            .with_max_depth(Some(2))
+            .with_directories(DirectoryMode::Empty),
+        WalkConfig::new().with_max_depth(Some(0)),
+        WalkConfig::new().with_one_file_system(true),
+        WalkConfig::new()
+            .with_min_size(Some(4))
+            .with_max_size(Some(5)),
+    ];
+
+    for config in configs {
+        let mut expected = DirHash::new()
+            .with_files_from_dir(dir.path(), &config)
+            .expect("Can't create DirHash");
+        expected.compute_hash().expect("Can't compute hash");
+        let mut expected_paths: Vec<_> = expected.files().iter().map(|ph| ph.path()).collect();
+        expected_paths.sort();
+
+        for threads in [0, 3] {
+            let progress = Arc::new(CountingProgress::default());
+            let dh = DirHash::new()
+                .with_threads(threads)
+                .with_progress(progress.clone())
+                .compute_hash_from_dir(dir.path(), &config)
+                .expect("Can't compute hash");
+
+            assert_eq!(dh.root(), expected.root());
+            assert_eq!(dh.hashtable(), expected.hashtable());
+            assert_eq!(dh.hash(), expected.hash());
+            assert_eq!(dh.ignored(), expected.ignored());
+            let paths: Vec<_> = dh.files().iter().map(|ph| ph.path()).collect();
+            assert_eq!(paths, expected_paths);
+
+            let files = expected.files().len();
+            assert_eq!(progress.found.load(Ordering::Relaxed), files);
+            assert_eq!(progress.hashed.load(Ordering::Relaxed), files);
+            assert_eq!(progress.finished.load(Ordering::Relaxed), 1);
+        }
+    }
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+fn compute_hash_from_dir_errors() {
+    common::init_tracing();
+    let dir = tempdir().expect("Can't create tempdir");
+    fs::create_dir(dir.path().join("a")).unwrap();
+    fs::write(dir.path().join("a/0"), "0").unwrap();
+    unix::fs::symlink(dir.path(), dir.path().join("a/loop")).expect("Can't create symlink");
+
+    let follow = WalkConfig::new().with_follow_symlinks(true);
+    assert!(DirHash::new()
+        .with_files_from_dir(dir.path(), &follow)
+        .is_err());
+    let err = DirHash::new()
+        .with_threads(2)
+        .compute_hash_from_dir(dir.path(), &follow)
+        .unwrap_err();
+    assert!(matches!(err, DirHashError::PathIo(path, _) if path == dir.path().join("a/loop")));
+
+    // Without following, the link is ignored
+    let dh = DirHash::new()
+        .with_threads(2)
+        .compute_hash_from_dir(dir.path(), &WalkConfig::new())
+        .expect("Can't compute hash");
+    assert_eq!(
+        dh.ignored(),
+        [(dir.path().join("a/loop"), IgnoreReason::Symlink)]
+    );
+
+    let err = DirHash::new()
+        .compute_hash_from_dir(&dir.path().join("missing"), &WalkConfig::new())
+        .unwrap_err();
+    assert!(matches!(err, DirHashError::PathIo(..)));
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+fn with_files_from_list_symlinks() {
+    common::init_tracing();
+    let dir = tempdir().expect("Can't create tempdir");
+    let root = dir.path().join("root");
+    let other = dir.path().join("other");
+    fs::create_dir_all(root.join("d")).expect("Can't create dir");
+    fs::create_dir(&other).expect("Can't create dir");
+    fs::write(root.join("a"), "a").expect("Can't write to file");
+    fs::write(root.join("d/b"), "b").expect("Can't write to file");
+    fs::write(other.join("o"), "o").expect("Can't write to file");
+    unix::fs::symlink(&other, root.join("lnk")).expect("Error while creating symlink");
+    unix::fs::symlink("d", root.join("inside")).expect("Error while creating symlink");
+
+    // Not followed, like walking reports the symlinked directories
+    let dh = DirHash::new()
+        .with_files_from_list(&root, ["lnk/o", "inside/b", "a"], &WalkConfig::new())
+        .expect("Can't create DirHash");
+    assert_eq!(dh.list_paths().unwrap(), [Path::new("a")]);
+    assert_eq!(
+        dh.ignored(),
+        vec![
+            (root.join("inside/b"), IgnoreReason::Symlink),
+            (root.join("lnk/o"), IgnoreReason::Symlink),
+        ]
+    );
+
+    // Followed, but only below the root
+    let config = WalkConfig::new().with_symlinks(SymlinkMode::Follow);
+    let dh = DirHash::new()
+        .with_files_from_list(&root, ["inside/b"], &config)
+        .expect("Can't create DirHash");
+    assert_eq!(dh.list_paths().unwrap(), [Path::new("inside/b")]);
+
+    let err = DirHash::new()
+        .with_files_from_list(&root, ["lnk/o"], &config)
+        .unwrap_err();
+    assert!(matches!(err, DirHashError::OutsideRoot(path) if path == root.join("lnk/o")));
+
+    // `..` is resolved on the file system: lnk/.. is the tempdir, not the root
+    for config in [WalkConfig::new(), config] {
+        let err = DirHash::new()
+            .with_files_from_list(&root, ["lnk/../root/a"], &config)
+            .unwrap_err();
+        assert!(matches!(err, DirHashError::OutsideRoot(_)));
+    }
+    let dh = DirHash::new()
+        .with_files_from_list(&root, ["inside/../a"], &WalkConfig::new())
+        .expect("Can't create DirHash");
+    assert_eq!(dh.list_paths().unwrap(), [Path::new("a")]);
+
+    dir.close().expect("Can't close tempdir");
+}
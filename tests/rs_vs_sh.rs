@@ -5,8 +5,15 @@ use std::{fs::File, io::Write, time::Instant};
 
 use dirhash_rs::test_config;
 use dirhash_rs::{
-    bash::{compute_recursive_hash_with_bash, list_files_with_bash},
+    algorithm::HashAlgorithm,
+    bash::{
+        compute_recursive_hash_with_bash, compute_recursive_hash_with_bash_and_algorithm,
+        list_files_with_bash,
+    },
     dirhash::{DirHash, IgnoreReason},
+    escape::{escape_path, line_marker},
+    merkle::MerkleTree,
+    walk::WalkConfig,
 };
 use tempfile::tempdir;
 use tracing::info;
@@ -35,7 +42,7 @@ fn with_empty_files_and_check_lc_all_ordering() {
     // ------------------
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, false, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new())
         .expect("Can't create DirHash");
 
     let rs_list_paths = dh.list_paths().expect("Can't list files with dirhash");
@@ -155,7 +162,12 @@ fn absolute_paths() {
     // ------------------
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), false, false, false, false)
+        .with_files_from_dir(
+            dir.path(),
+            &WalkConfig::new()
+                .with_absolute(true)
+                .with_include_hidden_files(false),
+        )
         .expect("Can't create DirHash");
 
     let rs_list_paths = dh.list_paths().expect("Can't list files with dirhash");
@@ -259,7 +271,12 @@ fn ignoring_invalid_files() {
     // ------------------
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, true, true, true)
+        .with_files_from_dir(
+            dir.path(),
+            &WalkConfig::new()
+                .with_follow_symlinks(true)
+                .with_ignore_invalid_filetypes(true),
+        )
         .expect("Can't create DirHash");
 
     let ignored = dh.ignored();
@@ -405,7 +422,7 @@ fn following_symlinks() {
     // ------------------
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, true, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new().with_follow_symlinks(true))
         .expect("Can't create DirHash");
 
     assert_eq!(dh.ignored().len(), 0);
@@ -508,7 +525,7 @@ fn not_following_symlinks() {
     // ------------------
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, false, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new())
         .expect("Can't create DirHash");
 
     assert_eq!(
@@ -623,7 +640,7 @@ fn including_hidden_files() {
     // ------------------
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, false, true, false)
+        .with_files_from_dir(dir.path(), &WalkConfig::new())
         .expect("Can't create DirHash");
 
     assert_eq!(dh.ignored().len(), 0);
@@ -700,7 +717,10 @@ fn ignoring_hidden_files() {
     // ------------------
 
     let mut dh = DirHash::new()
-        .with_files_from_dir(dir.path(), true, false, false, false)
+        .with_files_from_dir(
+            dir.path(),
+            &WalkConfig::new().with_include_hidden_files(false),
+        )
         .expect("Can't create DirHash");
 
     assert_eq!(dh.ignored(), vec![(hidden_path, IgnoreReason::Hidden)]);
@@ -745,6 +765,157 @@ fn ignoring_hidden_files() {
     dir.close().expect("Can't close tempdir");
 }
 
+#[test]
+fn all_algorithms() {
+    common::init_tracing();
+
+    // Setup
+    // ------
+
+    let dir = common::creating_tempdir(
+        None,
+        3,
+        // specifically crafted to check if sorting with LC_ALL=C is working
+        &["b,foo", "bc,pe", "bcd,ty"][..],
+        2,
+        &["x", "y"][..],
+        2,
+        true,
+    );
+
+    for algorithm in HashAlgorithm::ALL {
+        info!("Algorithm: {algorithm}");
+
+        // rs implementation
+        // ------------------
+
+        let mut dh = DirHash::new()
+            .with_algorithm(algorithm)
+            .with_files_from_dir(
+                dir.path(),
+                &WalkConfig::new().with_include_hidden_files(false),
+            )
+            .expect("Can't create DirHash");
+
+        assert!(dh.compute_hash().is_ok());
+
+        let rs_hash_str = hex::encode(dh.hash().unwrap());
+        let rs_hashtable_str = dh.hashtable().unwrap().to_string();
+
+        // sh implementation
+        // ------------------
+        let (sh_hashtable_str, sh_hash_str) = compute_recursive_hash_with_bash_and_algorithm(
+            dir.path(),
+            false,
+            false,
+            false,
+            algorithm,
+        );
+
+        // Verification
+        // ------------
+        assert_eq!(sh_hash_str, rs_hash_str, "{algorithm}");
+        assert_eq!(sh_hashtable_str, rs_hashtable_str, "{algorithm}");
+        assert_eq!(rs_hash_str.len(), 2 * algorithm.output_len());
+    }
+
+    dir.close().expect("Can't close tempdir");
+}
+
+#[test]
+fn adversarial_names() {
+    common::init_tracing();
+
+    // Setup
+    // ------
+
+    // The escaped names are mixed with plain ones, whose lines don't start with a backslash. The
+    // sh implementation sorts the lines as text, like the hashtable.
+    let dir = tempdir().expect("Can't create tempdir");
+    let escaped_names = [
+        "new\nline",
+        "fake\n0000000000000000000000000000000000000000000000000000000000000000  injected",
+        "carriage\rreturn",
+        "back\\slash",
+        "trailing\n",
+    ];
+    let names = [&escaped_names[..], &["a", "plain", "z"]].concat();
+    for name in &names {
+        let mut file = File::create(dir.path().join(name)).expect("Can't create file");
+        file.write_all(name.as_bytes())
+            .expect("Can't write to file");
+    }
+    // The file in the escaped directory is escaped, too
+    std::fs::create_dir(dir.path().join("d\nx")).expect("Can't create dir");
+    File::create(dir.path().join("d\nx/f")).expect("Can't create file");
+
+    // rs implementation
+    // ------------------
+
+    let mut dh = DirHash::new()
+        .with_files_from_dir(dir.path(), &WalkConfig::new())
+        .expect("Can't create DirHash");
+
+    let rs_list_paths = dh.list_paths().expect("Can't list files with dirhash");
+    let rs_list_paths_str = rs_list_paths
+        .iter()
+        .map(|p| escape_path(p.as_os_str()))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    assert!(dh.compute_hash().is_ok());
+
+    let rs_hash_str = hex::encode(dh.hash().unwrap());
+    let rs_hashtable_str = dh.hashtable().unwrap().to_string();
+
+    // sh implementation
+    // ------------------
+    let sh_list_paths_str = list_files_with_bash(dir.path(), false, false, false);
+
+    let (sh_hashtable_str, sh_hash_str) =
+        compute_recursive_hash_with_bash(dir.path(), false, false, false);
+
+    // Verification
+    // ------------
+    assert_eq!(sh_list_paths_str, rs_list_paths_str);
+    assert_eq!(sh_hash_str, rs_hash_str);
+    assert_eq!(sh_hashtable_str, rs_hashtable_str);
+
+    assert_eq!(rs_hashtable_str.lines().count(), names.len() + 1);
+    assert_eq!(
+        rs_hashtable_str
+            .lines()
+            .filter(|line| line.starts_with('\\'))
+            .count(),
+        escaped_names.len() + 1
+    );
+
+    // Other lines starting with an escaped path (e.g. printed by `dirhash list` and `dirhash tree`)
+    // are marked the same way
+    let marked_paths = rs_list_paths_str
+        .lines()
+        .filter(|path| line_marker(path) == "\\")
+        .count();
+    assert_eq!(marked_paths, escaped_names.len() + 1);
+
+    let tree = MerkleTree::new(dh.hashtable().unwrap(), HashAlgorithm::Sha256)
+        .expect("Can't build Merkle tree");
+    let tree_lines: Vec<_> = tree
+        .subtree_hashes()
+        .into_iter()
+        .map(|(path, hash)| format!("{}{}  {path}", line_marker(&path), hex::encode(hash)))
+        .collect();
+    assert_eq!(tree_lines.len(), 2);
+    assert!(!tree_lines[0].starts_with('\\'));
+    assert!(tree_lines[1].starts_with('\\') && tree_lines[1].ends_with("  ./d\\nx/"));
+    assert_eq!(
+        dh.hashtable().unwrap(),
+        &rs_hashtable_str.parse().expect("Can't parse hashtable")
+    );
+
+    dir.close().expect("Can't close tempdir");
+}
+
 #[test]
 fn comparing_rs_sh_with_random_data() {
     common::init_tracing();
@@ -771,7 +942,7 @@ fn comparing_rs_sh_with_random_data() {
         // ------------------
 
         let mut dh = DirHash::new()
-            .with_files_from_dir(dir.path(), true, false, true, false)
+            .with_files_from_dir(dir.path(), &WalkConfig::new())
             .expect("Can't create DirHash");
 
         let rs_list_paths = dh.list_paths().expect("Can't list files with dirhash");
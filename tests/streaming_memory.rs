@@ -0,0 +1,87 @@
+//! Checks that hashing a file doesn't load it into memory as a whole.
+//!
+//! This needs its own test binary, as the peak memory is tracked by a global allocator. Only one
+//! test is defined here, so that no other tests allocate in parallel while measuring.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    fs::File,
+    io::Write,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use dirhash_rs::pathhash::{PathHash, PathHashProvider, ReadOptions};
+use tempfile::tempdir;
+
+struct PeakTrackingAllocator;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for PeakTrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(current, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOC: PeakTrackingAllocator = PeakTrackingAllocator;
+
+// Returns the additional peak memory used while hashing the file.
+fn peak_memory_while_hashing(pathhash: &mut PathHash) -> usize {
+    let baseline = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(baseline, Ordering::SeqCst);
+
+    pathhash.compute_hash().expect("Can't compute hash");
+
+    PEAK.load(Ordering::SeqCst) - baseline
+}
+
+#[test]
+fn peak_memory_stays_flat() {
+    let dir = tempdir().expect("Can't create tempdir");
+    let buffer_size = ReadOptions::DEFAULT_BUFFER_SIZE;
+
+    let mut peaks = vec![];
+
+    // 16 MiB and 64 MiB, both way larger than the buffer
+    for size_mib in [16, 64] {
+        let path = dir.path().join(format!("{size_mib}"));
+        let mut file = File::create(&path).expect("Error while creating file");
+        let chunk = vec![0xA5u8; 1024 * 1024];
+        for _ in 0..size_mib {
+            file.write_all(&chunk).expect("Can't write to tempfile");
+        }
+        drop(file);
+
+        let mut pathhash = PathHash::new(&path)
+            .expect("Can't create PathHash from existing file")
+            .with_read_options(ReadOptions {
+                buffer_size,
+                mmap_threshold: None,
+            });
+
+        let peak = peak_memory_while_hashing(&mut pathhash);
+        assert!(pathhash.hash().is_some());
+        assert!(
+            peak < 2 * buffer_size,
+            "Peak memory of {peak} bytes for a {size_mib} MiB file"
+        );
+        peaks.push(peak);
+    }
+
+    // Independent of the file size
+    assert_eq!(peaks[0], peaks[1]);
+
+    dir.close().expect("Can't close tempdir");
+}